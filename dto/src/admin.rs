@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::{
+    staff::StaffRole,
+    user::{User, UserType},
+};
+
+/// Filters for the user listing. `search` matches part of the name or email address.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSearch {
+    #[validate(length(max = 100))]
+    pub search: Option<String>,
+    pub user_type: Option<UserType>,
+    pub disabled: Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ManagedUser {
+    #[serde(flatten)]
+    pub user: User,
+    pub disabled_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct UserTypeUpdate {
+    pub user_type: UserType,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ApothecaryLink {
+    pub apothecary_id: Uuid,
+    pub role: StaffRole,
+}
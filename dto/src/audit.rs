@@ -0,0 +1,31 @@
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AuditEventKind {
+    AccountLocked,
+    IpLocked,
+    TotpEnabled,
+    TotpDisabled,
+    RecoveryCodeUsed,
+    UserTypeChanged,
+    AccountDisabled,
+    AccountEnabled,
+    PasswordResetForced,
+    ApothecaryLinked,
+    ApothecaryUnlinked,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub kind: AuditEventKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actor_id: Option<Uuid>,
+    pub ip: Option<String>,
+    pub detail: String,
+    pub created_at: PrimitiveDateTime,
+}
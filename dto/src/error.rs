@@ -1,21 +1,30 @@
 use serde::{Deserialize, Serialize};
 
+/// Problem details as in RFC 7807, served as `application/problem+json`.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct RestError {
-    pub message: String,
-}
-
-impl From<String> for RestError {
-    fn from(message: String) -> Self {
-        Self { message }
-    }
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable identifier of the error, e.g. `invalidCredentials`. Unlike `detail`, clients can
+    /// rely on it.
+    pub code: String,
+    /// Identifies the request in the server logs, it is also sent as `X-Request-Id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Problems with individual request fields, if the request was rejected as invalid.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<FieldError>,
 }
 
-impl From<&str> for RestError {
-    fn from(message: &str) -> Self {
-        Self {
-            message: message.to_owned(),
-        }
-    }
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    pub field: String,
+    /// Stable identifier of the problem, e.g. `tooShort`.
+    pub code: String,
+    pub message: String,
 }
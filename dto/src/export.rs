@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+use crate::{audit::AuditEvent, oidc::Identity, reservation::MedicationReservation, user::User};
+
+/// Everything stored about a user, for data portability requests.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserExport {
+    pub exported_at: PrimitiveDateTime,
+    pub profile: User,
+    pub reservations: Vec<MedicationReservation>,
+    pub no_shows: Vec<NoShowExport>,
+    pub sessions: Vec<SessionExport>,
+    pub identities: Vec<Identity>,
+    pub audit_events: Vec<AuditEvent>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NoShowExport {
+    pub reservation_id: Uuid,
+    pub apothecary_id: Uuid,
+    pub missed_at: PrimitiveDateTime,
+}
+
+/// A refresh token issued to the user. Tokens with the same `session_id` belong to one login.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionExport {
+    pub session_id: Uuid,
+    pub issued_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+    pub revoked_at: Option<PrimitiveDateTime>,
+}
@@ -1,8 +1,16 @@
+pub mod admin;
+pub mod api_key;
 pub mod apothecary;
+pub mod audit;
 pub mod error;
+pub mod export;
 pub mod heartbeat;
 pub mod medication;
+pub mod notification;
+pub mod oidc;
 pub mod page;
 pub mod reservation;
 pub mod schedule;
+pub mod staff;
+pub mod totp;
 pub mod user;
@@ -1,6 +1,7 @@
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use validator::{Validate, ValidationError, ValidationErrors};
 
 use crate::apothecary::ApothecaryDetail;
 
@@ -20,6 +21,78 @@ pub enum MedicationQuantity {
     Unknown(MedicationQuantityUnknown),
 }
 
+impl Validate for MedicationQuantity {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+
+        match self {
+            MedicationQuantity::Liquid(liquid) => {
+                if !liquid.quantity.is_finite() || liquid.quantity < 0.0 {
+                    errors.add("quantity", negative());
+                }
+                if liquid.unit.trim().is_empty() {
+                    errors.add("unit", ValidationError::new("length"));
+                }
+            }
+            MedicationQuantity::Package(package) => {
+                if package.price.is_sign_negative() {
+                    errors.add("price", negative());
+                }
+            }
+            MedicationQuantity::Unknown(_) => {}
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn negative() -> ValidationError {
+    let mut error = ValidationError::new("range");
+    error.add_param("min".into(), &0);
+
+    error
+}
+
+fn too_large<T: Serialize>(max: &T) -> ValidationError {
+    let mut error = ValidationError::new("range");
+    error.add_param("max".into(), max);
+
+    error
+}
+
+/// Most packages a single reservation may ask for.
+pub const MAX_RESERVED_PACKAGES: u64 = 1_000;
+/// Largest amount of a liquid a single reservation may ask for.
+pub const MAX_RESERVED_LIQUID: f64 = 100_000.0;
+
+/// Reservations have to ask for at least one package or some amount of a liquid, and no more
+/// than any apothecary could hand out.
+pub fn validate_reserved_quantity(quantity: &MedicationQuantity) -> Result<(), ValidationError> {
+    let reserves_something = match quantity {
+        MedicationQuantity::Liquid(liquid) => liquid.quantity > 0.0,
+        MedicationQuantity::Package(package) => package.quantity > 0,
+        MedicationQuantity::Unknown(_) => false,
+    };
+
+    if !reserves_something {
+        return Err(ValidationError::new("positiveQuantity"));
+    }
+
+    match quantity {
+        MedicationQuantity::Liquid(liquid) if liquid.quantity > MAX_RESERVED_LIQUID => {
+            Err(too_large(&MAX_RESERVED_LIQUID))
+        }
+        MedicationQuantity::Package(package) if package.quantity > MAX_RESERVED_PACKAGES => {
+            Err(too_large(&MAX_RESERVED_PACKAGES))
+        }
+        _ => Ok(()),
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MedicationDetailWithQuantity {
@@ -29,6 +102,22 @@ pub struct MedicationDetailWithQuantity {
     pub quantity: MedicationQuantity,
 }
 
+/// The stock of one apothecary.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApothecaryInventory {
+    pub apothecary_id: Uuid,
+    pub apothecary_name: String,
+    pub medications: Vec<MedicationDetailWithQuantity>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryQuery {
+    /// Limits the result to one of the user's apothecaries.
+    pub apothecary_id: Option<Uuid>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct MedicationQuantityLiquid {
@@ -47,20 +136,27 @@ pub struct MedicationQuantityPackage {
 #[serde(rename_all = "camelCase")]
 pub struct MedicationQuantityUnknown;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct MedicationSearch {
+    #[validate(length(min = 1, max = 100))]
     pub name: String,
+    #[validate(range(min = -90.0, max = 90.0))]
     pub latitude: f32,
+    #[validate(range(min = -180.0, max = 180.0))]
     pub longitude: f32,
+    #[validate(range(min = 1, max = 1000))]
     pub max_distance: u64,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct MedicationSearchCda {
+    #[validate(range(min = -90.0, max = 90.0))]
     pub latitude: f32,
+    #[validate(range(min = -180.0, max = 180.0))]
     pub longitude: f32,
+    #[validate(range(min = 1, max = 1000))]
     pub max_distance: u64,
 }
 
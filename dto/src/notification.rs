@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NotificationKind {
+    ReservationCancelled,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    pub id: Uuid,
+    pub apothecary_id: Uuid,
+    pub reservation_id: Option<Uuid>,
+    pub kind: NotificationKind,
+    pub message: String,
+    pub created_at: PrimitiveDateTime,
+}
@@ -1,6 +1,7 @@
 use std::{fmt::Display, str::FromStr};
 
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -14,45 +15,66 @@ pub struct Page<T> {
     pub first: bool,
     pub number_of_elements: u64,
     pub empty: bool,
+    /// Cursor of the following page, if there is one. See [`Pageable::cursor`].
+    pub next: Option<String>,
+    /// Cursor of the preceding page, if there is one.
+    pub prev: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+/// Selects a page of a list from query parameters such as `?page=1&size=50&sort=name,asc`.
+/// Pages are numbered from 0 and hold at most [`MAX_PAGE_SIZE`] elements. `sort` can be
+/// repeated, criteria apply in the given order and sort ascending unless `desc` is given.
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct Pageable {
-    #[serde(flatten)]
-    pub options: PageableOptions,
+    #[serde(default)]
+    #[validate(range(max = 1_000_000))]
+    pub page: u64,
 
-    pub sort: Option<Sort>,
+    #[serde(default = "default_size")]
+    #[validate(range(min = 1, max = MAX_PAGE_SIZE))]
+    pub size: u64,
 
-    #[serde(default = "default_per_page")]
-    pub per_page: u64,
-}
+    #[serde(default)]
+    pub sort: Vec<SortCriterion>,
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-#[serde(untagged)]
-pub enum PageableOptions {
-    OffsetAndLimit((u64, u64)),
-    Page(PageQuery),
+    /// Continues from the `next` or `prev` cursor of a page, which takes the place of `page`.
+    /// Unlike page numbers, cursors stay fast on long lists and do not skip or repeat elements
+    /// when the list changes. The `sort` has to be the same as for the page it came from, and
+    /// no cursors are given when sorting by a column that can be empty.
+    #[validate(length(max = 1024))]
+    pub cursor: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct PageQuery {
-    #[serde(default = "default_per_page")]
-    pub per_page: u64,
-    pub index: u64,
+impl Pageable {
+    /// Sorts by `field` if no sort was requested.
+    pub fn sorted_by_default(mut self, field: &str, direction: SortDirection) -> Self {
+        if self.sort.is_empty() {
+            self.sort.push(SortCriterion {
+                field: field.to_owned(),
+                direction,
+            });
+        }
+
+        self
+    }
 }
 
-const fn default_per_page() -> u64 {
-    20
+impl Default for Pageable {
+    fn default() -> Self {
+        Self {
+            page: 0,
+            size: default_size(),
+            sort: Vec::new(),
+            cursor: None,
+        }
+    }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct Sort {
-    #[serde(flatten)]
-    pub criteria: Vec<SortCriterion>,
+pub const MAX_PAGE_SIZE: u64 = 100;
+
+const fn default_size() -> u64 {
+    20
 }
 
 #[derive(Clone, Debug)]
@@ -70,9 +92,10 @@ impl<'de> Deserialize<'de> for SortCriterion {
         let field = split.next().unwrap_or_default().to_string();
         let direction = split
             .next()
-            .unwrap_or_default()
-            .parse::<SortDirection>()
-            .map_err(serde::de::Error::custom)?;
+            .map(str::parse::<SortDirection>)
+            .transpose()
+            .map_err(serde::de::Error::custom)?
+            .unwrap_or(SortDirection::Asc);
 
         Ok(Self { field, direction })
     }
@@ -111,3 +134,71 @@ impl FromStr for SortDirection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn parse(sort: &str) -> Result<SortCriterion, serde_json::Error> {
+        serde_json::from_value(json!(sort))
+    }
+
+    #[test]
+    fn sort_criteria_name_a_field_and_a_direction() {
+        let criterion = parse("name,desc").unwrap();
+        assert_eq!(criterion.field, "name");
+        assert!(matches!(criterion.direction, SortDirection::Desc));
+
+        let criterion = parse("name,asc").unwrap();
+        assert!(matches!(criterion.direction, SortDirection::Asc));
+    }
+
+    #[test]
+    fn sort_criteria_default_to_ascending() {
+        let criterion = parse("createdAt").unwrap();
+        assert_eq!(criterion.field, "createdAt");
+        assert!(matches!(criterion.direction, SortDirection::Asc));
+    }
+
+    #[test]
+    fn unknown_sort_directions_are_rejected() {
+        assert!(parse("name,up").is_err());
+        assert!(parse("name,DESC").is_err());
+    }
+
+    #[test]
+    fn sort_criteria_serialize_as_they_are_parsed() {
+        let criterion = parse("name,desc").unwrap();
+        assert_eq!(
+            serde_json::to_value(&criterion).unwrap(),
+            json!("name,desc")
+        );
+
+        let criterion = parse("name").unwrap();
+        assert_eq!(serde_json::to_value(&criterion).unwrap(), json!("name,asc"));
+    }
+
+    #[test]
+    fn pageables_default_to_the_first_page() {
+        let pageable: Pageable = serde_json::from_value(json!({})).unwrap();
+
+        assert_eq!(pageable.page, 0);
+        assert_eq!(pageable.size, 20);
+        assert!(pageable.sort.is_empty());
+        assert!(pageable.cursor.is_none());
+    }
+
+    #[test]
+    fn default_sorts_do_not_replace_requested_ones() {
+        let pageable = Pageable::default().sorted_by_default("id", SortDirection::Asc);
+        assert_eq!(pageable.sort.len(), 1);
+        assert_eq!(pageable.sort[0].field, "id");
+
+        let pageable: Pageable = serde_json::from_value(json!({ "sort": ["name,desc"] })).unwrap();
+        let pageable = pageable.sorted_by_default("id", SortDirection::Asc);
+        assert_eq!(pageable.sort.len(), 1);
+        assert_eq!(pageable.sort[0].field, "name");
+    }
+}
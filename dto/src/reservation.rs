@@ -1,10 +1,11 @@
 use serde::{Deserialize, Serialize};
-use time::PrimitiveDateTime;
+use time::{Date, PrimitiveDateTime};
 use uuid::Uuid;
+use validator::Validate;
 
 use crate::{
     apothecary::ApothecaryDetail,
-    medication::{MedicationDetail, MedicationQuantity},
+    medication::{validate_reserved_quantity, MedicationDetail, MedicationQuantity},
 };
 
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
@@ -15,19 +16,54 @@ pub enum MedicationReservationStatus {
     Done,
     Rejected,
     Expired,
+    Cancelled,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CancellationReason {
+    NoLongerNeeded,
+    FoundElsewhere,
+    PickupNotPossible,
+    Other,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct MedicationReservationCancellationRequest {
+    pub reason: Option<CancellationReason>,
+    #[validate(length(max = 1000))]
+    pub note: Option<String>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
+pub struct MedicationReservationCancellation {
+    pub reason: Option<CancellationReason>,
+    pub note: Option<String>,
+    pub cancelled_at: PrimitiveDateTime,
+}
+
+/// Filters for the reservation history. `from` and `to` are inclusive creation dates.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ReservationFilter {
+    pub status: Option<MedicationReservationStatus>,
+    pub from: Option<Date>,
+    pub to: Option<Date>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
 pub struct MedicationReservationRequest {
     pub apothecary_id: Uuid,
     pub medication_id: Uuid,
+    #[validate(custom(function = "validate_reserved_quantity"))]
     pub quantity: MedicationQuantity,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
-
 pub struct MedicationReservation {
     pub id: Uuid,
     pub apothecary: ApothecaryDetail,
@@ -36,4 +72,30 @@ pub struct MedicationReservation {
     pub start_date_time: Option<PrimitiveDateTime>,
     pub end_date_time: Option<PrimitiveDateTime>,
     pub status: MedicationReservationStatus,
+    pub cancellation: Option<MedicationReservationCancellation>,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomerReliability {
+    pub completed: u64,
+    pub no_shows: u64,
+    pub score: u8,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Customer {
+    pub id: Uuid,
+    pub name: String,
+    pub reliability: CustomerReliability,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApothecaryReservation {
+    #[serde(flatten)]
+    pub reservation: MedicationReservation,
+    pub customer: Customer,
 }
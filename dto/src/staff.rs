@@ -0,0 +1,62 @@
+use serde::{Deserialize, Serialize};
+use time::PrimitiveDateTime;
+use uuid::Uuid;
+use validator::Validate;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum StaffRole {
+    Owner,
+    Pharmacist,
+    Assistant,
+}
+
+/// An apothecary the user works at.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Membership {
+    pub apothecary_id: Uuid,
+    pub apothecary_name: String,
+    pub role: StaffRole,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StaffMember {
+    pub user_id: Uuid,
+    pub name: String,
+    pub email: String,
+    pub role: StaffRole,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct StaffRoleUpdate {
+    pub role: StaffRole,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+    pub role: StaffRole,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Invitation {
+    pub id: Uuid,
+    pub apothecary_id: Uuid,
+    pub email: String,
+    pub role: StaffRole,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct AcceptInvitationRequest {
+    #[validate(length(min = 1))]
+    pub token: String,
+}
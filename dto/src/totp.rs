@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::user::AuthTokens;
+
+/// Secret to add to an authenticator app, either by hand or as a QR code of the URI.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpCodeRequest {
+    #[validate(length(min = 1))]
+    pub code: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct DisableTotpRequest {
+    #[validate(length(min = 1))]
+    pub password: String,
+    #[validate(length(min = 1))]
+    pub code: String,
+}
+
+/// Shown only once, each code can be used a single time instead of a TOTP code.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Returned by the login when the password was accepted but a code is still needed. If the
+/// account has to use two-factor authentication but has not enrolled yet, `enrollment` holds
+/// the secret to set up first.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondFactorChallenge {
+    pub challenge: String,
+    /// Seconds until the challenge expires.
+    pub expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enrollment: Option<TotpEnrollment>,
+}
+
+/// Completes a login with either a TOTP code or a recovery code.
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondFactorLogin {
+    #[validate(length(min = 1))]
+    pub challenge: String,
+    #[validate(length(min = 1))]
+    pub code: Option<String>,
+    #[validate(length(min = 1))]
+    pub recovery_code: Option<String>,
+}
+
+/// The session of a completed two-step login. Recovery codes are included when the login
+/// finished an enrollment.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SecondFactorAuthTokens {
+    #[serde(flatten)]
+    pub tokens: AuthTokens,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub recovery_codes: Vec<String>,
+}
@@ -1,17 +1,24 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+use validator::Validate;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+use crate::totp::SecondFactorChallenge;
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct UserLogin {
+    #[validate(length(min = 1))]
     pub email: String,
+    #[validate(length(min = 1))]
     pub password: String,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct UserRegistration {
+    #[validate(length(min = 1, max = 100))]
     pub name: String,
+    #[validate(email, length(max = 254))]
     pub email: String,
     pub password: String,
 }
@@ -30,5 +37,89 @@ pub struct User {
     pub id: Uuid,
     pub name: String,
     pub email: String,
+    pub email_verified: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pending_email: Option<String>,
     pub user_type: UserType,
 }
+
+/// Fields left out stay unchanged. A new email address only takes effect once it is verified.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct UserUpdate {
+    #[validate(length(min = 1, max = 100))]
+    pub name: Option<String>,
+    #[validate(email, length(max = 254))]
+    pub email: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePasswordRequest {
+    #[validate(length(min = 1))]
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteAccountRequest {
+    /// Can be left out shortly after logging in, e.g. for accounts without a password that log
+    /// in through a provider.
+    #[validate(length(min = 1))]
+    pub password: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailVerificationRequest {
+    #[validate(length(min = 1))]
+    pub token: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ResendVerificationRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ForgotPasswordRequest {
+    #[validate(email, length(max = 254))]
+    pub email: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPasswordRequest {
+    #[validate(length(min = 1))]
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthTokens {
+    pub token_type: String,
+    pub access_token: String,
+    /// Lifetime of the access token in seconds.
+    pub expires_in: i64,
+    pub refresh_token: String,
+}
+
+/// Either a session, or the challenge of a two-step login.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(AuthTokens),
+    SecondFactorRequired(SecondFactorChallenge),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshTokenRequest {
+    #[validate(length(min = 1))]
+    pub refresh_token: String,
+}
@@ -0,0 +1,249 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    response::{ErrorResponse, IntoResponse},
+    Json,
+};
+use dto::{
+    admin::{ApothecaryLink, ManagedUser, UserSearch, UserTypeUpdate},
+    audit::AuditEvent,
+    page::{Page, Pageable},
+    staff::Membership,
+};
+use service::{admin::AdminServiceError, page::PageError};
+use uuid::Uuid;
+
+use crate::{
+    appstate::AppState,
+    auth::UserAuth,
+    error::{problem, ApiError},
+    extract::{ValidatedJson, ValidatedQuery},
+};
+
+impl ApiError for AdminServiceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AdminServiceError::UserNotFound
+            | AdminServiceError::ApothecaryNotFound
+            | AdminServiceError::MembershipNotFound => StatusCode::NOT_FOUND,
+            AdminServiceError::AlreadyMember | AdminServiceError::LastOwner => StatusCode::CONFLICT,
+            AdminServiceError::OwnAccount => StatusCode::FORBIDDEN,
+            AdminServiceError::Page(PageError::DbErr(_)) | AdminServiceError::Anyhow(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            AdminServiceError::Page(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            AdminServiceError::UserNotFound => "userNotFound",
+            AdminServiceError::ApothecaryNotFound => "apothecaryNotFound",
+            AdminServiceError::MembershipNotFound => "membershipNotFound",
+            AdminServiceError::AlreadyMember => "alreadyMember",
+            AdminServiceError::LastOwner => "lastOwner",
+            AdminServiceError::OwnAccount => "ownAccount",
+            AdminServiceError::Page(PageError::DbErr(_)) | AdminServiceError::Anyhow(_) => {
+                "internalError"
+            }
+            AdminServiceError::Page(PageError::InvalidCursor) => "invalidCursor",
+            AdminServiceError::Page(PageError::InvalidColumnName(_)) => "invalidSort",
+        }
+    }
+}
+
+pub async fn get_users(
+    State(ref state): State<AppState>,
+    ValidatedQuery(search): ValidatedQuery<UserSearch>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<ManagedUser>>, ErrorResponse> {
+    Ok(Json(
+        state
+            .admin_service
+            .get_users(search, Some(pageable))
+            .await
+            .map_err(problem)?
+            .into(),
+    ))
+}
+
+pub async fn get_user(
+    State(ref state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ManagedUser>, ErrorResponse> {
+    let user = state
+        .admin_service
+        .get_user(user_id)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(user.into()))
+}
+
+pub async fn update_user_type(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(user_id): Path<Uuid>,
+    ValidatedJson(update): ValidatedJson<UserTypeUpdate>,
+) -> Result<Json<ManagedUser>, ErrorResponse> {
+    let user = state
+        .admin_service
+        .set_user_type(
+            auth.user_id,
+            user_id,
+            update.user_type.into(),
+            Some(remote_addr.ip().to_string()),
+        )
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(user.into()))
+}
+
+/// Disables the account and ends all of its sessions.
+pub async fn disable_user(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ManagedUser>, ErrorResponse> {
+    let user = state
+        .admin_service
+        .set_disabled(
+            auth.user_id,
+            user_id,
+            true,
+            Some(remote_addr.ip().to_string()),
+        )
+        .await
+        .map_err(problem)?;
+
+    state
+        .jwt_service
+        .revoke_all(user.id)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(user.into()))
+}
+
+pub async fn enable_user(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<ManagedUser>, ErrorResponse> {
+    let user = state
+        .admin_service
+        .set_disabled(
+            auth.user_id,
+            user_id,
+            false,
+            Some(remote_addr.ip().to_string()),
+        )
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(user.into()))
+}
+
+/// Invalidates the password and sessions of the user, who gets a reset link by mail.
+pub async fn force_password_reset(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let user = state
+        .admin_service
+        .force_password_reset(auth.user_id, user_id, Some(remote_addr.ip().to_string()))
+        .await
+        .map_err(problem)?;
+
+    state
+        .jwt_service
+        .revoke_all(user.id)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
+
+pub async fn get_memberships(
+    State(ref state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<Membership>>, ErrorResponse> {
+    state
+        .admin_service
+        .get_user(user_id)
+        .await
+        .map_err(problem)?;
+
+    let memberships = state
+        .staff_service
+        .get_memberships(user_id, Some(pageable))
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(memberships.into()))
+}
+
+pub async fn link_apothecary(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path(user_id): Path<Uuid>,
+    ValidatedJson(link): ValidatedJson<ApothecaryLink>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let membership = state
+        .admin_service
+        .link_apothecary(
+            auth.user_id,
+            user_id,
+            link,
+            Some(remote_addr.ip().to_string()),
+        )
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::CREATED, Json(membership)))
+}
+
+pub async fn unlink_apothecary(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    Path((user_id, apothecary_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .admin_service
+        .unlink_apothecary(
+            auth.user_id,
+            user_id,
+            apothecary_id,
+            Some(remote_addr.ip().to_string()),
+        )
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
+
+pub async fn get_audit_events(
+    State(ref state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<AuditEvent>>, ErrorResponse> {
+    Ok(Json(
+        state
+            .admin_service
+            .get_audit_events(user_id, Some(pageable))
+            .await
+            .map_err(problem)?
+            .into(),
+    ))
+}
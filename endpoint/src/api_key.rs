@@ -0,0 +1,88 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{ErrorResponse, IntoResponse},
+    Json,
+};
+use dto::api_key::{ApiKey, ApiKeyRequest, CreatedApiKey};
+use service::api_key::ApiKeyServiceError;
+use uuid::Uuid;
+
+use crate::{
+    appstate::AppState,
+    auth::UserAuth,
+    error::{problem, ApiError},
+    extract::ValidatedJson,
+};
+
+impl ApiError for ApiKeyServiceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiKeyServiceError::Forbidden => StatusCode::FORBIDDEN,
+            ApiKeyServiceError::KeyNotFound => StatusCode::NOT_FOUND,
+            ApiKeyServiceError::InvalidKey => StatusCode::UNAUTHORIZED,
+            ApiKeyServiceError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiKeyServiceError::Forbidden => "forbidden",
+            ApiKeyServiceError::KeyNotFound => "apiKeyNotFound",
+            ApiKeyServiceError::InvalidKey => "invalidApiKey",
+            ApiKeyServiceError::Anyhow(_) => "internalError",
+        }
+    }
+}
+
+pub async fn get(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path(apothecary_id): Path<Uuid>,
+) -> Result<Json<Vec<ApiKey>>, ErrorResponse> {
+    Ok(Json(
+        state
+            .api_key_service
+            .get_keys(auth.user_id, apothecary_id)
+            .await
+            .map_err(problem)?
+            .into_iter()
+            .map(ApiKey::from)
+            .collect(),
+    ))
+}
+
+pub async fn post(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path(apothecary_id): Path<Uuid>,
+    ValidatedJson(request): ValidatedJson<ApiKeyRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let (api_key, key) = state
+        .api_key_service
+        .create(auth.user_id, apothecary_id, request)
+        .await
+        .map_err(problem)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(CreatedApiKey {
+            api_key: api_key.into(),
+            key,
+        }),
+    ))
+}
+
+pub async fn delete(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path((apothecary_id, key_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .api_key_service
+        .revoke(auth.user_id, apothecary_id, key_id)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
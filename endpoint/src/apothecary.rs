@@ -1,45 +1,66 @@
 use axum::{
-    extract::{Query, State},
+    extract::{Path, State},
     http::StatusCode,
     response::{ErrorResponse, IntoResponse, Response},
     Json,
 };
 use dto::{
     apothecary::ApothecaryDetail,
-    error::RestError,
     medication::{
-        MedicationDetailWithQuantity, MedicationSearch, MedicationSearchCda,
-        MedicationSearchResultList,
+        ApothecaryInventory, InventoryQuery, MedicationDetailWithQuantity, MedicationQuantity,
+        MedicationSearch, MedicationSearchCda, MedicationSearchResultList,
     },
-    page::Page,
+    page::{Page, Pageable},
 };
 use entity::apothecary::ApothecaryWithSchedules;
-use service::apothecary::ApothecaryServiceError;
-
-use crate::{appstate::AppState, auth::Auth};
-
-fn handle_apothecary_service_error(error: ApothecaryServiceError) -> Response {
-    let (status_code, message) = match error {
-        ApothecaryServiceError::NotFound => (StatusCode::NOT_FOUND, error.to_string()),
-        ApothecaryServiceError::InvalidSortColumn(e) => (StatusCode::BAD_REQUEST, e),
-        ApothecaryServiceError::InvalidXml => (StatusCode::BAD_REQUEST, error.to_string()),
-        ApothecaryServiceError::Anyhow(e) => {
-            tracing::error!("Error: {}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR, String::new())
+use service::{
+    api_key::ApiKeyScope, apothecary::ApothecaryServiceError, jwt::Role, staff::StaffServiceError,
+};
+use uuid::Uuid;
+
+use crate::{
+    appstate::AppState,
+    auth::{Auth, UserAuth},
+    error::{problem, ApiError},
+    extract::{ValidatedJson, ValidatedQuery},
+};
+
+impl ApiError for ApothecaryServiceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ApothecaryServiceError::NotFound | ApothecaryServiceError::MedicationNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            ApothecaryServiceError::UnsupportedQuantity => StatusCode::UNPROCESSABLE_ENTITY,
+            ApothecaryServiceError::InvalidSortColumn(_)
+            | ApothecaryServiceError::InvalidCursor
+            | ApothecaryServiceError::InvalidXml => StatusCode::BAD_REQUEST,
+            ApothecaryServiceError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
-    };
+    }
 
-    (status_code, Json(RestError { message })).into_response()
+    fn code(&self) -> &'static str {
+        match self {
+            ApothecaryServiceError::NotFound => "apothecaryNotFound",
+            ApothecaryServiceError::MedicationNotFound => "medicationNotFound",
+            ApothecaryServiceError::UnsupportedQuantity => "unsupportedQuantity",
+            ApothecaryServiceError::InvalidSortColumn(_) => "invalidSort",
+            ApothecaryServiceError::InvalidCursor => "invalidCursor",
+            ApothecaryServiceError::InvalidXml => "invalidXml",
+            ApothecaryServiceError::Anyhow(_) => "internalError",
+        }
+    }
 }
 
 pub async fn get(
     State(ref state): State<AppState>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
 ) -> Result<Json<Page<ApothecaryDetail>>, ErrorResponse> {
     let result = state
         .apothecary_service
-        .get(None)
+        .get(Some(pageable))
         .await
-        .map_err(handle_apothecary_service_error)?
+        .map_err(problem)?
         .map(|p| ApothecaryDetail::from(ApothecaryWithSchedules::from(p)))
         .into();
 
@@ -48,40 +69,259 @@ pub async fn get(
 
 pub async fn get_medications(
     State(ref state): State<AppState>,
-    Query(search_dto): Query<MedicationSearch>,
-) -> Result<Json<Vec<MedicationSearchResultList>>, ErrorResponse> {
+    ValidatedQuery(search_dto): ValidatedQuery<MedicationSearch>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<MedicationSearchResultList>>, ErrorResponse> {
     let result = state
         .apothecary_service
-        .get_medications(search_dto)
+        .get_medications(search_dto, Some(pageable))
         .await
-        .map_err(handle_apothecary_service_error)?;
+        .map_err(problem)?
+        .into();
 
     Ok(Json(result))
 }
 
 pub async fn get_own_medications(
     State(ref state): State<AppState>,
-    auth: Auth,
-) -> Result<Json<Vec<MedicationDetailWithQuantity>>, ErrorResponse> {
+    auth: UserAuth,
+    ValidatedQuery(query): ValidatedQuery<InventoryQuery>,
+) -> Result<Json<Vec<ApothecaryInventory>>, ErrorResponse> {
     let result = state
         .apothecary_service
-        .get_own_medications(auth.user_id)
+        .get_own_medications(auth.user_id, query.apothecary_id)
         .await
-        .map_err(handle_apothecary_service_error)?;
+        .map_err(problem)?;
 
     Ok(Json(result))
 }
 
 pub async fn get_medications_by_cda(
     State(ref state): State<AppState>,
-    Query(search_dto): Query<MedicationSearchCda>,
+    ValidatedQuery(search_dto): ValidatedQuery<MedicationSearchCda>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
     cda: String,
-) -> Result<Json<Vec<MedicationSearchResultList>>, ErrorResponse> {
+) -> Result<Json<Page<MedicationSearchResultList>>, ErrorResponse> {
+    let result = state
+        .apothecary_service
+        .get_medications_by_cda(cda, search_dto, Some(pageable))
+        .await
+        .map_err(problem)?
+        .into();
+
+    Ok(Json(result))
+}
+
+/// Lets staff of the apothecary through, and API keys of it with the required scope.
+async fn authorize_inventory(
+    state: &AppState,
+    auth: &Auth,
+    apothecary_id: Uuid,
+    scope: ApiKeyScope,
+) -> Result<(), Response> {
+    let allowed = match auth {
+        Auth::ApiKey(api_key) => {
+            api_key.apothecary_id == apothecary_id && api_key.scope.allows(scope)
+        }
+        Auth::User(user) => {
+            user.has_role(Role::Apothecary)
+                && state
+                    .staff_service
+                    .get_memberships(user.user_id, None)
+                    .await
+                    .map_err(problem)?
+                    .content
+                    .iter()
+                    .any(|membership| membership.apothecary_id == apothecary_id)
+        }
+    };
+
+    if !allowed {
+        return Err(problem(StaffServiceError::Forbidden));
+    }
+
+    Ok(())
+}
+
+pub async fn get_inventory(
+    State(ref state): State<AppState>,
+    auth: Auth,
+    Path(apothecary_id): Path<Uuid>,
+) -> Result<Json<ApothecaryInventory>, ErrorResponse> {
+    authorize_inventory(state, &auth, apothecary_id, ApiKeyScope::InventoryRead).await?;
+
+    let result = state
+        .apothecary_service
+        .get_inventory(apothecary_id)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(result))
+}
+
+/// Sets the stock to the packages on hand. The response shows what is left for reservations,
+/// without the packages active reservations hold.
+pub async fn put_stock(
+    State(ref state): State<AppState>,
+    auth: Auth,
+    Path((apothecary_id, medication_id)): Path<(Uuid, Uuid)>,
+    ValidatedJson(quantity): ValidatedJson<MedicationQuantity>,
+) -> Result<Json<MedicationDetailWithQuantity>, ErrorResponse> {
+    authorize_inventory(state, &auth, apothecary_id, ApiKeyScope::InventoryWrite).await?;
+
     let result = state
         .apothecary_service
-        .get_medications_by_cda(cda, search_dto)
+        .set_stock(apothecary_id, medication_id, quantity)
         .await
-        .map_err(handle_apothecary_service_error)?;
+        .map_err(problem)?;
 
     Ok(Json(result))
 }
+
+pub async fn delete_stock(
+    State(ref state): State<AppState>,
+    auth: Auth,
+    Path((apothecary_id, medication_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    authorize_inventory(state, &auth, apothecary_id, ApiKeyScope::InventoryWrite).await?;
+
+    state
+        .apothecary_service
+        .remove_stock(apothecary_id, medication_id)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+
+    use crate::test_support::{router, send, state};
+
+    #[tokio::test]
+    async fn next_cursors_continue_the_list() {
+        let state = state().await;
+        let router = router(&state);
+
+        let (status, first) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries?size=1&sort=name",
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{first}");
+        assert_eq!(first["content"][0]["name"], "St. Rudolf");
+
+        let next = first["next"].as_str().unwrap();
+        let (status, second) = send(
+            &router,
+            Method::GET,
+            &format!("/api/v1/apothecaries?size=1&sort=name&cursor={next}"),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{second}");
+        assert_eq!(second["content"][0]["name"], "Zur goldenen Krone");
+        assert_eq!(second["next"], serde_json::Value::Null);
+        assert!(second["prev"].is_string());
+    }
+
+    #[tokio::test]
+    async fn invalid_cursors_are_rejected() {
+        let state = state().await;
+        let router = router(&state);
+
+        let (_, first) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries?size=1&sort=name",
+            None,
+            None,
+        )
+        .await;
+        let next = first["next"].as_str().unwrap();
+
+        for uri in [
+            "/api/v1/apothecaries?cursor=garbage".to_owned(),
+            // Cursors only continue the ordering they were made for.
+            format!("/api/v1/apothecaries?size=1&sort=name,desc&cursor={next}"),
+        ] {
+            let (status, body) = send(&router, Method::GET, &uri, None, None).await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}: {body}");
+            assert_eq!(body["code"], "invalidCursor", "{uri}: {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn medication_search_is_paginated_by_name() {
+        let state = state().await;
+        let router = router(&state);
+        let search = "/api/v1/apothecaries/medications?latitude=48.194256&longitude=16.31812";
+
+        let (status, first) = send(
+            &router,
+            Method::GET,
+            &format!("{search}&name=I&maxDistance=10&size=1"),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{first}");
+        assert_eq!(first["totalElements"], 2);
+        assert_eq!(first["content"][0]["medication"]["name"], "Ibuprofen");
+
+        let (status, second) = send(
+            &router,
+            Method::GET,
+            &format!("{search}&name=I&maxDistance=10&size=1&page=1"),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{second}");
+        assert_eq!(second["content"][0]["medication"]["name"], "Oleovit D3 TR");
+        assert_eq!(second["content"][0]["results"].as_array().unwrap().len(), 2);
+        assert_eq!(second["last"], true);
+    }
+
+    #[tokio::test]
+    async fn medication_search_only_covers_nearby_stock() {
+        let state = state().await;
+        let router = router(&state);
+
+        // Zur goldenen Krone is about 4 km away from St. Rudolf.
+        let (status, page) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries/medications?latitude=48.194256&longitude=16.31812\
+             &name=oleovit&maxDistance=1",
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{page}");
+
+        let results = page["content"][0]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["apothecary"]["name"], "St. Rudolf");
+
+        // Wildcards in the name are matched literally.
+        let (status, page) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries/medications?latitude=48.194256&longitude=16.31812\
+             &name=%25&maxDistance=10",
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{page}");
+        assert_eq!(page["empty"], true);
+    }
+}
@@ -2,7 +2,9 @@ use std::sync::Arc;
 
 use entity::DatabaseConnection;
 use service::{
-    apothecary::ApothecaryService, jwt::JwtService, reservation::ReservationService,
+    admin::AdminService, api_key::ApiKeyService, apothecary::ApothecaryService,
+    export::ExportService, jwt::JwtService, mail, notification::NotificationService,
+    oidc::OidcService, reservation::ReservationService, staff::StaffService, totp::TotpService,
     user::UserService,
 };
 use settings::Settings;
@@ -15,15 +17,38 @@ pub struct AppState {
     pub jwt_service: Arc<JwtService>,
     pub user_service: Arc<UserService>,
     pub reservation_service: Arc<ReservationService>,
+    pub notification_service: Arc<NotificationService>,
+    pub export_service: Arc<ExportService>,
+    pub totp_service: Arc<TotpService>,
+    pub oidc_service: Arc<OidcService>,
+    pub staff_service: Arc<StaffService>,
+    pub admin_service: Arc<AdminService>,
+    pub api_key_service: Arc<ApiKeyService>,
 }
 
 impl AppState {
     pub fn new(settings: Settings, conn: DatabaseConnection) -> anyhow::Result<Self> {
         let settings = Arc::new(settings);
         let apothecary_service = Arc::new(ApothecaryService::new(conn.clone()));
-        let jwt_service = Arc::new(JwtService::new(conn.clone(), settings.clone()));
-        let user_service = Arc::new(UserService::new(conn.clone()));
-        let reservation_service = Arc::new(ReservationService::new(conn.clone()));
+        let jwt_service = Arc::new(JwtService::new(conn.clone(), settings.clone())?);
+        let mailer = mail::from_settings(&settings.mail)?;
+        let user_service = Arc::new(UserService::new(
+            conn.clone(),
+            settings.clone(),
+            mailer.clone(),
+        ));
+        let reservation_service = Arc::new(ReservationService::new(conn.clone(), settings.clone()));
+        let notification_service = Arc::new(NotificationService::new(conn.clone()));
+        let export_service = Arc::new(ExportService::new(conn.clone()));
+        let totp_service = Arc::new(TotpService::new(conn.clone(), settings.clone()));
+        let oidc_service = Arc::new(OidcService::new(conn.clone(), settings.clone()));
+        let staff_service = Arc::new(StaffService::new(
+            conn.clone(),
+            settings.clone(),
+            mailer.clone(),
+        ));
+        let admin_service = Arc::new(AdminService::new(conn.clone(), settings.clone(), mailer));
+        let api_key_service = Arc::new(ApiKeyService::new(conn.clone()));
 
         Ok(Self {
             conn,
@@ -32,6 +57,13 @@ impl AppState {
             jwt_service,
             user_service,
             reservation_service,
+            notification_service,
+            export_service,
+            totp_service,
+            oidc_service,
+            staff_service,
+            admin_service,
+            api_key_service,
         })
     }
 }
@@ -1,48 +1,106 @@
-use std::fmt::Display;
+use std::{fmt::Display, marker::PhantomData};
 
-use axum::extract::FromRequestParts;
+use axum::extract::{FromRequestParts, Request};
 use axum::http::request::Parts;
 use axum::http::StatusCode;
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
-use axum::{Json, RequestPartsExt};
+use axum::RequestPartsExt;
 use axum_extra::{
     headers::{authorization::Bearer, Authorization},
     TypedHeader,
 };
-use dto::error::RestError;
-use service::jwt::Role;
+use service::{
+    api_key::{ApiKey, ApiKeyServiceError},
+    jwt::Role,
+    user::UserServiceError,
+};
 use uuid::Uuid;
 
-use crate::appstate::AppState;
+use crate::{
+    appstate::AppState,
+    error::{problem, ApiError},
+};
 
 pub enum AuthError {
     InvalidToken,
+    InvalidApiKey,
+    /// An API key was presented to a route that only serves users.
+    ApiKeyNotAccepted,
+    AccountDisabled,
+    MissingRole(Role),
+    Internal,
 }
 
 impl Display for AuthError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             AuthError::InvalidToken => write!(f, "Invalid token"),
+            AuthError::InvalidApiKey => write!(f, "Invalid or expired API key"),
+            AuthError::ApiKeyNotAccepted => write!(f, "API keys cannot be used for this route"),
+            AuthError::AccountDisabled => write!(f, "Account is disabled"),
+            AuthError::MissingRole(role) => write!(f, "Requires role {:?}", role),
+            AuthError::Internal => write!(f, "Failed to authenticate the request"),
+        }
+    }
+}
+
+impl ApiError for AuthError {
+    fn status(&self) -> StatusCode {
+        match self {
+            AuthError::InvalidToken | AuthError::InvalidApiKey => StatusCode::UNAUTHORIZED,
+            AuthError::ApiKeyNotAccepted
+            | AuthError::AccountDisabled
+            | AuthError::MissingRole(_) => StatusCode::FORBIDDEN,
+            AuthError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            AuthError::InvalidToken => "invalidToken",
+            AuthError::InvalidApiKey => "invalidApiKey",
+            AuthError::ApiKeyNotAccepted => "apiKeyNotAccepted",
+            AuthError::AccountDisabled => "accountDisabled",
+            AuthError::MissingRole(_) => "missingRole",
+            AuthError::Internal => "internalError",
         }
     }
 }
 
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::UNAUTHORIZED,
-            Json(RestError {
-                message: self.to_string(),
-            }),
-        )
-            .into_response()
+        problem(self)
     }
 }
 
+/// A logged-in user. API keys are not accepted: they belong to an apothecary rather than a
+/// user, hold no roles and are limited to its inventory. Routes that serve them take
+/// [`Auth`] instead.
 #[derive(Clone)]
-pub struct Auth {
+pub struct UserAuth {
     pub user_id: Uuid,
     pub roles: Vec<Role>,
+    /// When the user logged in, see [`service::jwt::TokenClaims::auth_time`].
+    pub auth_time: i64,
+}
+
+impl UserAuth {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+}
+
+/// Header carrying an API key, see [`Auth`].
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The caller of a request, authenticated by a bearer JWT or by an API key. A request with an
+/// `X-Api-Key` header is authenticated by the key, anything else like [`UserAuth`]. Handlers
+/// have to check the key's apothecary and scope themselves.
+#[derive(Clone)]
+pub enum Auth {
+    User(UserAuth),
+    ApiKey(ApiKey),
 }
 
 #[axum::async_trait]
@@ -53,19 +111,131 @@ impl FromRequestParts<AppState> for Auth {
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
+        let Some(key) = parts.headers.get(API_KEY_HEADER) else {
+            return Ok(Self::User(
+                UserAuth::from_request_parts(parts, state).await?,
+            ));
+        };
+
+        let key = key.to_str().map_err(|_| AuthError::InvalidApiKey)?;
+
+        state
+            .api_key_service
+            .authenticate(key)
+            .await
+            .map(Self::ApiKey)
+            .map_err(|e| match e {
+                ApiKeyServiceError::Anyhow(e) => {
+                    tracing::error!("Failed to look up API key: {}", e);
+                    AuthError::Internal
+                }
+                _ => AuthError::InvalidApiKey,
+            })
+    }
+}
+
+/// Marker for a role that a route requires, see [`HasRole`] and [`require_role`].
+pub trait RequiredRole: Send + Sync + 'static {
+    const ROLE: Role;
+}
+
+pub struct Customer;
+pub struct Apothecary;
+pub struct Admin;
+
+impl RequiredRole for Customer {
+    const ROLE: Role = Role::Customer;
+}
+
+impl RequiredRole for Apothecary {
+    const ROLE: Role = Role::Apothecary;
+}
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// Extracts [`UserAuth`] and rejects the request with 403 unless the account holds role `R`.
+pub struct HasRole<R: RequiredRole>(pub UserAuth, PhantomData<R>);
+
+#[axum::async_trait]
+impl<R: RequiredRole> FromRequestParts<AppState> for HasRole<R> {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let auth = UserAuth::from_request_parts(parts, state).await?;
+
+        if !auth.has_role(R::ROLE) {
+            return Err(AuthError::MissingRole(R::ROLE));
+        }
+
+        Ok(Self(auth, PhantomData))
+    }
+}
+
+/// Middleware guarding a group of routes with a role. Use with
+/// `middleware::from_fn_with_state(state, require_role::<R>)`.
+pub async fn require_role<R: RequiredRole>(
+    HasRole(auth, _): HasRole<R>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    request.extensions_mut().insert(auth);
+    next.run(request).await
+}
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for UserAuth {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(auth) = parts.extensions.get::<UserAuth>() {
+            return Ok(auth.clone());
+        }
+
         let TypedHeader(Authorization(bearer)) = parts
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
-            .map_err(|_| AuthError::InvalidToken)?;
+            .map_err(|_| {
+                if parts.headers.contains_key(API_KEY_HEADER) {
+                    AuthError::ApiKeyNotAccepted
+                } else {
+                    AuthError::InvalidToken
+                }
+            })?;
 
         let token_data = state
             .jwt_service
             .claims(bearer.token())
             .map_err(|_| AuthError::InvalidToken)?;
 
+        let user_id = Uuid::parse_str(&token_data.sub).map_err(|_| AuthError::InvalidToken)?;
+
+        // Tokens outlive changes to the account, so it is looked up on every request. The roles
+        // come from the account as well, the ones in the token may be out of date.
+        let user = state
+            .user_service
+            .get_active(user_id)
+            .await
+            .map_err(|e| match e {
+                UserServiceError::UserNotFound => AuthError::InvalidToken,
+                UserServiceError::AccountDisabled => AuthError::AccountDisabled,
+                e => {
+                    tracing::error!("Failed to look up user {}: {}", user_id, e);
+                    AuthError::Internal
+                }
+            })?;
+
         Ok(Self {
-            user_id: Uuid::parse_str(&token_data.sub).map_err(|_| AuthError::InvalidToken)?,
-            roles: token_data.roles,
+            user_id,
+            roles: Role::for_user_type(&user.user_type),
+            auth_time: token_data.auth_time,
         })
     }
 }
@@ -0,0 +1,193 @@
+use std::fmt::Display;
+
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use dto::error::{FieldError, RestError};
+use service::page::PageError;
+
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// An error that handlers report as problem details, see [`problem`].
+pub(crate) trait ApiError: Display {
+    fn status(&self) -> StatusCode;
+
+    /// Stable identifier of the error, sent as `code`.
+    fn code(&self) -> &'static str;
+
+    fn field_errors(&self) -> Vec<FieldError> {
+        Vec::new()
+    }
+
+    /// Headers sent along with the error, e.g. `Retry-After`.
+    fn headers(&self) -> HeaderMap {
+        HeaderMap::new()
+    }
+}
+
+/// Turns an error into an `application/problem+json` response. Server errors are logged and
+/// only described generically, so internals do not leak to clients.
+pub(crate) fn problem<E: ApiError>(error: E) -> Response {
+    let status = error.status();
+
+    let detail = if status.is_server_error() {
+        tracing::error!("Error: {}", error);
+        "The request could not be processed".to_owned()
+    } else {
+        error.to_string()
+    };
+
+    let body = RestError {
+        problem_type: "about:blank".to_owned(),
+        title: status.canonical_reason().unwrap_or_default().to_owned(),
+        status: status.as_u16(),
+        detail,
+        code: error.code().to_owned(),
+        request_id: REQUEST_ID.try_with(Clone::clone).ok(),
+        errors: error.field_errors(),
+    };
+
+    let mut response = (status, error.headers(), Json(body)).into_response();
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+
+    response
+}
+
+/// A client error found by the endpoint itself rather than by a service.
+pub(crate) struct BadRequest {
+    pub code: &'static str,
+    pub message: &'static str,
+}
+
+impl Display for BadRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ApiError for BadRequest {
+    fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn code(&self) -> &'static str {
+        self.code
+    }
+}
+
+impl ApiError for PageError {
+    fn status(&self) -> StatusCode {
+        match self {
+            PageError::InvalidColumnName(_) | PageError::InvalidCursor => StatusCode::BAD_REQUEST,
+            PageError::DbErr(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            PageError::InvalidColumnName(_) => "invalidSort",
+            PageError::InvalidCursor => "invalidCursor",
+            PageError::DbErr(_) => "internalError",
+        }
+    }
+}
+
+impl ApiError for anyhow::Error {
+    fn status(&self) -> StatusCode {
+        StatusCode::INTERNAL_SERVER_ERROR
+    }
+
+    fn code(&self) -> &'static str {
+        "internalError"
+    }
+}
+
+/// Makes the ID assigned by the request ID layer available to [`problem`].
+pub(crate) async fn scope_request_id(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default()
+        .to_owned();
+
+    REQUEST_ID.scope(request_id, next.run(request)).await
+}
+
+pub(crate) fn retry_after(seconds: i64) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::RETRY_AFTER, HeaderValue::from(seconds.max(1)));
+
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware, routing::get, Router};
+    use tower::ServiceExt;
+    use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+
+    use super::*;
+    use crate::test_support::json_body;
+
+    /// A router with the request ID layers of the application and a route that fails.
+    fn failing_router() -> Router {
+        Router::new()
+            .route(
+                "/fails",
+                get(|| async { problem(anyhow::anyhow!("connection to 10.0.0.5:5432 refused")) }),
+            )
+            .layer((
+                SetRequestIdLayer::x_request_id(MakeRequestUuid),
+                PropagateRequestIdLayer::x_request_id(),
+                middleware::from_fn(scope_request_id),
+            ))
+    }
+
+    #[tokio::test]
+    async fn internal_errors_are_generic_problems_with_a_request_id() {
+        for given_id in [None, Some("client-chosen-id")] {
+            let mut request = Request::builder().uri("/fails");
+            if let Some(id) = given_id {
+                request = request.header(REQUEST_ID_HEADER, id);
+            }
+
+            let response = failing_router()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "application/problem+json"
+            );
+            let request_id = response.headers()[REQUEST_ID_HEADER]
+                .to_str()
+                .unwrap()
+                .to_owned();
+            let body = json_body(response).await;
+
+            assert_eq!(body["status"], 500);
+            assert_eq!(body["code"], "internalError");
+            assert_eq!(body["detail"], "The request could not be processed");
+            assert_eq!(body["requestId"], request_id);
+            assert!(!request_id.is_empty());
+            if let Some(id) = given_id {
+                assert_eq!(request_id, id);
+            }
+            assert!(!body.to_string().contains("10.0.0.5"), "{body}");
+        }
+    }
+}
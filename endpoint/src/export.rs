@@ -0,0 +1,222 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{ErrorResponse, IntoResponse},
+    Json,
+};
+use service::export::ExportServiceError;
+
+use crate::{
+    appstate::AppState,
+    auth::UserAuth,
+    error::{problem, ApiError},
+};
+
+impl ApiError for ExportServiceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ExportServiceError::UserNotFound => StatusCode::NOT_FOUND,
+            ExportServiceError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ExportServiceError::UserNotFound => "userNotFound",
+            ExportServiceError::Anyhow(_) => "internalError",
+        }
+    }
+}
+
+pub async fn get(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let export = state
+        .export_service
+        .export(auth.user_id)
+        .await
+        .map_err(problem)?;
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"pharmatracker-export.json\"",
+        )],
+        Json(export),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, Method, StatusCode};
+    use sea_orm::{
+        prelude::{TimeDateTime, TimeDateTimeWithTimeZone},
+        ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set,
+    };
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use crate::{
+        appstate::AppState,
+        test_support::{json_body, respond, router, send, state, verified_customer_token},
+    };
+
+    struct Customer {
+        token: String,
+        id: Uuid,
+        reservation_id: Uuid,
+    }
+
+    /// A customer with a reservation they missed, a linked identity and an audit event.
+    async fn customer_with_history(
+        state: &AppState,
+        router: &axum::Router,
+        email: &str,
+    ) -> Customer {
+        let token = verified_customer_token(state, email).await;
+        let id = entity::user::Entity::find()
+            .filter(entity::user::Column::Email.eq(email))
+            .one(&state.conn)
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+
+        let stock = entity::apothecary_medication::Entity::find()
+            .filter(entity::apothecary_medication::Column::MedicationQuantity.gt(2))
+            .one(&state.conn)
+            .await
+            .unwrap()
+            .unwrap();
+        let (status, reservation) = send(
+            router,
+            Method::POST,
+            "/api/v1/reservations",
+            Some(&token),
+            Some(json!({
+                "apothecaryId": stock.apothecary_id,
+                "medicationId": stock.medication_id,
+                "quantity": { "type": "package", "quantity": 1, "price": "10.99" },
+            })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{reservation}");
+        let reservation_id = reservation["id"].as_str().unwrap().parse().unwrap();
+
+        let now = TimeDateTimeWithTimeZone::now_utc();
+        let now = TimeDateTime::new(now.date(), now.time());
+
+        entity::no_show::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(id),
+            reservation_id: Set(reservation_id),
+            apothecary_id: Set(stock.apothecary_id),
+            missed_at: Set(now),
+        }
+        .insert(&state.conn)
+        .await
+        .unwrap();
+
+        entity::user_identity::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(id),
+            provider: Set("google".to_owned()),
+            subject: Set(format!("subject-of-{email}")),
+            email: Set(Some(email.to_owned())),
+            created_at: Set(now),
+            last_login_at: Set(now),
+        }
+        .insert(&state.conn)
+        .await
+        .unwrap();
+
+        entity::audit_event::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(Some(id)),
+            actor_id: Set(None),
+            kind: Set(entity::audit_event::AuditEventKind::TotpEnabled),
+            ip: Set(Some("203.0.113.7".to_owned())),
+            detail: Set(format!("Enabled by {email}")),
+            created_at: Set(now),
+        }
+        .insert(&state.conn)
+        .await
+        .unwrap();
+
+        Customer {
+            token,
+            id,
+            reservation_id,
+        }
+    }
+
+    fn ids<'a>(items: &'a Value, field: &str) -> Vec<&'a str> {
+        items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item[field].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn exports_contain_the_users_data_only() {
+        let state = state().await;
+        let router = router(&state);
+
+        let jane = customer_with_history(&state, &router, "jane@example.com").await;
+        let bob = customer_with_history(&state, &router, "bob@example.com").await;
+
+        let response = respond(
+            &router,
+            Method::GET,
+            "/api/v1/users/me/export",
+            Some(&jane.token),
+            None,
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"pharmatracker-export.json\""
+        );
+        let export = json_body(response).await;
+
+        assert_eq!(export["profile"]["id"], jane.id.to_string());
+        assert_eq!(export["profile"]["email"], "jane@example.com");
+        assert_eq!(
+            ids(&export["reservations"], "id"),
+            [jane.reservation_id.to_string()]
+        );
+        assert_eq!(
+            ids(&export["noShows"], "reservationId"),
+            [jane.reservation_id.to_string()]
+        );
+        assert_eq!(export["sessions"].as_array().unwrap().len(), 1);
+        assert_eq!(ids(&export["identities"], "email"), ["jane@example.com"]);
+        assert_eq!(
+            ids(&export["auditEvents"], "detail"),
+            ["Enabled by jane@example.com"]
+        );
+
+        // Nothing of the other customer, and no secrets.
+        let password_hash = entity::user::Entity::find_by_id(jane.id)
+            .one(&state.conn)
+            .await
+            .unwrap()
+            .unwrap()
+            .password;
+        let text = export.to_string();
+
+        for leaked in [
+            bob.id.to_string(),
+            bob.reservation_id.to_string(),
+            "bob@example.com".to_owned(),
+            password_hash,
+            jane.token.clone(),
+        ] {
+            assert!(!text.contains(&leaked), "{leaked} in {text}");
+        }
+    }
+}
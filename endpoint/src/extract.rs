@@ -0,0 +1,387 @@
+use std::fmt::Display;
+
+use axum::{
+    extract::{rejection::JsonRejection, FromRequest, FromRequestParts, Request},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::{Query, QueryRejection};
+use dto::error::FieldError;
+use serde::de::DeserializeOwned;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::error::{problem, ApiError};
+
+/// Like [`Json`], but the body also has to pass the rules of its [`Validate`] impl.
+pub struct ValidatedJson<T>(pub T);
+
+/// Like [`Query`], but the parameters also have to pass the rules of their [`Validate`] impl.
+/// Repeated parameters, e.g. several `sort`s, are collected into sequences.
+pub struct ValidatedQuery<T>(pub T);
+
+pub enum RequestRejection {
+    Json(JsonRejection),
+    Query(QueryRejection),
+    Invalid(ValidationErrors),
+}
+
+#[axum::async_trait]
+impl<T, S> FromRequest<S> for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = RequestRejection;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(request, state)
+            .await
+            .map_err(RequestRejection::Json)?;
+
+        value.validate().map_err(RequestRejection::Invalid)?;
+
+        Ok(Self(value))
+    }
+}
+
+#[axum::async_trait]
+impl<T, S> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = RequestRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(RequestRejection::Query)?;
+
+        value.validate().map_err(RequestRejection::Invalid)?;
+
+        Ok(Self(value))
+    }
+}
+
+impl Display for RequestRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestRejection::Json(e) => write!(f, "{}", e.body_text()),
+            RequestRejection::Query(e) => write!(f, "Failed to deserialize query string: {}", e),
+            RequestRejection::Invalid(_) => write!(f, "The request contains invalid fields"),
+        }
+    }
+}
+
+impl ApiError for RequestRejection {
+    fn status(&self) -> StatusCode {
+        match self {
+            RequestRejection::Json(JsonRejection::MissingJsonContentType(_)) => {
+                StatusCode::UNSUPPORTED_MEDIA_TYPE
+            }
+            RequestRejection::Json(JsonRejection::BytesRejection(e)) => e.status(),
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            RequestRejection::Json(JsonRejection::MissingJsonContentType(_)) => {
+                "unsupportedMediaType"
+            }
+            RequestRejection::Json(_) => "malformedBody",
+            RequestRejection::Query(_) => "malformedQuery",
+            RequestRejection::Invalid(_) => "validationFailed",
+        }
+    }
+
+    fn field_errors(&self) -> Vec<FieldError> {
+        let RequestRejection::Invalid(errors) = self else {
+            return Vec::new();
+        };
+
+        let mut field_errors = Vec::new();
+        collect_field_errors(errors, "", &mut field_errors);
+        field_errors.sort_by(|a, b| a.field.cmp(&b.field));
+
+        field_errors
+    }
+}
+
+impl IntoResponse for RequestRejection {
+    fn into_response(self) -> Response {
+        problem(self)
+    }
+}
+
+fn collect_field_errors(errors: &ValidationErrors, path: &str, out: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let field = if path.is_empty() {
+            camel_case(field)
+        } else {
+            format!("{}.{}", path, camel_case(field))
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                out.extend(errors.iter().map(|error| FieldError {
+                    field: field.clone(),
+                    code: field_code(error),
+                    message: field_message(error),
+                }));
+            }
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(errors, &field, out),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    collect_field_errors(errors, &format!("{}[{}]", field, index), out);
+                }
+            }
+        }
+    }
+}
+
+/// Field names of the DTOs are snake case in Rust and camel case on the wire.
+fn camel_case(field: &str) -> String {
+    let mut parts = field.split('_');
+    let mut name = parts.next().unwrap_or_default().to_owned();
+
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+
+    name
+}
+
+fn field_code(error: &ValidationError) -> String {
+    match error.code.as_ref() {
+        "length" => "invalidLength".to_owned(),
+        "range" => "outOfRange".to_owned(),
+        "email" => "invalidEmail".to_owned(),
+        code => code.to_owned(),
+    }
+}
+
+fn field_message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+
+    let min = error.params.get("min");
+    let max = error.params.get("max");
+
+    match error.code.as_ref() {
+        "length" => match (min, max) {
+            (Some(min), Some(max)) => format!("Must be between {} and {} characters", min, max),
+            (Some(min), None) if min == 1 => "Must not be empty".to_owned(),
+            (Some(min), None) => format!("Must be at least {} characters", min),
+            (None, Some(max)) => format!("Must be at most {} characters", max),
+            (None, None) => "Has an invalid length".to_owned(),
+        },
+        "range" => match (min, max) {
+            (Some(min), Some(max)) => format!("Must be between {} and {}", min, max),
+            (Some(min), None) => format!("Must be at least {}", min),
+            (None, Some(max)) => format!("Must be at most {}", max),
+            (None, None) => "Is out of range".to_owned(),
+        },
+        "email" => "Must be a valid email address".to_owned(),
+        "positiveQuantity" => "Must be greater than zero".to_owned(),
+        _ => "Is invalid".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    use crate::test_support::{customer_token, router, send, state};
+
+    fn field_errors(body: &Value) -> Vec<(&str, &str, &str)> {
+        body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| {
+                (
+                    error["field"].as_str().unwrap(),
+                    error["code"].as_str().unwrap(),
+                    error["message"].as_str().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn invalid_bodies_report_each_field() {
+        let state = state().await;
+        let router = router(&state);
+
+        let (status, body) = send(
+            &router,
+            Method::POST,
+            "/api/v1/register",
+            None,
+            Some(json!({ "name": "", "email": "not-an-address", "password": "secret" })),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["code"], "validationFailed");
+        assert_eq!(
+            field_errors(&body),
+            vec![
+                ("email", "invalidEmail", "Must be a valid email address"),
+                (
+                    "name",
+                    "invalidLength",
+                    "Must be between 1 and 100 characters"
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn reservations_need_a_quantity_in_range() {
+        let state = state().await;
+        let router = router(&state);
+        let token = customer_token(&state, "jane@example.com").await;
+
+        for (quantity, code, message) in [
+            (0, "positiveQuantity", "Must be greater than zero"),
+            (1_001, "outOfRange", "Must be at most 1000"),
+        ] {
+            let (status, body) = send(
+                &router,
+                Method::POST,
+                "/api/v1/reservations",
+                Some(&token),
+                Some(json!({
+                    "apothecaryId": Uuid::new_v4(),
+                    "medicationId": Uuid::new_v4(),
+                    "quantity": { "type": "package", "quantity": quantity, "price": "1.00" },
+                })),
+            )
+            .await;
+
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+            assert_eq!(field_errors(&body), vec![("quantity", code, message)]);
+        }
+    }
+
+    #[tokio::test]
+    async fn bodies_of_the_wrong_shape_are_malformed() {
+        let state = state().await;
+        let router = router(&state);
+
+        let (status, body) = send(
+            &router,
+            Method::POST,
+            "/api/v1/register",
+            None,
+            Some(json!({ "name": 1 })),
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["code"], "malformedBody");
+        assert!(body.get("errors").is_none(), "{body}");
+    }
+
+    #[tokio::test]
+    async fn invalid_query_parameters_are_rejected() {
+        let state = state().await;
+        let router = router(&state);
+
+        let (status, body) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries?size=1000",
+            None,
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["code"], "validationFailed");
+        assert_eq!(
+            field_errors(&body),
+            vec![("size", "outOfRange", "Must be between 1 and 100")]
+        );
+
+        let (status, body) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries?size=many",
+            None,
+            None,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["code"], "malformedQuery");
+    }
+
+    #[tokio::test]
+    async fn sort_parameters_can_be_repeated() {
+        let state = state().await;
+        let router = router(&state);
+
+        let names = |body: &Value| -> Vec<String> {
+            body["content"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|apothecary| apothecary["name"].as_str().unwrap().to_owned())
+                .collect()
+        };
+
+        let (status, ascending) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries?sort=name,asc&sort=id",
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{ascending}");
+
+        let (status, descending) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries?sort=name,desc&sort=id",
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{descending}");
+
+        let mut reversed = names(&descending);
+        reversed.reverse();
+        assert_eq!(names(&ascending), reversed);
+        assert_eq!(names(&ascending), ["St. Rudolf", "Zur goldenen Krone"]);
+
+        let (status, body) = send(
+            &router,
+            Method::GET,
+            "/api/v1/apothecaries?sort=name,sideways",
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{body}");
+        assert_eq!(body["code"], "malformedQuery");
+    }
+
+    #[test]
+    fn field_names_are_camel_case() {
+        assert_eq!(super::camel_case("apothecary_id"), "apothecaryId");
+        assert_eq!(super::camel_case("end_date_time"), "endDateTime");
+        assert_eq!(super::camel_case("name"), "name");
+    }
+}
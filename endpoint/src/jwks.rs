@@ -0,0 +1,10 @@
+use axum::{extract::State, http::header, response::IntoResponse, Json};
+
+use crate::appstate::AppState;
+
+pub async fn get(State(ref state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(state.jwt_service.jwks().clone()),
+    )
+}
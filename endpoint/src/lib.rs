@@ -1,7 +1,9 @@
 use appstate::AppState;
+use auth::{require_role, Admin, Apothecary, Customer};
 use axum::{
-    extract::Request,
-    routing::{delete, get, post},
+    extract::{ConnectInfo, Request},
+    middleware,
+    routing::{delete, get, patch, post, put},
     Router,
 };
 use hyper::body::Incoming;
@@ -10,15 +12,28 @@ use migration::{Migrator, MigratorTrait};
 use tokio::net::TcpListener;
 use tokio::sync::watch;
 use tower::Service;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::timeout::TimeoutLayer;
 use tower_http::trace::TraceLayer;
-use tracing::{debug, info};
+use tracing::{debug, error, info, info_span};
 
+mod admin;
+mod api_key;
 mod apothecary;
 mod appstate;
 mod auth;
+mod error;
+mod export;
+mod extract;
 mod heartbeat;
+mod jwks;
+mod notification;
+mod oidc;
 mod reservation;
+mod staff;
+#[cfg(test)]
+mod test_support;
+mod totp;
 mod user;
 
 async fn migrate(db: &entity::DatabaseConnection, drop_all: bool) -> Result<(), migration::DbErr> {
@@ -30,13 +45,104 @@ async fn migrate(db: &entity::DatabaseConnection, drop_all: bool) -> Result<(),
 }
 
 fn create_router(appstate: AppState) -> Router {
+    let customer = Router::new()
+        .route("/reservations", get(reservation::get))
+        .route("/reservations", post(reservation::post))
+        .route("/reservations/:id", delete(reservation::delete))
+        .route("/reservations/:id/cancel", post(reservation::cancel))
+        .route_layer(middleware::from_fn_with_state(
+            appstate.clone(),
+            require_role::<Customer>,
+        ));
+
+    let apothecary = Router::new()
+        .route(
+            "/users/me/apothecary/medications",
+            get(apothecary::get_own_medications),
+        )
+        .route(
+            "/users/me/apothecary/reservations",
+            get(reservation::get_apothecary),
+        )
+        .route(
+            "/users/me/apothecary/notifications",
+            get(notification::get_apothecary),
+        )
+        .route("/users/me/apothecaries", get(staff::get_memberships))
+        .route("/apothecaries/:id/staff", get(staff::get_members))
+        .route(
+            "/apothecaries/:id/staff/:user_id",
+            patch(staff::update_role).delete(staff::remove_member),
+        )
+        .route(
+            "/apothecaries/:id/invitations",
+            get(staff::get_invitations).post(staff::invite),
+        )
+        .route(
+            "/apothecaries/:id/invitations/:invitation_id",
+            delete(staff::revoke_invitation),
+        )
+        .route(
+            "/apothecaries/:id/api-keys",
+            get(api_key::get).post(api_key::post),
+        )
+        .route(
+            "/apothecaries/:id/api-keys/:key_id",
+            delete(api_key::delete),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            appstate.clone(),
+            require_role::<Apothecary>,
+        ));
+
+    let admin = Router::new()
+        .route("/admin/users", get(admin::get_users))
+        .route(
+            "/admin/users/:id",
+            get(admin::get_user).patch(admin::update_user_type),
+        )
+        .route("/admin/users/:id/disable", post(admin::disable_user))
+        .route("/admin/users/:id/enable", post(admin::enable_user))
+        .route(
+            "/admin/users/:id/password-reset",
+            post(admin::force_password_reset),
+        )
+        .route(
+            "/admin/users/:id/apothecaries",
+            get(admin::get_memberships).post(admin::link_apothecary),
+        )
+        .route(
+            "/admin/users/:id/apothecaries/:apothecary_id",
+            delete(admin::unlink_apothecary),
+        )
+        .route(
+            "/admin/users/:id/audit-events",
+            get(admin::get_audit_events),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            appstate.clone(),
+            require_role::<Admin>,
+        ));
+
     Router::new()
+        .route("/.well-known/jwks.json", get(jwks::get))
         .nest(
             "/api/v1",
             Router::new()
                 .route("/heartbeat", get(heartbeat::get))
                 .route("/login", post(user::login))
+                .route("/login/totp", post(user::login_second_factor))
+                .route("/oidc/:provider/authorize", post(oidc::authorize))
+                .route("/oidc/:provider/callback", post(oidc::callback))
+                .route("/oidc/:provider/link/authorize", post(oidc::authorize_link))
+                .route("/oidc/:provider/link", post(oidc::link))
                 .route("/register", post(user::register))
+                .route("/verify-email", post(user::verify_email))
+                .route("/verify-email/resend", post(user::resend_verification))
+                .route("/password/forgot", post(user::forgot_password))
+                .route("/password/reset", post(user::reset_password))
+                .route("/token/refresh", post(user::refresh))
+                .route("/logout", post(user::logout))
                 .route("/apothecaries", get(apothecary::get))
                 .route(
                     "/apothecaries/medications",
@@ -46,32 +152,98 @@ fn create_router(appstate: AppState) -> Router {
                     "/apothecaries/medications",
                     post(apothecary::get_medications_by_cda),
                 )
-                .route("/reservations", get(reservation::get))
-                .route("/reservations", post(reservation::post))
-                .route("/reservations/:id", delete(reservation::delete))
-                .route("/users/me", get(user::me))
                 .route(
-                    "/users/me/apothecary/medications",
-                    get(apothecary::get_own_medications),
-                ),
+                    "/users/me",
+                    get(user::me).patch(user::update_me).delete(user::delete_me),
+                )
+                .route("/users/me/password", post(user::change_password))
+                .route("/users/me/export", get(export::get))
+                .route("/invitations/accept", post(staff::accept))
+                .route(
+                    "/apothecaries/:id/inventory",
+                    get(apothecary::get_inventory),
+                )
+                .route(
+                    "/apothecaries/:id/inventory/:medication_id",
+                    put(apothecary::put_stock).delete(apothecary::delete_stock),
+                )
+                .route("/users/me/identities", get(oidc::get_identities))
+                .route("/users/me/identities/:id", delete(oidc::unlink))
+                .route("/users/me/totp", post(totp::enroll).delete(totp::disable))
+                .route("/users/me/totp/confirm", post(totp::confirm))
+                .route(
+                    "/users/me/totp/recovery-codes",
+                    post(totp::regenerate_recovery_codes),
+                )
+                .merge(customer)
+                .merge(apothecary)
+                .merge(admin),
         )
         .layer((
-            TraceLayer::new_for_http(),
+            SetRequestIdLayer::x_request_id(MakeRequestUuid),
+            TraceLayer::new_for_http().make_span_with(|request: &Request| {
+                let request_id = request
+                    .headers()
+                    .get(error::REQUEST_ID_HEADER)
+                    .and_then(|id| id.to_str().ok())
+                    .unwrap_or_default();
+
+                info_span!(
+                    "request",
+                    method = %request.method(),
+                    uri = %request.uri(),
+                    request_id,
+                )
+            }),
+            PropagateRequestIdLayer::x_request_id(),
+            middleware::from_fn(error::scope_request_id),
             TimeoutLayer::new(std::time::Duration::from_secs(10)),
         ))
         .with_state(appstate)
 }
 
+fn spawn_expiry_sweep(appstate: &AppState, mut close_rx: watch::Receiver<()>) {
+    let reservation_service = appstate.reservation_service.clone();
+    let period =
+        std::time::Duration::from_secs(appstate.settings.reservation.expiry_sweep_interval_seconds);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    match reservation_service.expire_overdue().await {
+                        Ok(0) => {}
+                        Ok(expired) => info!("Expired {expired} overdue reservations"),
+                        Err(e) => error!("Failed to expire overdue reservations: {e}"),
+                    }
+
+                    if let Err(e) = reservation_service.delete_expired_idempotency_keys().await {
+                        error!("Failed to delete expired idempotency keys: {e}");
+                    }
+                }
+                _ = close_rx.changed() => {
+                    break;
+                }
+            }
+        }
+    });
+}
+
 pub async fn run(close_rx: watch::Receiver<()>) -> anyhow::Result<()> {
     let settings = settings::Settings::new("config")?;
     let db: migration::sea_orm::prelude::DatabaseConnection =
         entity::create_database_connection(&settings.database.url).await?;
 
     let server_url = format!("{}:{}", settings.endpoint.host, settings.endpoint.port);
+    let refresh = settings.database.refresh;
 
     let appstate = AppState::new(settings, db)?;
 
-    migrate(&appstate.conn, true).await?;
+    migrate(&appstate.conn, refresh).await?;
+
+    spawn_expiry_sweep(&appstate, close_rx.clone());
 
     let app = create_router(appstate);
 
@@ -101,9 +273,11 @@ pub async fn run(close_rx: watch::Receiver<()>) -> anyhow::Result<()> {
         tokio::spawn(async move {
             let socket = TokioIo::new(socket);
 
-            let hyper_service = hyper::service::service_fn(move |request: Request<Incoming>| {
-                service.clone().call(request)
-            });
+            let hyper_service =
+                hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                    request.extensions_mut().insert(ConnectInfo(remote_addr));
+                    service.clone().call(request)
+                });
 
             let conn = hyper::server::conn::http1::Builder::new()
                 .serve_connection(socket, hyper_service)
@@ -111,20 +285,16 @@ pub async fn run(close_rx: watch::Receiver<()>) -> anyhow::Result<()> {
 
             let mut conn = std::pin::pin!(conn);
 
-            loop {
-                tokio::select! {
-                    result = conn.as_mut() => {
-                        if let Err(err) = result {
-                            debug!("Failed to serve connection: {err:#}");
-                        }
-                        break;
-                    }
-                    _ = close_rx.changed() => {
-                        info!("Shutting down server...");
-                        conn.as_mut().graceful_shutdown();
-                        break;
+            tokio::select! {
+                result = conn.as_mut() => {
+                    if let Err(err) = result {
+                        debug!("Failed to serve connection: {err:#}");
                     }
                 }
+                _ = close_rx.changed() => {
+                    info!("Shutting down server...");
+                    conn.as_mut().graceful_shutdown();
+                }
             }
 
             debug!("Connection {remote_addr} closed");
@@ -144,3 +314,402 @@ pub async fn run(close_rx: watch::Receiver<()>) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::test_support::{customer_token, router, send, state, token_for};
+
+    const CUSTOMER_ROUTE: &str = "/api/v1/reservations";
+    const APOTHECARY_ROUTE: &str = "/api/v1/users/me/apothecaries";
+    const ADMIN_ROUTE: &str = "/api/v1/admin/users";
+
+    /// What a route answers one caller: a status with the problem code (empty for success), or
+    /// a rejection for lacking the named role.
+    #[derive(Clone, Copy)]
+    enum Expect {
+        Status(StatusCode, &'static str),
+        MissingRole(&'static str),
+    }
+
+    use Expect::{MissingRole, Status};
+
+    const OK: Expect = Status(StatusCode::OK, "");
+    const NO_TOKEN: Expect = Status(StatusCode::UNAUTHORIZED, "invalidToken");
+    const NOT_STAFF: Expect = Status(StatusCode::FORBIDDEN, "forbidden");
+    const MALFORMED_BODY: Expect = Status(StatusCode::BAD_REQUEST, "malformedBody");
+    const MALFORMED_QUERY: Expect = Status(StatusCode::BAD_REQUEST, "malformedQuery");
+    const NO_MEDIA_TYPE: Expect =
+        Status(StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupportedMediaType");
+
+    const fn not_found(code: &'static str) -> Expect {
+        Status(StatusCode::NOT_FOUND, code)
+    }
+
+    /// Every route with what it answers without a token, and to a customer, to the owner of
+    /// St. Rudolf and to an admin. Requests with a body send an empty object, ids that aren't
+    /// St. Rudolf's are unknown.
+    fn routes(rudolf: Uuid, unknown: Uuid) -> Vec<(Method, String, [Expect; 4])> {
+        let both = |expect| [expect, expect, expect, expect];
+        let signed_in = |expect| [NO_TOKEN, expect, expect, expect];
+        let customer = |expect| [NO_TOKEN, expect, MissingRole("Customer"), expect];
+        let apothecary = |owner, admin| [NO_TOKEN, MissingRole("Apothecary"), owner, admin];
+        let admin = |expect| [NO_TOKEN, MissingRole("Admin"), MissingRole("Admin"), expect];
+
+        let staff = format!("/api/v1/apothecaries/{rudolf}");
+        let user = format!("/api/v1/admin/users/{unknown}");
+
+        vec![
+            (Method::GET, "/.well-known/jwks.json".into(), both(OK)),
+            (Method::GET, "/api/v1/heartbeat".into(), both(OK)),
+            (Method::POST, "/api/v1/login".into(), both(MALFORMED_BODY)),
+            (
+                Method::POST,
+                "/api/v1/login/totp".into(),
+                both(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/oidc/unknown/authorize".into(),
+                both(not_found("unknownProvider")),
+            ),
+            (
+                Method::POST,
+                "/api/v1/oidc/unknown/callback".into(),
+                both(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/oidc/unknown/link/authorize".into(),
+                signed_in(not_found("unknownProvider")),
+            ),
+            (
+                Method::POST,
+                "/api/v1/oidc/unknown/link".into(),
+                signed_in(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/register".into(),
+                both(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/verify-email".into(),
+                both(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/verify-email/resend".into(),
+                both(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/password/forgot".into(),
+                both(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/password/reset".into(),
+                both(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/token/refresh".into(),
+                both(MALFORMED_BODY),
+            ),
+            (Method::POST, "/api/v1/logout".into(), both(MALFORMED_BODY)),
+            (Method::GET, "/api/v1/apothecaries".into(), both(OK)),
+            (
+                Method::GET,
+                "/api/v1/apothecaries/medications".into(),
+                both(MALFORMED_QUERY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/apothecaries/medications".into(),
+                both(MALFORMED_QUERY),
+            ),
+            (Method::GET, "/api/v1/users/me".into(), signed_in(OK)),
+            (Method::PATCH, "/api/v1/users/me".into(), signed_in(OK)),
+            (
+                Method::DELETE,
+                "/api/v1/users/me".into(),
+                signed_in(NO_MEDIA_TYPE),
+            ),
+            (
+                Method::POST,
+                "/api/v1/users/me/password".into(),
+                signed_in(MALFORMED_BODY),
+            ),
+            (Method::GET, "/api/v1/users/me/export".into(), signed_in(OK)),
+            (
+                Method::POST,
+                "/api/v1/invitations/accept".into(),
+                signed_in(MALFORMED_BODY),
+            ),
+            (
+                Method::GET,
+                format!("{staff}/inventory"),
+                [NO_TOKEN, NOT_STAFF, OK, NOT_STAFF],
+            ),
+            (
+                Method::PUT,
+                format!("{staff}/inventory/{unknown}"),
+                signed_in(MALFORMED_BODY),
+            ),
+            (
+                Method::DELETE,
+                format!("{staff}/inventory/{unknown}"),
+                [
+                    NO_TOKEN,
+                    NOT_STAFF,
+                    not_found("medicationNotFound"),
+                    NOT_STAFF,
+                ],
+            ),
+            (
+                Method::GET,
+                "/api/v1/users/me/identities".into(),
+                signed_in(OK),
+            ),
+            (
+                Method::DELETE,
+                format!("/api/v1/users/me/identities/{unknown}"),
+                signed_in(not_found("identityNotFound")),
+            ),
+            (Method::POST, "/api/v1/users/me/totp".into(), signed_in(OK)),
+            (
+                Method::DELETE,
+                "/api/v1/users/me/totp".into(),
+                signed_in(NO_MEDIA_TYPE),
+            ),
+            (
+                Method::POST,
+                "/api/v1/users/me/totp/confirm".into(),
+                signed_in(MALFORMED_BODY),
+            ),
+            (
+                Method::POST,
+                "/api/v1/users/me/totp/recovery-codes".into(),
+                signed_in(MALFORMED_BODY),
+            ),
+            (Method::GET, CUSTOMER_ROUTE.into(), customer(OK)),
+            (
+                Method::POST,
+                CUSTOMER_ROUTE.into(),
+                customer(MALFORMED_BODY),
+            ),
+            (
+                Method::DELETE,
+                format!("{CUSTOMER_ROUTE}/{unknown}"),
+                customer(not_found("reservationNotFound")),
+            ),
+            (
+                Method::POST,
+                format!("{CUSTOMER_ROUTE}/{unknown}/cancel"),
+                customer(not_found("reservationNotFound")),
+            ),
+            (
+                Method::GET,
+                "/api/v1/users/me/apothecary/medications".into(),
+                apothecary(OK, not_found("apothecaryNotFound")),
+            ),
+            (
+                Method::GET,
+                "/api/v1/users/me/apothecary/reservations".into(),
+                apothecary(OK, OK),
+            ),
+            (
+                Method::GET,
+                "/api/v1/users/me/apothecary/notifications".into(),
+                apothecary(OK, OK),
+            ),
+            (Method::GET, APOTHECARY_ROUTE.into(), apothecary(OK, OK)),
+            (
+                Method::GET,
+                format!("{staff}/staff"),
+                apothecary(OK, NOT_STAFF),
+            ),
+            (
+                Method::PATCH,
+                format!("{staff}/staff/{unknown}"),
+                apothecary(MALFORMED_BODY, MALFORMED_BODY),
+            ),
+            (
+                Method::DELETE,
+                format!("{staff}/staff/{unknown}"),
+                apothecary(not_found("memberNotFound"), NOT_STAFF),
+            ),
+            (
+                Method::GET,
+                format!("{staff}/invitations"),
+                apothecary(OK, NOT_STAFF),
+            ),
+            (
+                Method::POST,
+                format!("{staff}/invitations"),
+                apothecary(MALFORMED_BODY, MALFORMED_BODY),
+            ),
+            (
+                Method::DELETE,
+                format!("{staff}/invitations/{unknown}"),
+                apothecary(not_found("invitationNotFound"), NOT_STAFF),
+            ),
+            (
+                Method::GET,
+                format!("{staff}/api-keys"),
+                apothecary(OK, NOT_STAFF),
+            ),
+            (
+                Method::POST,
+                format!("{staff}/api-keys"),
+                apothecary(MALFORMED_BODY, MALFORMED_BODY),
+            ),
+            (
+                Method::DELETE,
+                format!("{staff}/api-keys/{unknown}"),
+                apothecary(not_found("apiKeyNotFound"), NOT_STAFF),
+            ),
+            (Method::GET, ADMIN_ROUTE.into(), admin(OK)),
+            (Method::GET, user.clone(), admin(not_found("userNotFound"))),
+            (Method::PATCH, user.clone(), admin(MALFORMED_BODY)),
+            (
+                Method::POST,
+                format!("{user}/disable"),
+                admin(not_found("userNotFound")),
+            ),
+            (
+                Method::POST,
+                format!("{user}/enable"),
+                admin(not_found("userNotFound")),
+            ),
+            (
+                Method::POST,
+                format!("{user}/password-reset"),
+                admin(not_found("userNotFound")),
+            ),
+            (
+                Method::GET,
+                format!("{user}/apothecaries"),
+                admin(not_found("userNotFound")),
+            ),
+            (
+                Method::POST,
+                format!("{user}/apothecaries"),
+                admin(MALFORMED_BODY),
+            ),
+            (
+                Method::DELETE,
+                format!("{user}/apothecaries/{rudolf}"),
+                admin(not_found("membershipNotFound")),
+            ),
+            (
+                Method::GET,
+                format!("{user}/audit-events"),
+                admin(not_found("userNotFound")),
+            ),
+        ]
+    }
+
+    #[tokio::test]
+    async fn every_route_answers_each_role_as_expected() {
+        let state = state().await;
+        let router = router(&state);
+
+        let customer = customer_token(&state, "customer@example.com").await;
+        let apothecary = token_for(&state, "john@apo.com").await;
+        let admin = token_for(&state, "admin@email.com").await;
+        let callers = [
+            ("nobody", None),
+            ("customer", Some(customer.as_str())),
+            ("apothecary", Some(apothecary.as_str())),
+            ("admin", Some(admin.as_str())),
+        ];
+
+        let rudolf = entity::apothecary::Entity::find()
+            .filter(entity::apothecary::Column::Name.eq("St. Rudolf"))
+            .one(&state.conn)
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+
+        for (method, uri, expected) in routes(rudolf, Uuid::new_v4()) {
+            for ((caller, token), expect) in callers.iter().zip(expected) {
+                let body =
+                    matches!(method, Method::POST | Method::PUT | Method::PATCH).then(|| json!({}));
+                let (status, body) = send(&router, method.clone(), &uri, *token, body).await;
+                let code = body["code"].as_str().unwrap_or_default();
+                let context = format!("{method} {uri} as {caller}: {body}");
+
+                match expect {
+                    Status(expected_status, expected_code) => {
+                        assert_eq!(status, expected_status, "{context}");
+                        assert_eq!(code, expected_code, "{context}");
+                    }
+                    MissingRole(role) => {
+                        assert_eq!(status, StatusCode::FORBIDDEN, "{context}");
+                        assert_eq!(code, "missingRole", "{context}");
+                        assert!(
+                            body["detail"].as_str().unwrap_or_default().contains(role),
+                            "{context}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn role_changes_apply_to_issued_tokens() {
+        let state = state().await;
+        let router = router(&state);
+
+        let admin = token_for(&state, "admin@email.com").await;
+        let jane = customer_token(&state, "jane@example.com").await;
+        let jane_id = entity::user::Entity::find()
+            .filter(entity::user::Column::Email.eq("jane@example.com"))
+            .one(&state.conn)
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        let uri = format!("{ADMIN_ROUTE}/{jane_id}");
+
+        for (user_type, status) in [
+            ("admin", StatusCode::OK),
+            ("customer", StatusCode::FORBIDDEN),
+        ] {
+            let (update_status, body) = send(
+                &router,
+                Method::PATCH,
+                &uri,
+                Some(&admin),
+                Some(json!({ "userType": user_type })),
+            )
+            .await;
+            assert_eq!(update_status, StatusCode::OK, "{body}");
+
+            let (actual, body) = send(&router, Method::GET, ADMIN_ROUTE, Some(&jane), None).await;
+            assert_eq!(actual, status, "as {user_type}: {body}");
+        }
+    }
+
+    #[tokio::test]
+    async fn route_groups_reject_missing_tokens() {
+        let state = state().await;
+        let router = router(&state);
+
+        for uri in [CUSTOMER_ROUTE, APOTHECARY_ROUTE, ADMIN_ROUTE] {
+            let (status, body) = send(&router, Method::GET, uri, None, None).await;
+
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{uri}: {body}");
+            assert_eq!(body["code"], "invalidToken", "{uri}: {body}");
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use axum::{extract::State, response::ErrorResponse, Json};
+use dto::{
+    notification::Notification,
+    page::{Page, Pageable},
+};
+
+use crate::{appstate::AppState, auth::UserAuth, error::problem, extract::ValidatedQuery};
+
+pub async fn get_apothecary(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<Notification>>, ErrorResponse> {
+    let notifications = state
+        .notification_service
+        .get_for_staff(auth.user_id, Some(pageable))
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(notifications.into()))
+}
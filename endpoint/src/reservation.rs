@@ -1,71 +1,283 @@
 use axum::{
     extract::{Path, State},
-    http::StatusCode,
-    response::{ErrorResponse, IntoResponse},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{ErrorResponse, IntoResponse, Response},
     Json,
 };
+use axum_extra::{
+    headers::{self, Header},
+    typed_header::{TypedHeaderRejection, TypedHeaderRejectionReason},
+    TypedHeader,
+};
 use dto::{
-    error::RestError,
-    page::Page,
-    reservation::{MedicationReservation, MedicationReservationRequest},
+    page::{Page, Pageable},
+    reservation::{
+        ApothecaryReservation, MedicationReservation, MedicationReservationCancellationRequest,
+        MedicationReservationRequest, ReservationFilter,
+    },
 };
-use service::reservation::ReservationServiceError;
+use service::reservation::{ReservationServiceError, ReserveOutcome};
 use uuid::Uuid;
 
-use crate::{appstate::AppState, auth::Auth};
+use crate::{
+    appstate::AppState,
+    auth::UserAuth,
+    error::{problem, retry_after, ApiError, BadRequest},
+    extract::{ValidatedJson, ValidatedQuery},
+};
 
-fn handle_reservation_service_error(e: ReservationServiceError) -> impl IntoResponse {
-    let (status, message) = match e {
-        ReservationServiceError::UserNotFound
-        | ReservationServiceError::MedicationNotFound
-        | ReservationServiceError::ReservationNotFound => (StatusCode::NOT_FOUND, e.to_string()),
-        ReservationServiceError::NotEnoughAvailable => (StatusCode::NOT_FOUND, e.to_string()),
-        ReservationServiceError::Anyhow(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
-    };
+static IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+pub struct IdempotencyKey(String);
+
+impl Header for IdempotencyKey {
+    fn name() -> &'static HeaderName {
+        &IDEMPOTENCY_KEY
+    }
+
+    fn decode<'i, I>(values: &mut I) -> Result<Self, headers::Error>
+    where
+        I: Iterator<Item = &'i HeaderValue>,
+    {
+        let value = values
+            .next()
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty() && v.len() <= MAX_IDEMPOTENCY_KEY_LENGTH)
+            .ok_or_else(headers::Error::invalid)?;
+
+        Ok(Self(value.to_owned()))
+    }
+
+    fn encode<E: Extend<HeaderValue>>(&self, values: &mut E) {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            values.extend(std::iter::once(value));
+        }
+    }
+}
+
+impl ApiError for ReservationServiceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ReservationServiceError::UserNotFound
+            | ReservationServiceError::MedicationNotFound
+            | ReservationServiceError::ReservationNotFound
+            | ReservationServiceError::NotEnoughAvailable => StatusCode::NOT_FOUND,
+            ReservationServiceError::EmailNotVerified | ReservationServiceError::Banned(_) => {
+                StatusCode::FORBIDDEN
+            }
+            ReservationServiceError::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
+            ReservationServiceError::LimitExceeded(_) | ReservationServiceError::NotCancellable => {
+                StatusCode::CONFLICT
+            }
+            ReservationServiceError::NoShowCooldown(_) => StatusCode::TOO_MANY_REQUESTS,
+            ReservationServiceError::InvalidSortColumn(_)
+            | ReservationServiceError::InvalidCursor => StatusCode::BAD_REQUEST,
+            ReservationServiceError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ReservationServiceError::UserNotFound => "userNotFound",
+            ReservationServiceError::EmailNotVerified => "emailNotVerified",
+            ReservationServiceError::MedicationNotFound => "medicationNotFound",
+            ReservationServiceError::ReservationNotFound => "reservationNotFound",
+            ReservationServiceError::NotEnoughAvailable => "notEnoughAvailable",
+            ReservationServiceError::IdempotencyKeyReused => "idempotencyKeyReused",
+            ReservationServiceError::LimitExceeded(_) => "limitExceeded",
+            ReservationServiceError::NoShowCooldown(_) => "noShowCooldown",
+            ReservationServiceError::Banned(_) => "banned",
+            ReservationServiceError::NotCancellable => "notCancellable",
+            ReservationServiceError::InvalidSortColumn(_) => "invalidSort",
+            ReservationServiceError::InvalidCursor => "invalidCursor",
+            ReservationServiceError::Anyhow(_) => "internalError",
+        }
+    }
 
-    (status, Json(RestError { message })).into_response()
+    fn headers(&self) -> HeaderMap {
+        match self {
+            ReservationServiceError::NoShowCooldown(remaining) => {
+                retry_after(remaining.whole_seconds())
+            }
+            _ => HeaderMap::new(),
+        }
+    }
 }
 
 pub async fn get(
     State(ref state): State<AppState>,
-    auth: Auth,
+    auth: UserAuth,
+    ValidatedQuery(filter): ValidatedQuery<ReservationFilter>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
 ) -> Result<Json<Page<MedicationReservation>>, ErrorResponse> {
     Ok(Json(
         state
             .reservation_service
-            .get(auth.user_id)
+            .get(auth.user_id, filter, Some(pageable))
             .await
-            .map_err(handle_reservation_service_error)?
+            .map_err(problem)?
             .map(MedicationReservation::from)
             .into(),
     ))
 }
 
-pub async fn post(
+pub async fn get_apothecary(
     State(ref state): State<AppState>,
-    auth: Auth,
-    Json(request): Json<MedicationReservationRequest>,
-) -> Result<Json<MedicationReservation>, ErrorResponse> {
+    auth: UserAuth,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<ApothecaryReservation>>, ErrorResponse> {
     Ok(Json(
         state
             .reservation_service
-            .reserve(auth.user_id, request)
+            .get_for_staff(auth.user_id, Some(pageable))
             .await
-            .map_err(handle_reservation_service_error)?
+            .map_err(problem)?
             .into(),
     ))
 }
 
+pub async fn post(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    idempotency_key: Result<TypedHeader<IdempotencyKey>, TypedHeaderRejection>,
+    ValidatedJson(request): ValidatedJson<MedicationReservationRequest>,
+) -> Result<Response, ErrorResponse> {
+    let idempotency_key = match idempotency_key {
+        Ok(TypedHeader(IdempotencyKey(key))) => Some(key),
+        Err(e) if matches!(e.reason(), TypedHeaderRejectionReason::Missing) => None,
+        Err(_) => {
+            return Err(problem(BadRequest {
+                code: "invalidIdempotencyKey",
+                message: "Invalid Idempotency-Key header",
+            })
+            .into())
+        }
+    };
+
+    let outcome = state
+        .reservation_service
+        .reserve(auth.user_id, idempotency_key, request)
+        .await
+        .map_err(problem)?;
+
+    Ok(match outcome {
+        ReserveOutcome::Created(reservation) => {
+            Json(MedicationReservation::from(*reservation)).into_response()
+        }
+        ReserveOutcome::Replayed { status, body } => (
+            StatusCode::from_u16(status).unwrap_or(StatusCode::OK),
+            [(header::CONTENT_TYPE, "application/json")],
+            body,
+        )
+            .into_response(),
+    })
+}
+
 pub async fn delete(
     State(ref state): State<AppState>,
-    auth: Auth,
+    auth: UserAuth,
     Path(id): Path<Uuid>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
     state
         .reservation_service
-        .delete(auth.user_id, id)
+        .cancel(
+            auth.user_id,
+            id,
+            MedicationReservationCancellationRequest::default(),
+        )
         .await
-        .map_err(|e| handle_reservation_service_error(e))?;
+        .map_err(problem)?;
 
     Ok((StatusCode::NO_CONTENT, ()))
 }
+
+pub async fn cancel(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path(id): Path<Uuid>,
+    ValidatedJson(request): ValidatedJson<MedicationReservationCancellationRequest>,
+) -> Result<Json<MedicationReservation>, ErrorResponse> {
+    Ok(Json(
+        state
+            .reservation_service
+            .cancel(auth.user_id, id, request)
+            .await
+            .map_err(problem)?
+            .into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{header, Method, StatusCode};
+    use sea_orm::{
+        prelude::{Expr, TimeDateTime, TimeDateTimeWithTimeZone},
+        ColumnTrait, EntityTrait, QueryFilter,
+    };
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::test_support::{json_body, respond, router, send, state, verified_customer_token};
+
+    #[tokio::test]
+    async fn no_shows_are_answered_with_retry_after() {
+        let state = state().await;
+        let router = router(&state);
+
+        let token = verified_customer_token(&state, "jane@example.com").await;
+
+        let stock = entity::apothecary_medication::Entity::find()
+            .filter(entity::apothecary_medication::Column::MedicationQuantity.eq(10))
+            .one(&state.conn)
+            .await
+            .unwrap()
+            .unwrap();
+        let request = json!({
+            "apothecaryId": stock.apothecary_id,
+            "medicationId": stock.medication_id,
+            "quantity": { "type": "package", "quantity": 1, "price": "10.99" },
+        });
+
+        let (status, body) = send(
+            &router,
+            Method::POST,
+            "/api/v1/reservations",
+            Some(&token),
+            Some(request.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK, "{body}");
+
+        let reservation_id: Uuid = body["id"].as_str().unwrap().parse().unwrap();
+        let now = TimeDateTimeWithTimeZone::now_utc();
+        entity::reservation::Entity::update_many()
+            .col_expr(
+                entity::reservation::Column::EndDateTime,
+                Expr::value(TimeDateTime::new(now.date(), now.time())),
+            )
+            .filter(entity::reservation::Column::Id.eq(reservation_id))
+            .exec(&state.conn)
+            .await
+            .unwrap();
+
+        let response = respond(
+            &router,
+            Method::POST,
+            "/api/v1/reservations",
+            Some(&token),
+            Some(request),
+        )
+        .await;
+        let status = response.status();
+        let retry_after = response.headers().get(header::RETRY_AFTER).cloned();
+        let body = json_body(response).await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{body}");
+        assert_eq!(body["code"], "noShowCooldown");
+
+        let retry_after: i64 = retry_after.unwrap().to_str().unwrap().parse().unwrap();
+        assert!((24 * 60 * 60 - 60..=24 * 60 * 60).contains(&retry_after));
+    }
+}
@@ -0,0 +1,167 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{ErrorResponse, IntoResponse},
+    Json,
+};
+use dto::{
+    page::{Page, Pageable},
+    staff::{
+        AcceptInvitationRequest, Invitation, InvitationRequest, Membership, StaffMember,
+        StaffRoleUpdate,
+    },
+};
+use service::staff::StaffServiceError;
+use uuid::Uuid;
+
+use crate::{
+    appstate::AppState,
+    auth::UserAuth,
+    error::{problem, ApiError},
+    extract::{ValidatedJson, ValidatedQuery},
+};
+
+impl ApiError for StaffServiceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            StaffServiceError::ApothecaryNotFound
+            | StaffServiceError::MemberNotFound
+            | StaffServiceError::InvitationNotFound => StatusCode::NOT_FOUND,
+            StaffServiceError::Forbidden => StatusCode::FORBIDDEN,
+            StaffServiceError::AlreadyMember | StaffServiceError::LastOwner => StatusCode::CONFLICT,
+            StaffServiceError::InvalidInvitation => StatusCode::BAD_REQUEST,
+            StaffServiceError::Page(e) => e.status(),
+            StaffServiceError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            StaffServiceError::ApothecaryNotFound => "apothecaryNotFound",
+            StaffServiceError::Forbidden => "forbidden",
+            StaffServiceError::MemberNotFound => "memberNotFound",
+            StaffServiceError::AlreadyMember => "alreadyMember",
+            StaffServiceError::InvitationNotFound => "invitationNotFound",
+            StaffServiceError::InvalidInvitation => "invalidInvitation",
+            StaffServiceError::LastOwner => "lastOwner",
+            StaffServiceError::Page(e) => e.code(),
+            StaffServiceError::Anyhow(_) => "internalError",
+        }
+    }
+}
+
+pub async fn get_memberships(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<Membership>>, ErrorResponse> {
+    let memberships = state
+        .staff_service
+        .get_memberships(auth.user_id, Some(pageable))
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(memberships.into()))
+}
+
+pub async fn get_members(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path(apothecary_id): Path<Uuid>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<StaffMember>>, ErrorResponse> {
+    let members = state
+        .staff_service
+        .get_members(auth.user_id, apothecary_id, Some(pageable))
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(members.into()))
+}
+
+pub async fn update_role(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path((apothecary_id, member_id)): Path<(Uuid, Uuid)>,
+    ValidatedJson(update): ValidatedJson<StaffRoleUpdate>,
+) -> Result<Json<StaffMember>, ErrorResponse> {
+    let member = state
+        .staff_service
+        .update_role(auth.user_id, apothecary_id, member_id, update.role.into())
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(member))
+}
+
+pub async fn remove_member(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path((apothecary_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .staff_service
+        .remove_member(auth.user_id, apothecary_id, member_id)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
+
+pub async fn get_invitations(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path(apothecary_id): Path<Uuid>,
+    ValidatedQuery(pageable): ValidatedQuery<Pageable>,
+) -> Result<Json<Page<Invitation>>, ErrorResponse> {
+    let invitations = state
+        .staff_service
+        .get_invitations(auth.user_id, apothecary_id, Some(pageable))
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(invitations.into()))
+}
+
+pub async fn invite(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path(apothecary_id): Path<Uuid>,
+    ValidatedJson(request): ValidatedJson<InvitationRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let invitation = state
+        .staff_service
+        .invite(auth.user_id, apothecary_id, request)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::CREATED, Json(Invitation::from(invitation))))
+}
+
+pub async fn revoke_invitation(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    Path((apothecary_id, invitation_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .staff_service
+        .revoke_invitation(auth.user_id, apothecary_id, invitation_id)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
+
+pub async fn accept(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedJson(request): ValidatedJson<AcceptInvitationRequest>,
+) -> Result<Json<Membership>, ErrorResponse> {
+    let membership = state
+        .staff_service
+        .accept(auth.user_id, &request.token)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(membership))
+}
@@ -0,0 +1,164 @@
+use std::net::SocketAddr;
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, Method, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use sea_orm::{
+    prelude::{Expr, TimeDateTime, TimeDateTimeWithTimeZone},
+    ColumnTrait, EntityTrait, QueryFilter,
+};
+use serde_json::{json, Value};
+use settings::Settings;
+use tower::ServiceExt;
+
+use crate::{appstate::AppState, create_router, migrate};
+
+/// Settings for an in-memory database, with mails kept in memory as well.
+pub(crate) fn settings() -> Settings {
+    serde_json::from_value(json!({
+        "database": { "url": "sqlite::memory:" },
+        "endpoint": { "host": "127.0.0.1", "port": 0 },
+        "jwt": { "secret": "test-secret" },
+        "mail": {
+            "from": "PharmaTracker <noreply@example.com>",
+            "link_secret": "test-link-secret",
+            "verification_url": "http://localhost/verify-email",
+            "password_reset_url": "http://localhost/reset-password",
+            "invitation_url": "http://localhost/invitation",
+        },
+    }))
+    .expect("test settings are valid")
+}
+
+/// Migrated and seeded application state, with the seed accounts `admin@email.com` and
+/// `john@apo.com`.
+pub(crate) async fn state_with(settings: Settings) -> AppState {
+    let db = entity::create_database_connection(&settings.database.url)
+        .await
+        .unwrap();
+    let state = AppState::new(settings, db).unwrap();
+
+    migrate(&state.conn, false).await.unwrap();
+
+    state
+}
+
+pub(crate) async fn state() -> AppState {
+    state_with(settings()).await
+}
+
+pub(crate) fn router(state: &AppState) -> Router {
+    create_router(state.clone())
+}
+
+/// Access token for an existing account.
+pub(crate) async fn token_for(state: &AppState, email: &str) -> String {
+    let user = entity::user::Entity::find()
+        .filter(entity::user::Column::Email.eq(email))
+        .one(&state.conn)
+        .await
+        .unwrap()
+        .unwrap_or_else(|| panic!("no account for {email}"));
+
+    state
+        .jwt_service
+        .create_session(&user)
+        .await
+        .ok()
+        .and_then(|session| session.access_token.token)
+        .unwrap()
+}
+
+/// Registers a customer and returns an access token for them.
+pub(crate) async fn customer_token(state: &AppState, email: &str) -> String {
+    state
+        .user_service
+        .register(dto::user::UserRegistration {
+            name: "Customer".to_owned(),
+            email: email.to_owned(),
+            password: "Correct-Horse-Battery-9".to_owned(),
+        })
+        .await
+        .ok()
+        .unwrap();
+
+    token_for(state, email).await
+}
+
+/// Registers a customer, verifies their address and returns an access token for them.
+pub(crate) async fn verified_customer_token(state: &AppState, email: &str) -> String {
+    let token = customer_token(state, email).await;
+    let now = TimeDateTimeWithTimeZone::now_utc();
+
+    entity::user::Entity::update_many()
+        .col_expr(
+            entity::user::Column::EmailVerifiedAt,
+            Expr::value(TimeDateTime::new(now.date(), now.time())),
+        )
+        .filter(entity::user::Column::Email.eq(email))
+        .exec(&state.conn)
+        .await
+        .unwrap();
+
+    token
+}
+
+/// Sends a request through the router with an optional bearer token and JSON body.
+pub(crate) async fn respond(
+    router: &Router,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> Response {
+    let mut request = Request::builder().method(method).uri(uri);
+
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+
+    let mut request = match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    }
+    .unwrap();
+
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 40000))));
+
+    router.clone().oneshot(request).await.unwrap()
+}
+
+/// Reads a response body as JSON, or `Null` if it is empty.
+pub(crate) async fn json_body(response: Response) -> Value {
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&body).unwrap()
+    }
+}
+
+/// Sends a request through the router and returns the status and the JSON body, or `Null` if
+/// the body is empty.
+pub(crate) async fn send(
+    router: &Router,
+    method: Method,
+    uri: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let response = respond(router, method, uri, token, body).await;
+
+    (response.status(), json_body(response).await)
+}
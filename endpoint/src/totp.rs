@@ -0,0 +1,104 @@
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{ErrorResponse, IntoResponse},
+    Json,
+};
+use dto::totp::{DisableTotpRequest, RecoveryCodes, TotpCodeRequest, TotpEnrollment};
+use service::totp::TotpServiceError;
+
+use crate::{
+    appstate::AppState,
+    auth::UserAuth,
+    error::{problem, retry_after, ApiError},
+    extract::ValidatedJson,
+};
+
+impl ApiError for TotpServiceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            TotpServiceError::UserNotFound => StatusCode::NOT_FOUND,
+            TotpServiceError::AlreadyEnabled | TotpServiceError::NotEnabled => StatusCode::CONFLICT,
+            TotpServiceError::InvalidCode => StatusCode::BAD_REQUEST,
+            TotpServiceError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            TotpServiceError::Required => StatusCode::FORBIDDEN,
+            TotpServiceError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            TotpServiceError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            TotpServiceError::UserNotFound => "userNotFound",
+            TotpServiceError::AlreadyEnabled => "totpAlreadyEnabled",
+            TotpServiceError::NotEnabled => "totpNotEnabled",
+            TotpServiceError::InvalidCode => "invalidCode",
+            TotpServiceError::InvalidCredentials => "invalidCredentials",
+            TotpServiceError::Required => "totpRequired",
+            TotpServiceError::TooManyAttempts(_) => "tooManyAttempts",
+            TotpServiceError::Anyhow(_) => "internalError",
+        }
+    }
+
+    fn headers(&self) -> HeaderMap {
+        match self {
+            TotpServiceError::TooManyAttempts(wait) => retry_after(wait.whole_seconds()),
+            _ => HeaderMap::new(),
+        }
+    }
+}
+
+pub async fn enroll(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+) -> Result<Json<TotpEnrollment>, ErrorResponse> {
+    let enrollment = state
+        .totp_service
+        .enroll(auth.user_id)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(enrollment))
+}
+
+pub async fn confirm(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedJson(request): ValidatedJson<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodes>, ErrorResponse> {
+    let recovery_codes = state
+        .totp_service
+        .confirm(auth.user_id, &request.code)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+pub async fn disable(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedJson(request): ValidatedJson<DisableTotpRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .totp_service
+        .disable(auth.user_id, request)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
+
+pub async fn regenerate_recovery_codes(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedJson(request): ValidatedJson<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodes>, ErrorResponse> {
+    let recovery_codes = state
+        .totp_service
+        .regenerate_recovery_codes(auth.user_id, &request.code)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
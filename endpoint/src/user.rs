@@ -1,92 +1,340 @@
+use std::net::SocketAddr;
+
 use axum::{
-    body::Body,
-    extract::State,
-    http::StatusCode,
-    response::{ErrorResponse, IntoResponse, Response},
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, StatusCode},
+    response::{ErrorResponse, IntoResponse},
     Json,
 };
-use service::user::UserServiceError;
+use service::{
+    jwt::TokenError,
+    user::{LoginOutcome, UserServiceError},
+};
 use tracing::debug;
 
-use crate::{appstate::AppState, auth::Auth};
+use crate::{
+    appstate::AppState,
+    auth::UserAuth,
+    error::{problem, retry_after, ApiError},
+    extract::ValidatedJson,
+};
 use dto::{
-    error::RestError,
-    user::{User, UserLogin, UserRegistration},
+    error::FieldError,
+    totp::{SecondFactorAuthTokens, SecondFactorLogin},
+    user::{
+        AuthTokens, ChangePasswordRequest, DeleteAccountRequest, EmailVerificationRequest,
+        ForgotPasswordRequest, LoginResponse, RefreshTokenRequest, ResendVerificationRequest,
+        ResetPasswordRequest, User, UserLogin, UserRegistration, UserUpdate,
+    },
 };
 
-fn handle_user_service_error(error: UserServiceError) -> Response {
-    let (status_code, message) = match error {
-        UserServiceError::InvalidCredentials => {
-            (axum::http::StatusCode::UNAUTHORIZED, error.to_string())
+impl ApiError for UserServiceError {
+    fn status(&self) -> StatusCode {
+        match self {
+            UserServiceError::InvalidCredentials
+            | UserServiceError::InvalidLoginChallenge
+            | UserServiceError::ReauthenticationRequired => StatusCode::UNAUTHORIZED,
+            UserServiceError::TooManyAttempts(_) => StatusCode::TOO_MANY_REQUESTS,
+            UserServiceError::InvalidPassword(_, _) => StatusCode::UNPROCESSABLE_ENTITY,
+            UserServiceError::UserAlreadyExists => StatusCode::CONFLICT,
+            UserServiceError::UserNotFound => StatusCode::NOT_FOUND,
+            UserServiceError::EmailNotVerified | UserServiceError::AccountDisabled => {
+                StatusCode::FORBIDDEN
+            }
+            UserServiceError::InvalidVerificationToken | UserServiceError::InvalidResetToken => {
+                StatusCode::BAD_REQUEST
+            }
+            UserServiceError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
-        UserServiceError::UserAlreadyExists => {
-            (axum::http::StatusCode::CONFLICT, error.to_string())
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            UserServiceError::InvalidCredentials => "invalidCredentials",
+            UserServiceError::TooManyAttempts(_) => "tooManyAttempts",
+            UserServiceError::UserAlreadyExists => "userAlreadyExists",
+            UserServiceError::UserNotFound => "userNotFound",
+            UserServiceError::EmailNotVerified => "emailNotVerified",
+            UserServiceError::InvalidVerificationToken => "invalidVerificationToken",
+            UserServiceError::InvalidResetToken => "invalidResetToken",
+            UserServiceError::InvalidLoginChallenge => "invalidLoginChallenge",
+            UserServiceError::ReauthenticationRequired => "reauthenticationRequired",
+            UserServiceError::AccountDisabled => "accountDisabled",
+            UserServiceError::InvalidPassword(_, _) => "invalidPassword",
+            UserServiceError::Anyhow(_) => "internalError",
         }
-        UserServiceError::UserNotFound => (axum::http::StatusCode::NOT_FOUND, error.to_string()),
-        UserServiceError::Anyhow(e) => {
-            tracing::error!("Error: {}", e);
-            (axum::http::StatusCode::INTERNAL_SERVER_ERROR, String::new())
+    }
+
+    fn field_errors(&self) -> Vec<FieldError> {
+        let UserServiceError::InvalidPassword(field, violations) = self else {
+            return Vec::new();
+        };
+
+        violations
+            .iter()
+            .map(|v| FieldError {
+                field: (*field).to_owned(),
+                code: v.code().to_owned(),
+                message: v.to_string(),
+            })
+            .collect()
+    }
+
+    fn headers(&self) -> HeaderMap {
+        match self {
+            UserServiceError::TooManyAttempts(wait) => retry_after(wait.whole_seconds()),
+            _ => HeaderMap::new(),
         }
-    };
+    }
+}
+
+impl ApiError for TokenError {
+    fn status(&self) -> StatusCode {
+        match self {
+            TokenError::InvalidRefreshToken | TokenError::RefreshTokenReused => {
+                StatusCode::UNAUTHORIZED
+            }
+            TokenError::Jwt(_)
+            | TokenError::Uuid(_)
+            | TokenError::UnknownKey
+            | TokenError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 
-    (status_code, Json(RestError { message })).into_response()
+    fn code(&self) -> &'static str {
+        match self {
+            TokenError::InvalidRefreshToken => "invalidRefreshToken",
+            TokenError::RefreshTokenReused => "refreshTokenReused",
+            TokenError::Jwt(_)
+            | TokenError::Uuid(_)
+            | TokenError::UnknownKey
+            | TokenError::Db(_) => "internalError",
+        }
+    }
 }
 
 pub async fn login(
     State(ref state): State<AppState>,
-    Json(user_login): Json<UserLogin>,
-) -> Result<impl IntoResponse, ErrorResponse> {
-    debug!("Login: {:?}", user_login);
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    ValidatedJson(user_login): ValidatedJson<UserLogin>,
+) -> Result<Json<LoginResponse>, ErrorResponse> {
+    debug!("Login: {:?}", user_login.email);
 
-    let user = state
+    let outcome = state
+        .user_service
+        .login(user_login, remote_addr.ip())
+        .await
+        .map_err(problem)?;
+
+    login_response(state, outcome).await
+}
+
+/// Starts a session, unless the login still needs a second factor.
+pub(crate) async fn login_response(
+    state: &AppState,
+    outcome: LoginOutcome,
+) -> Result<Json<LoginResponse>, ErrorResponse> {
+    let user = match outcome {
+        LoginOutcome::Authenticated(user) => user,
+        LoginOutcome::SecondFactorRequired(challenge) => {
+            return Ok(Json(LoginResponse::SecondFactorRequired(challenge)));
+        }
+    };
+
+    let session = state
+        .jwt_service
+        .create_session(&user)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(LoginResponse::Authenticated(session.into())))
+}
+
+pub async fn login_second_factor(
+    State(ref state): State<AppState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    ValidatedJson(request): ValidatedJson<SecondFactorLogin>,
+) -> Result<Json<SecondFactorAuthTokens>, ErrorResponse> {
+    let (user, recovery_codes) = state
         .user_service
-        .login(user_login)
+        .login_second_factor(request, remote_addr.ip())
+        .await
+        .map_err(problem)?;
+
+    let session = state
+        .jwt_service
+        .create_session(&user)
         .await
-        .map_err(handle_user_service_error)?;
+        .map_err(problem)?;
 
-    Ok((
-        StatusCode::OK,
-        format!(
-            "Bearer {}",
-            state.jwt_service.create_token(user).map_err(|e| {
-                tracing::error!("Error: {}", e);
-                (axum::http::StatusCode::INTERNAL_SERVER_ERROR, Body::empty())
-            })?
-        ),
-    ))
+    Ok(Json(SecondFactorAuthTokens {
+        tokens: session.into(),
+        recovery_codes,
+    }))
 }
 
 pub async fn register(
     State(ref state): State<AppState>,
-    Json(user_register): Json<UserRegistration>,
+    ValidatedJson(user_register): ValidatedJson<UserRegistration>,
 ) -> Result<impl IntoResponse, ErrorResponse> {
     let user = state
         .user_service
         .register(user_register)
         .await
-        .map_err(handle_user_service_error)?;
+        .map_err(problem)?;
+
+    Ok((StatusCode::CREATED, Json(User::from(user))))
+}
+
+pub async fn verify_email(
+    State(ref state): State<AppState>,
+    ValidatedJson(request): ValidatedJson<EmailVerificationRequest>,
+) -> Result<Json<User>, ErrorResponse> {
+    let user = state
+        .user_service
+        .verify_email(&request.token)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(user.into()))
+}
+
+pub async fn resend_verification(
+    State(ref state): State<AppState>,
+    ValidatedJson(request): ValidatedJson<ResendVerificationRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .user_service
+        .resend_verification(request.email)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::ACCEPTED, ()))
+}
+
+pub async fn forgot_password(
+    State(ref state): State<AppState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    ValidatedJson(request): ValidatedJson<ForgotPasswordRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .user_service
+        .forgot_password(request.email, remote_addr.ip())
+        .await
+        .map_err(problem)?;
 
-    Ok((
-        StatusCode::OK,
-        format!(
-            "Bearer {}",
-            state.jwt_service.create_token(user).map_err(|e| {
-                tracing::error!("Error: {}", e);
-                (axum::http::StatusCode::INTERNAL_SERVER_ERROR, Body::empty())
-            })?
-        ),
-    ))
+    Ok((StatusCode::ACCEPTED, ()))
+}
+
+pub async fn reset_password(
+    State(ref state): State<AppState>,
+    ValidatedJson(request): ValidatedJson<ResetPasswordRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    let user = state
+        .user_service
+        .reset_password(request)
+        .await
+        .map_err(problem)?;
+
+    state
+        .jwt_service
+        .revoke_all(user.id)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
+
+pub async fn refresh(
+    State(ref state): State<AppState>,
+    ValidatedJson(request): ValidatedJson<RefreshTokenRequest>,
+) -> Result<Json<AuthTokens>, ErrorResponse> {
+    let session = state
+        .jwt_service
+        .refresh(&request.refresh_token)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(session.into()))
+}
+
+pub async fn logout(
+    State(ref state): State<AppState>,
+    ValidatedJson(request): ValidatedJson<RefreshTokenRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .jwt_service
+        .logout(&request.refresh_token)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
 }
 
 pub async fn me(
     State(ref state): State<AppState>,
-    auth: Auth,
+    auth: UserAuth,
 ) -> Result<Json<User>, ErrorResponse> {
     let user = state
         .user_service
         .get_by_id(auth.user_id)
         .await
-        .map_err(handle_user_service_error)?;
+        .map_err(problem)?;
+
+    Ok(Json(user.into()))
+}
+
+pub async fn update_me(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedJson(update): ValidatedJson<UserUpdate>,
+) -> Result<Json<User>, ErrorResponse> {
+    let user = state
+        .user_service
+        .update_profile(auth.user_id, update)
+        .await
+        .map_err(problem)?;
 
     Ok(Json(user.into()))
 }
+
+/// Changes the password and replaces all sessions with a new one.
+pub async fn change_password(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedJson(request): ValidatedJson<ChangePasswordRequest>,
+) -> Result<Json<AuthTokens>, ErrorResponse> {
+    let user = state
+        .user_service
+        .change_password(auth.user_id, request)
+        .await
+        .map_err(problem)?;
+
+    state
+        .jwt_service
+        .revoke_all(user.id)
+        .await
+        .map_err(problem)?;
+
+    let session = state
+        .jwt_service
+        .create_session(&user)
+        .await
+        .map_err(problem)?;
+
+    Ok(Json(session.into()))
+}
+
+pub async fn delete_me(
+    State(ref state): State<AppState>,
+    auth: UserAuth,
+    ValidatedJson(request): ValidatedJson<DeleteAccountRequest>,
+) -> Result<impl IntoResponse, ErrorResponse> {
+    state
+        .user_service
+        .delete_account(auth.user_id, auth.auth_time, request)
+        .await
+        .map_err(problem)?;
+
+    Ok((StatusCode::NO_CONTENT, ()))
+}
@@ -0,0 +1,92 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(Some(1))",
+    enum_name = "api_key_scope"
+)]
+pub enum ApiKeyScope {
+    #[sea_orm(string_value = "r")]
+    InventoryRead,
+    /// Includes [`ApiKeyScope::InventoryRead`].
+    #[sea_orm(string_value = "w")]
+    InventoryWrite,
+}
+
+impl ApiKeyScope {
+    pub fn allows(self, required: ApiKeyScope) -> bool {
+        self == required || self == ApiKeyScope::InventoryWrite
+    }
+}
+
+impl From<ApiKeyScope> for dto::api_key::ApiKeyScope {
+    fn from(scope: ApiKeyScope) -> Self {
+        match scope {
+            ApiKeyScope::InventoryRead => Self::InventoryRead,
+            ApiKeyScope::InventoryWrite => Self::InventoryWrite,
+        }
+    }
+}
+
+impl From<dto::api_key::ApiKeyScope> for ApiKeyScope {
+    fn from(scope: dto::api_key::ApiKeyScope) -> Self {
+        match scope {
+            dto::api_key::ApiKeyScope::InventoryRead => Self::InventoryRead,
+            dto::api_key::ApiKeyScope::InventoryWrite => Self::InventoryWrite,
+        }
+    }
+}
+
+/// A key an apothecary's own systems use instead of a user login. Only the hash of the key is
+/// stored; `prefix` is its beginning, so owners can tell keys apart.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "api_key")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub apothecary_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    #[sea_orm(unique)]
+    pub key_hash: String,
+    pub scope: ApiKeyScope,
+    pub created_by: Option<Uuid>,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: Option<PrimitiveDateTime>,
+    pub last_used_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::apothecary::Entity",
+        from = "Column::ApothecaryId",
+        to = "super::apothecary::Column::Id"
+    )]
+    Apothecary,
+}
+
+impl Related<super::apothecary::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Apothecary.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for dto::api_key::ApiKey {
+    fn from(key: Model) -> Self {
+        Self {
+            id: key.id,
+            apothecary_id: key.apothecary_id,
+            name: key.name,
+            prefix: key.prefix,
+            scope: key.scope.into(),
+            created_at: key.created_at,
+            expires_at: key.expires_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
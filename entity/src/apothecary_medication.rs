@@ -33,8 +33,11 @@ pub struct Model {
     #[sea_orm(primary_key)]
     pub medication_id: Uuid,
     pub medication_quantity_type: QuantityType,
+    /// Packages left for new reservations, `None` for stock of an unknown quantity. Negative
+    /// if active reservations hold more than the apothecary reported to have.
     pub medication_quantity: Option<i64>,
-    pub medication_price: Decimal,
+    /// `None` for stock of an unknown quantity.
+    pub medication_price: Option<Decimal>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -54,6 +57,12 @@ pub enum Relation {
     Apothecary,
 }
 
+impl Related<super::medication::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Medication.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
 
 impl From<Model> for MedicationQuantity {
@@ -62,8 +71,12 @@ impl From<Model> for MedicationQuantity {
             QuantityType::Package => Self::Package(MedicationQuantityPackage {
                 quantity: medication
                     .medication_quantity
-                    .expect("Package quantity is missing") as _,
-                price: medication.medication_price,
+                    .expect("Package quantity is missing")
+                    .try_into()
+                    .unwrap_or(0),
+                price: medication
+                    .medication_price
+                    .expect("Package price is missing"),
             }),
             QuantityType::Unknown => Self::Unknown(MedicationQuantityUnknown),
         }
@@ -1,5 +1,21 @@
 use sea_orm::entity::prelude::*;
 
+/// What a member may do in an apothecary. Owners manage the staff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(Some(1))",
+    enum_name = "staff_role"
+)]
+pub enum StaffRole {
+    #[sea_orm(string_value = "o")]
+    Owner,
+    #[sea_orm(string_value = "p")]
+    Pharmacist,
+    #[sea_orm(string_value = "a")]
+    Assistant,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
 #[sea_orm(table_name = "apothecary_user")]
 pub struct Model {
@@ -7,6 +23,9 @@ pub struct Model {
     pub id: Uuid,
     pub apothecary_id: Uuid,
     pub user_id: Uuid,
+    /// Members from before roles existed run their apothecary, they become owners.
+    #[sea_orm(default_value = "o")]
+    pub role: StaffRole,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -26,4 +45,36 @@ pub enum Relation {
     Apothecary,
 }
 
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl Related<super::apothecary::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Apothecary.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
+
+impl From<StaffRole> for dto::staff::StaffRole {
+    fn from(role: StaffRole) -> Self {
+        match role {
+            StaffRole::Owner => Self::Owner,
+            StaffRole::Pharmacist => Self::Pharmacist,
+            StaffRole::Assistant => Self::Assistant,
+        }
+    }
+}
+
+impl From<dto::staff::StaffRole> for StaffRole {
+    fn from(role: dto::staff::StaffRole) -> Self {
+        match role {
+            dto::staff::StaffRole::Owner => Self::Owner,
+            dto::staff::StaffRole::Pharmacist => Self::Pharmacist,
+            dto::staff::StaffRole::Assistant => Self::Assistant,
+        }
+    }
+}
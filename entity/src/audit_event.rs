@@ -0,0 +1,83 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(Some(1))",
+    enum_name = "audit_event_kind"
+)]
+pub enum AuditEventKind {
+    #[sea_orm(string_value = "a")]
+    AccountLocked,
+    #[sea_orm(string_value = "i")]
+    IpLocked,
+    #[sea_orm(string_value = "t")]
+    TotpEnabled,
+    #[sea_orm(string_value = "d")]
+    TotpDisabled,
+    #[sea_orm(string_value = "r")]
+    RecoveryCodeUsed,
+    #[sea_orm(string_value = "u")]
+    UserTypeChanged,
+    #[sea_orm(string_value = "x")]
+    AccountDisabled,
+    #[sea_orm(string_value = "e")]
+    AccountEnabled,
+    #[sea_orm(string_value = "p")]
+    PasswordResetForced,
+    #[sea_orm(string_value = "l")]
+    ApothecaryLinked,
+    #[sea_orm(string_value = "k")]
+    ApothecaryUnlinked,
+}
+
+/// A security relevant event. `user_id` is kept without a foreign key so the log outlives
+/// deleted accounts, which are detached from it instead.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "audit_event")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: Option<Uuid>,
+    /// The admin who acted on the account, unset for events caused by the user.
+    pub actor_id: Option<Uuid>,
+    pub kind: AuditEventKind,
+    pub ip: Option<String>,
+    pub detail: String,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for dto::audit::AuditEvent {
+    fn from(event: Model) -> Self {
+        Self {
+            id: event.id,
+            kind: match event.kind {
+                AuditEventKind::AccountLocked => dto::audit::AuditEventKind::AccountLocked,
+                AuditEventKind::IpLocked => dto::audit::AuditEventKind::IpLocked,
+                AuditEventKind::TotpEnabled => dto::audit::AuditEventKind::TotpEnabled,
+                AuditEventKind::TotpDisabled => dto::audit::AuditEventKind::TotpDisabled,
+                AuditEventKind::RecoveryCodeUsed => dto::audit::AuditEventKind::RecoveryCodeUsed,
+                AuditEventKind::UserTypeChanged => dto::audit::AuditEventKind::UserTypeChanged,
+                AuditEventKind::AccountDisabled => dto::audit::AuditEventKind::AccountDisabled,
+                AuditEventKind::AccountEnabled => dto::audit::AuditEventKind::AccountEnabled,
+                AuditEventKind::PasswordResetForced => {
+                    dto::audit::AuditEventKind::PasswordResetForced
+                }
+                AuditEventKind::ApothecaryLinked => dto::audit::AuditEventKind::ApothecaryLinked,
+                AuditEventKind::ApothecaryUnlinked => {
+                    dto::audit::AuditEventKind::ApothecaryUnlinked
+                }
+            },
+            actor_id: event.actor_id,
+            ip: event.ip,
+            detail: event.detail,
+            created_at: event.created_at,
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "idempotency_key")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: Uuid,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub key: String,
+    pub request_hash: String,
+    pub reservation_id: Uuid,
+    /// HTTP status and JSON body of the first response, returned again on retries.
+    pub response_status: i32,
+    pub response_body: String,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+
+    #[sea_orm(
+        belongs_to = "super::reservation::Entity",
+        from = "Column::ReservationId",
+        to = "super::reservation::Column::Id"
+    )]
+    Reservation,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
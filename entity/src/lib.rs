@@ -1,11 +1,24 @@
+pub mod api_key;
 pub mod apothecary;
 pub mod apothecary_medication;
 pub mod apothecary_schedule;
 pub mod apothecary_user;
+pub mod audit_event;
+pub mod idempotency_key;
+pub mod login_throttle;
 pub mod medication;
+pub mod no_show;
+pub mod notification;
+pub mod oidc_authorization;
+pub mod password_reset_token;
+pub mod refresh_token;
 pub mod reservation;
 pub mod schedule;
+pub mod staff_invitation;
+pub mod totp_recovery_code;
 pub mod user;
+pub mod user_identity;
+pub mod user_totp;
 
 pub use sea_orm::DatabaseConnection;
 
@@ -0,0 +1,47 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(Some(1))",
+    enum_name = "login_throttle_scope"
+)]
+pub enum ThrottleScope {
+    /// Keyed by the normalized email address, whether or not an account exists for it.
+    #[sea_orm(string_value = "a")]
+    Account,
+    #[sea_orm(string_value = "i")]
+    Ip,
+    /// Password reset requests, keyed by the normalized email address.
+    #[sea_orm(string_value = "r")]
+    PasswordResetAccount,
+    /// Password reset requests from an IP address, across all addresses.
+    #[sea_orm(string_value = "p")]
+    PasswordResetIp,
+}
+
+impl ThrottleScope {
+    /// Whether the key is an IP address rather than an email address.
+    pub fn is_ip(self) -> bool {
+        matches!(self, Self::Ip | Self::PasswordResetIp)
+    }
+}
+
+/// Failed login attempts, or rate limited requests, for an account or an IP address.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "login_throttle")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub scope: ThrottleScope,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub key: String,
+    pub failures: i32,
+    pub last_failure_at: PrimitiveDateTime,
+    pub locked_until: Option<PrimitiveDateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
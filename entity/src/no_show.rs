@@ -0,0 +1,50 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "no_show")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[sea_orm(unique)]
+    pub reservation_id: Uuid,
+    pub apothecary_id: Uuid,
+    pub missed_at: PrimitiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+
+    #[sea_orm(
+        belongs_to = "super::reservation::Entity",
+        from = "Column::ReservationId",
+        to = "super::reservation::Column::Id"
+    )]
+    Reservation,
+
+    #[sea_orm(
+        belongs_to = "super::apothecary::Entity",
+        from = "Column::ApothecaryId",
+        to = "super::apothecary::Column::Id"
+    )]
+    Apothecary,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for dto::export::NoShowExport {
+    fn from(no_show: Model) -> Self {
+        Self {
+            reservation_id: no_show.reservation_id,
+            apothecary_id: no_show.apothecary_id,
+            missed_at: no_show.missed_at,
+        }
+    }
+}
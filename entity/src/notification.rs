@@ -0,0 +1,61 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(Some(1))",
+    enum_name = "notification_kind"
+)]
+pub enum NotificationKind {
+    #[sea_orm(string_value = "c")]
+    ReservationCancelled,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "notification")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub apothecary_id: Uuid,
+    pub reservation_id: Option<Uuid>,
+    pub kind: NotificationKind,
+    pub message: String,
+    pub created_at: PrimitiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::apothecary::Entity",
+        from = "Column::ApothecaryId",
+        to = "super::apothecary::Column::Id"
+    )]
+    Apothecary,
+
+    #[sea_orm(
+        belongs_to = "super::reservation::Entity",
+        from = "Column::ReservationId",
+        to = "super::reservation::Column::Id"
+    )]
+    Reservation,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for dto::notification::Notification {
+    fn from(notification: Model) -> Self {
+        Self {
+            id: notification.id,
+            apothecary_id: notification.apothecary_id,
+            reservation_id: notification.reservation_id,
+            kind: match notification.kind {
+                NotificationKind::ReservationCancelled => {
+                    dto::notification::NotificationKind::ReservationCancelled
+                }
+            },
+            message: notification.message,
+            created_at: notification.created_at,
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+/// A login started at an OpenID Connect provider, looked up by the hashed `state` parameter
+/// when the provider redirects back. It can be used once before `expires_at`.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "oidc_authorization")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub state_hash: String,
+    pub provider: String,
+    /// PKCE verifier, its S256 challenge was sent with the authorization request.
+    pub code_verifier: String,
+    pub nonce: String,
+    /// The logged-in user that started it to link the identity to their account, `None` for
+    /// a login.
+    pub user_id: Option<Uuid>,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,48 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+/// A refresh token. Tokens issued by rotating one another share a `family_id`, so a whole
+/// login session can be revoked at once.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "refresh_token")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub family_id: Uuid,
+    pub user_id: Uuid,
+    #[sea_orm(unique)]
+    pub token_hash: String,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+    pub revoked_at: Option<PrimitiveDateTime>,
+    pub replaced_by: Option<Uuid>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for dto::export::SessionExport {
+    fn from(refresh_token: Model) -> Self {
+        Self {
+            session_id: refresh_token.family_id,
+            issued_at: refresh_token.created_at,
+            expires_at: refresh_token.expires_at,
+            revoked_at: refresh_token.revoked_at,
+        }
+    }
+}
@@ -24,6 +24,10 @@ pub enum ReservationStatus {
     Rejected,
     #[sea_orm(string_value = "d")]
     Done,
+    #[sea_orm(string_value = "e")]
+    Expired,
+    #[sea_orm(string_value = "c")]
+    Cancelled,
 }
 
 impl Display for ReservationStatus {
@@ -33,6 +37,47 @@ impl Display for ReservationStatus {
             ReservationStatus::Pending => write!(f, "pending"),
             ReservationStatus::Rejected => write!(f, "rejected"),
             ReservationStatus::Done => write!(f, "done"),
+            ReservationStatus::Expired => write!(f, "expired"),
+            ReservationStatus::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
+#[sea_orm(
+    rs_type = "String",
+    db_type = "String(Some(1))",
+    enum_name = "cancellation_reason"
+)]
+pub enum CancellationReason {
+    #[sea_orm(string_value = "n")]
+    NoLongerNeeded,
+    #[sea_orm(string_value = "e")]
+    FoundElsewhere,
+    #[sea_orm(string_value = "p")]
+    PickupNotPossible,
+    #[sea_orm(string_value = "o")]
+    Other,
+}
+
+impl From<dto::reservation::CancellationReason> for CancellationReason {
+    fn from(reason: dto::reservation::CancellationReason) -> Self {
+        match reason {
+            dto::reservation::CancellationReason::NoLongerNeeded => Self::NoLongerNeeded,
+            dto::reservation::CancellationReason::FoundElsewhere => Self::FoundElsewhere,
+            dto::reservation::CancellationReason::PickupNotPossible => Self::PickupNotPossible,
+            dto::reservation::CancellationReason::Other => Self::Other,
+        }
+    }
+}
+
+impl From<CancellationReason> for dto::reservation::CancellationReason {
+    fn from(reason: CancellationReason) -> Self {
+        match reason {
+            CancellationReason::NoLongerNeeded => Self::NoLongerNeeded,
+            CancellationReason::FoundElsewhere => Self::FoundElsewhere,
+            CancellationReason::PickupNotPossible => Self::PickupNotPossible,
+            CancellationReason::Other => Self::Other,
         }
     }
 }
@@ -44,13 +89,20 @@ pub struct Model {
     pub id: Uuid,
     pub apothecary_id: Uuid,
     pub medication_id: Uuid,
-    pub user_id: Uuid,
+    /// `None` once the customer deleted their account.
+    pub user_id: Option<Uuid>,
     pub quantity_type: QuantityType,
     pub quantity: Option<i64>,
-    pub price: Decimal,
+    /// `None` for medications of an unknown quantity.
+    pub price: Option<Decimal>,
     pub status: ReservationStatus,
     pub start_date_time: Option<PrimitiveDateTime>,
     pub end_date_time: Option<PrimitiveDateTime>,
+    pub cancellation_reason: Option<CancellationReason>,
+    pub cancellation_note: Option<String>,
+    pub cancelled_at: Option<PrimitiveDateTime>,
+    #[sea_orm(default_expr = "Expr::current_timestamp()")]
+    pub created_at: PrimitiveDateTime,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -97,6 +149,7 @@ impl Related<super::user::Entity> for Entity {
     }
 }
 
+#[derive(Clone)]
 pub struct ReservationWithApothecaryAndMedication(
     (
         Model,
@@ -143,24 +196,31 @@ impl From<ReservationWithApothecaryAndMedication> for dto::reservation::Medicati
             quantity: match reservation.quantity_type {
                 QuantityType::Package => MedicationQuantity::Package(MedicationQuantityPackage {
                     quantity: reservation.quantity.unwrap() as _,
-                    price: reservation.price,
+                    price: reservation.price.expect("Package price is missing"),
                 }),
                 QuantityType::Unknown => MedicationQuantity::Unknown(MedicationQuantityUnknown),
             },
             start_date_time: reservation.start_date_time,
             end_date_time: reservation.end_date_time,
-            status: {
-                if reservation.end_date_time < Some(now) {
+            status: match reservation.status {
+                ReservationStatus::Active if reservation.end_date_time < Some(now) => {
                     MedicationReservationStatus::Expired
-                } else {
-                    match reservation.status {
-                        ReservationStatus::Active => MedicationReservationStatus::Active,
-                        ReservationStatus::Pending => MedicationReservationStatus::Pending,
-                        ReservationStatus::Rejected => MedicationReservationStatus::Rejected,
-                        ReservationStatus::Done => MedicationReservationStatus::Done,
-                    }
                 }
+                ReservationStatus::Active => MedicationReservationStatus::Active,
+                ReservationStatus::Pending => MedicationReservationStatus::Pending,
+                ReservationStatus::Rejected => MedicationReservationStatus::Rejected,
+                ReservationStatus::Done => MedicationReservationStatus::Done,
+                ReservationStatus::Expired => MedicationReservationStatus::Expired,
+                ReservationStatus::Cancelled => MedicationReservationStatus::Cancelled,
             },
+            created_at: reservation.created_at,
+            cancellation: reservation.cancelled_at.map(|cancelled_at| {
+                dto::reservation::MedicationReservationCancellation {
+                    reason: reservation.cancellation_reason.map(Into::into),
+                    note: reservation.cancellation_note,
+                    cancelled_at,
+                }
+            }),
         }
     }
 }
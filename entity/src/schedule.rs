@@ -30,8 +30,8 @@ impl From<Model> for dto::schedule::Schedule {
     fn from(schedule: Model) -> Self {
         Self {
             weekday: schedule.weekday.into(),
-            start: schedule.start.into(),
-            end: schedule.end.into(),
+            start: schedule.start,
+            end: schedule.end,
         }
     }
 }
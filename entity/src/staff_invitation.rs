@@ -0,0 +1,53 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+use crate::apothecary_user::StaffRole;
+
+/// An invitation to join an apothecary, sent by mail. The token is stored hashed and can be
+/// accepted once before `expires_at`, by the account with the invited address.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "staff_invitation")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub apothecary_id: Uuid,
+    pub email: String,
+    pub role: StaffRole,
+    #[sea_orm(unique)]
+    pub token_hash: String,
+    pub invited_by: Option<Uuid>,
+    pub created_at: PrimitiveDateTime,
+    pub expires_at: PrimitiveDateTime,
+    pub accepted_at: Option<PrimitiveDateTime>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::apothecary::Entity",
+        from = "Column::ApothecaryId",
+        to = "super::apothecary::Column::Id"
+    )]
+    Apothecary,
+}
+
+impl Related<super::apothecary::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Apothecary.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for dto::staff::Invitation {
+    fn from(invitation: Model) -> Self {
+        Self {
+            id: invitation.id,
+            apothecary_id: invitation.apothecary_id,
+            email: invitation.email,
+            role: invitation.role.into(),
+            created_at: invitation.created_at,
+            expires_at: invitation.expires_at,
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
 
 #[derive(Debug, Clone, PartialEq, Eq, EnumIter, DeriveActiveEnum)]
 #[sea_orm(
@@ -25,6 +26,11 @@ pub struct Model {
     pub email: String,
     pub password: String,
     pub user_type: UserType,
+    pub email_verified_at: Option<PrimitiveDateTime>,
+    /// New address the user asked for, it replaces `email` once verified.
+    pub pending_email: Option<String>,
+    /// Disabled accounts can neither log in nor use tokens issued before.
+    pub disabled_at: Option<PrimitiveDateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -42,17 +48,44 @@ impl Related<super::apothecary::Entity> for Entity {
     }
 }
 
+impl From<UserType> for dto::user::UserType {
+    fn from(user_type: UserType) -> Self {
+        match user_type {
+            UserType::Admin => Self::Admin,
+            UserType::Apothecary => Self::Apothecary,
+            UserType::Customer => Self::Customer,
+        }
+    }
+}
+
+impl From<dto::user::UserType> for UserType {
+    fn from(user_type: dto::user::UserType) -> Self {
+        match user_type {
+            dto::user::UserType::Admin => Self::Admin,
+            dto::user::UserType::Apothecary => Self::Apothecary,
+            dto::user::UserType::Customer => Self::Customer,
+        }
+    }
+}
+
 impl From<Model> for dto::user::User {
     fn from(user: Model) -> Self {
         Self {
             id: user.id,
             name: user.name,
             email: user.email,
-            user_type: match user.user_type {
-                UserType::Admin => dto::user::UserType::Admin,
-                UserType::Apothecary => dto::user::UserType::Apothecary,
-                UserType::Customer => dto::user::UserType::Customer,
-            },
+            email_verified: user.email_verified_at.is_some(),
+            pending_email: user.pending_email,
+            user_type: user.user_type.into(),
+        }
+    }
+}
+
+impl From<Model> for dto::admin::ManagedUser {
+    fn from(user: Model) -> Self {
+        Self {
+            disabled_at: user.disabled_at,
+            user: user.into(),
         }
     }
 }
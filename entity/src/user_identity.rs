@@ -0,0 +1,49 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+/// An account at an OpenID Connect provider that can be used to log in as the user. The pair
+/// of `provider` and `subject` is unique.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "user_identity")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub id: Uuid,
+    pub user_id: Uuid,
+    /// Name of the provider in the settings.
+    pub provider: String,
+    /// `sub` claim of the provider's ID tokens.
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: PrimitiveDateTime,
+    pub last_login_at: PrimitiveDateTime,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
+
+impl From<Model> for dto::oidc::Identity {
+    fn from(identity: Model) -> Self {
+        Self {
+            id: identity.id,
+            provider: identity.provider,
+            email: identity.email,
+            created_at: identity.created_at,
+            last_login_at: identity.last_login_at,
+        }
+    }
+}
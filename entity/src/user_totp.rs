@@ -0,0 +1,36 @@
+use sea_orm::entity::prelude::*;
+use time::PrimitiveDateTime;
+
+/// TOTP secret of a user. Enrollment is pending until a first code confirms it.
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+#[sea_orm(table_name = "user_totp")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub user_id: Uuid,
+    /// Base32 encoded shared secret. It is stored in plaintext because the server needs it to
+    /// compute codes, so it can't be hashed like passwords; anyone reading the database can
+    /// generate codes. The second factor still protects against leaked passwords alone.
+    pub secret: String,
+    pub created_at: PrimitiveDateTime,
+    pub confirmed_at: Option<PrimitiveDateTime>,
+    /// Time step of the last accepted code, codes of this or earlier steps are rejected.
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::user::Entity",
+        from = "Column::UserId",
+        to = "super::user::Column::Id"
+    )]
+    User,
+}
+
+impl Related<super::user::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::User.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,12 +1,211 @@
 pub use sea_orm_migration::prelude::*;
 
+macro_rules! create_table_from_entity {
+    ($manager:ident, $schema:ident, $entity_module:ident) => {
+        $manager
+            .create_table($schema.create_table_from_entity($entity_module::Entity))
+            .await?;
+    };
+}
+
+macro_rules! drop_table_from_entity {
+    ($manager:ident, $entity_module:ident) => {
+        $manager
+            .drop_table(
+                sea_query::Table::drop()
+                    .table($entity_module::Entity)
+                    .if_exists()
+                    .to_owned(),
+            )
+            .await?;
+    };
+}
+
+/// Tables are created from the current entities, so on a fresh database columns added by later
+/// migrations already exist. Those migrations only have to alter databases created by earlier
+/// releases. Returns whether the column was added.
+async fn add_column_if_missing<T>(
+    manager: &SchemaManager<'_>,
+    table: T,
+    mut column: ColumnDef,
+) -> Result<bool, DbErr>
+where
+    T: Iden + 'static,
+{
+    if manager
+        .has_column(table.to_string(), column.get_column_name())
+        .await?
+    {
+        return Ok(false);
+    }
+
+    manager
+        .alter_table(
+            Table::alter()
+                .table(table)
+                .add_column(&mut column)
+                .to_owned(),
+        )
+        .await?;
+
+    Ok(true)
+}
+
+/// Drops the NOT NULL constraint of a column, the entity has to declare it as nullable already.
+/// SQLite cannot alter columns, there the table is rebuilt from the entity and the rows are
+/// copied over. Returns whether the constraint was dropped.
+async fn make_nullable<E>(
+    manager: &SchemaManager<'_>,
+    entity: E,
+    column: E::Column,
+) -> Result<bool, DbErr>
+where
+    E: sea_orm_migration::sea_orm::EntityTrait,
+{
+    use sea_orm_migration::sea_orm::{ConnectionTrait, DbBackend, IdenStatic, Schema, Statement};
+
+    let db = manager.get_connection();
+    let backend = manager.get_database_backend();
+    let table = entity.table_name().to_owned();
+    let column = column.as_str().to_owned();
+
+    match backend {
+        DbBackend::Postgres => {
+            let not_null = db
+                .query_one(Statement::from_sql_and_values(
+                    backend,
+                    r#"SELECT is_nullable FROM information_schema.columns
+                       WHERE table_schema = current_schema() AND table_name = $1 AND column_name = $2"#,
+                    [table.clone().into(), column.clone().into()],
+                ))
+                .await?
+                .map(|row| row.try_get::<String>("", "is_nullable"))
+                .transpose()?
+                .is_some_and(|nullable| nullable == "NO");
+
+            if !not_null {
+                return Ok(false);
+            }
+
+            db.execute(Statement::from_string(
+                backend,
+                format!(r#"ALTER TABLE "{table}" ALTER COLUMN "{column}" DROP NOT NULL"#),
+            ))
+            .await?;
+        }
+        DbBackend::Sqlite => {
+            let columns = db
+                .query_all(Statement::from_string(
+                    backend,
+                    format!(r#"SELECT name, "notnull" FROM pragma_table_info('{table}')"#),
+                ))
+                .await?
+                .into_iter()
+                .map(|row| {
+                    Ok((
+                        row.try_get::<String>("", "name")?,
+                        row.try_get::<bool>("", "notnull")?,
+                    ))
+                })
+                .collect::<Result<Vec<_>, DbErr>>()?;
+
+            if !columns
+                .iter()
+                .any(|(name, not_null)| *name == column && *not_null)
+            {
+                return Ok(false);
+            }
+
+            let rebuilt = format!("{table}_rebuilt");
+            let names = columns
+                .iter()
+                .map(|(name, _)| format!(r#""{name}""#))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            // Dropping the old table must neither fail nor cascade because of rows that
+            // reference it.
+            db.execute_unprepared("PRAGMA foreign_keys = OFF").await?;
+
+            manager
+                .create_table(
+                    Schema::new(backend)
+                        .create_table_from_entity(entity)
+                        .table(Alias::new(&rebuilt))
+                        .to_owned(),
+                )
+                .await?;
+
+            db.execute_unprepared(&format!(
+                r#"INSERT INTO "{rebuilt}" ({names}) SELECT {names} FROM "{table}""#
+            ))
+            .await?;
+            db.execute_unprepared(&format!(r#"DROP TABLE "{table}""#))
+                .await?;
+            db.execute_unprepared(&format!(r#"ALTER TABLE "{rebuilt}" RENAME TO "{table}""#))
+                .await?;
+
+            db.execute_unprepared("PRAGMA foreign_keys = ON").await?;
+        }
+        DbBackend::MySql => {
+            return Err(DbErr::Migration(format!(
+                "Cannot make {table}.{column} nullable on MySQL"
+            )));
+        }
+    }
+
+    Ok(true)
+}
+
 mod m20231206_213800_create_table;
+mod m20261019_080000_add_user_account_columns;
+mod m20261019_080100_add_reservation_history_columns;
+mod m20261019_080200_add_apothecary_user_role;
+mod m20261019_090000_create_idempotency_key;
+mod m20261019_100000_create_no_show;
+mod m20261019_110000_create_notification;
+mod m20261019_120000_create_refresh_token;
+mod m20261019_130000_create_password_reset_token;
+mod m20261019_140000_create_audit_event;
+mod m20261019_140100_create_login_throttle;
+mod m20261019_150000_normalize_user_email;
+mod m20261019_160000_create_user_totp;
+mod m20261019_160100_create_totp_recovery_code;
+mod m20261019_170000_create_user_identity;
+mod m20261019_170100_create_oidc_authorization;
+mod m20261019_180000_create_staff_invitation;
+mod m20261019_190000_create_api_key;
+mod m20261019_200000_add_idempotency_key_response;
+mod m20261019_200100_add_oidc_authorization_user;
+mod m20261019_200200_make_prices_nullable;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20231206_213800_create_table::Migration)]
+        vec![
+            Box::new(m20231206_213800_create_table::Migration),
+            Box::new(m20261019_080000_add_user_account_columns::Migration),
+            Box::new(m20261019_080100_add_reservation_history_columns::Migration),
+            Box::new(m20261019_080200_add_apothecary_user_role::Migration),
+            Box::new(m20261019_090000_create_idempotency_key::Migration),
+            Box::new(m20261019_100000_create_no_show::Migration),
+            Box::new(m20261019_110000_create_notification::Migration),
+            Box::new(m20261019_120000_create_refresh_token::Migration),
+            Box::new(m20261019_130000_create_password_reset_token::Migration),
+            Box::new(m20261019_140000_create_audit_event::Migration),
+            Box::new(m20261019_140100_create_login_throttle::Migration),
+            Box::new(m20261019_150000_normalize_user_email::Migration),
+            Box::new(m20261019_160000_create_user_totp::Migration),
+            Box::new(m20261019_160100_create_totp_recovery_code::Migration),
+            Box::new(m20261019_170000_create_user_identity::Migration),
+            Box::new(m20261019_170100_create_oidc_authorization::Migration),
+            Box::new(m20261019_180000_create_staff_invitation::Migration),
+            Box::new(m20261019_190000_create_api_key::Migration),
+            Box::new(m20261019_200000_add_idempotency_key_response::Migration),
+            Box::new(m20261019_200100_add_oidc_authorization_user::Migration),
+            Box::new(m20261019_200200_make_prices_nullable::Migration),
+        ]
     }
 }
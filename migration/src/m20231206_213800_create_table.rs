@@ -19,27 +19,6 @@ use uuid::Uuid;
 #[derive(DeriveMigrationName)]
 pub struct Migration;
 
-macro_rules! create_table_from_entity {
-    ($manager:ident, $schema:ident, $entity_module:ident) => {
-        $manager
-            .create_table($schema.create_table_from_entity($entity_module::Entity))
-            .await?;
-    };
-}
-
-macro_rules! drop_table_from_entity {
-    ($manager:ident, $entity_module:ident) => {
-        $manager
-            .drop_table(
-                sea_query::Table::drop()
-                    .table($entity_module::Entity)
-                    .if_exists()
-                    .to_owned(),
-            )
-            .await?;
-    };
-}
-
 fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
@@ -70,6 +49,7 @@ impl MigrationTrait for Migration {
             email: Set("admin@email.com".to_owned()),
             password: Set(hash_password("password").map_err(|e| DbErr::Migration(e.to_string()))?),
             user_type: Set(user::UserType::Admin),
+            ..Default::default()
         }
         .insert(db)
         .await?
@@ -81,6 +61,7 @@ impl MigrationTrait for Migration {
             email: Set("john@apo.com".to_owned()),
             password: Set(hash_password("password").map_err(|e| DbErr::Migration(e.to_string()))?),
             user_type: Set(user::UserType::Apothecary),
+            ..Default::default()
         }
         .insert(db)
         .await?
@@ -90,8 +71,8 @@ impl MigrationTrait for Migration {
             apothecary::ActiveModel {
                 id: Set(Uuid::new_v4()),
                 name: Set("St. Rudolf".to_owned()),
-                latitude: Set(48.1942566),
-                longitude: Set(16.3181194),
+                latitude: Set(48.194_256),
+                longitude: Set(16.318_12),
                 street: Set("Goldschlagstraße".to_owned()),
                 number: Set("105".to_owned()),
                 post_code: Set(1150),
@@ -104,8 +85,8 @@ impl MigrationTrait for Migration {
             apothecary::ActiveModel {
                 id: Set(Uuid::new_v4()),
                 name: Set("Zur goldenen Krone".to_owned()),
-                latitude: Set(48.20591348182142),
-                longitude: Set(16.372607040478915),
+                latitude: Set(48.205_914),
+                longitude: Set(16.372_606),
                 street: Set("Himmelpfortgasse".to_owned()),
                 number: Set("7".to_owned()),
                 post_code: Set(1010),
@@ -121,6 +102,7 @@ impl MigrationTrait for Migration {
             id: Set(Uuid::new_v4()),
             apothecary_id: Set(apothecary_ids[0]),
             user_id: Set(user_a_id),
+            ..Default::default()
         }
         .insert(db)
         .await?;
@@ -138,7 +120,7 @@ impl MigrationTrait for Migration {
             medication_id: Set(medication_id),
             medication_quantity_type: Set(apothecary_medication::QuantityType::Package),
             medication_quantity: Set(Some(10)),
-            medication_price: Set(Decimal::new(1099, 2)),
+            medication_price: Set(Some(Decimal::new(1099, 2))),
         }
         .insert(db)
         .await?;
@@ -156,7 +138,7 @@ impl MigrationTrait for Migration {
             medication_id: Set(medication_id),
             medication_quantity_type: Set(apothecary_medication::QuantityType::Package),
             medication_quantity: Set(Some(1)),
-            medication_price: Set(Decimal::new(799, 2)),
+            medication_price: Set(Some(Decimal::new(799, 2))),
         }
         .insert(db)
         .await?;
@@ -166,7 +148,7 @@ impl MigrationTrait for Migration {
             medication_id: Set(medication_id),
             medication_quantity_type: Set(apothecary_medication::QuantityType::Package),
             medication_quantity: Set(Some(3)),
-            medication_price: Set(Decimal::new(899, 2)),
+            medication_price: Set(Some(Decimal::new(899, 2))),
         }
         .insert(db)
         .await?;
@@ -183,9 +165,7 @@ impl MigrationTrait for Migration {
 
         let time_format = format_description!("[hour]:[minute]");
 
-        let mut i = 0;
-
-        for (weekday, start, end) in schedules {
+        for (i, (weekday, start, end)) in schedules.into_iter().enumerate() {
             let schedule_id =
                 schedule::ActiveModel {
                     id: Set(Uuid::new_v4()),
@@ -205,8 +185,6 @@ impl MigrationTrait for Migration {
             }
             .insert(db)
             .await?;
-
-            i += 1;
         }
 
         let now = OffsetDateTime::now_utc();
@@ -219,13 +197,14 @@ impl MigrationTrait for Migration {
             id: Set(Uuid::new_v4()),
             apothecary_id: Set(apothecary_ids[0]),
             medication_id: Set(medication_id),
-            user_id: Set(user_id),
+            user_id: Set(Some(user_id)),
             quantity_type: Set(apothecary_medication::QuantityType::Package),
             quantity: Set(Some(1)),
-            price: Set(Decimal::new(1099, 2)),
+            price: Set(Some(Decimal::new(1099, 2))),
             status: Set(reservation::ReservationStatus::Active),
             start_date_time: Set(Some(now)),
             end_date_time: Set(Some(end)),
+            ..Default::default()
         }
         .insert(db)
         .await?;
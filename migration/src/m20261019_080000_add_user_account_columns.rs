@@ -0,0 +1,79 @@
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, Statement},
+};
+
+use crate::add_column_if_missing;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum User {
+    Table,
+    EmailVerifiedAt,
+    PendingEmail,
+    DisabledAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        add_column_if_missing(
+            manager,
+            User::Table,
+            ColumnDef::new(User::EmailVerifiedAt)
+                .date_time()
+                .null()
+                .to_owned(),
+        )
+        .await?;
+
+        add_column_if_missing(
+            manager,
+            User::Table,
+            ColumnDef::new(User::PendingEmail)
+                .string()
+                .null()
+                .to_owned(),
+        )
+        .await?;
+
+        add_column_if_missing(
+            manager,
+            User::Table,
+            ColumnDef::new(User::DisabledAt)
+                .date_time()
+                .null()
+                .to_owned(),
+        )
+        .await?;
+
+        // Accounts from before email verification could not have verified their address, they
+        // keep working as before.
+        let db = manager.get_connection();
+        db.execute(Statement::from_string(
+            manager.get_database_backend(),
+            r#"UPDATE "user" SET email_verified_at = CURRENT_TIMESTAMP WHERE email_verified_at IS NULL"#,
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite only supports one column per ALTER TABLE statement.
+        for column in [User::DisabledAt, User::PendingEmail, User::EmailVerifiedAt] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(User::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
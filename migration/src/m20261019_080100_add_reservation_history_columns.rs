@@ -0,0 +1,106 @@
+use entity::reservation;
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, Statement},
+};
+
+use crate::{add_column_if_missing, make_nullable};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum Reservation {
+    Table,
+    CancellationReason,
+    CancellationNote,
+    CancelledAt,
+    CreatedAt,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Reservations outlive the accounts of their customers. On SQLite this rebuilds the
+        // table from the entity, which already brings the columns below.
+        let rebuilt =
+            make_nullable(manager, reservation::Entity, reservation::Column::UserId).await?;
+
+        add_column_if_missing(
+            manager,
+            Reservation::Table,
+            ColumnDef::new(Reservation::CancellationReason)
+                .string_len(1)
+                .null()
+                .to_owned(),
+        )
+        .await?;
+
+        add_column_if_missing(
+            manager,
+            Reservation::Table,
+            ColumnDef::new(Reservation::CancellationNote)
+                .string()
+                .null()
+                .to_owned(),
+        )
+        .await?;
+
+        add_column_if_missing(
+            manager,
+            Reservation::Table,
+            ColumnDef::new(Reservation::CancelledAt)
+                .date_time()
+                .null()
+                .to_owned(),
+        )
+        .await?;
+
+        let added = add_column_if_missing(
+            manager,
+            Reservation::Table,
+            ColumnDef::new(Reservation::CreatedAt)
+                .date_time()
+                .not_null()
+                .default(Expr::current_timestamp())
+                .to_owned(),
+        )
+        .await?;
+
+        // Reservations are picked up from the moment they are made, older ones without a start
+        // keep the time of the migration.
+        if rebuilt || added {
+            manager
+                .get_connection()
+                .execute(Statement::from_string(
+                    manager.get_database_backend(),
+                    r#"UPDATE "reservation" SET created_at = start_date_time WHERE start_date_time IS NOT NULL"#,
+                ))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Reservations of deleted accounts cannot get their customer back, so user_id stays
+        // nullable.
+        for column in [
+            Reservation::CreatedAt,
+            Reservation::CancelledAt,
+            Reservation::CancellationNote,
+            Reservation::CancellationReason,
+        ] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Reservation::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,50 @@
+use entity::apothecary_user::{self, StaffRole};
+use sea_orm_migration::{prelude::*, sea_orm::EntityTrait};
+
+use crate::add_column_if_missing;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum ApothecaryUser {
+    Table,
+    Role,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let added = add_column_if_missing(
+            manager,
+            ApothecaryUser::Table,
+            ColumnDef::new(ApothecaryUser::Role)
+                .string_len(1)
+                .not_null()
+                .default("o")
+                .to_owned(),
+        )
+        .await?;
+
+        // Members from before roles existed run their apothecary, they become owners.
+        if added {
+            apothecary_user::Entity::update_many()
+                .col_expr(apothecary_user::Column::Role, Expr::value(StaffRole::Owner))
+                .exec(manager.get_connection())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(ApothecaryUser::Table)
+                    .drop_column(ApothecaryUser::Role)
+                    .to_owned(),
+            )
+            .await
+    }
+}
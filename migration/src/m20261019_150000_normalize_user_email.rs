@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use entity::user;
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{
+        ColumnTrait, ConnectionTrait, EntityTrait, QueryFilter, QuerySelect, Set, Statement,
+    },
+};
+use uuid::Uuid;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INDEX: &str = "idx-user-email-normalized";
+
+/// Same as `service::user::normalize_email`. Lowercasing happens here rather than in SQL, whose
+/// `LOWER` only handles ASCII on SQLite.
+fn normalize(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let users: Vec<(Uuid, String, Option<String>)> = user::Entity::find()
+            .select_only()
+            .column(user::Column::Id)
+            .column(user::Column::Email)
+            .column(user::Column::PendingEmail)
+            .into_tuple()
+            .all(db)
+            .await?;
+
+        // Addresses that only differ in case or surrounding whitespace would end up equal.
+        // Those accounts have to be merged or renamed by hand before the index can exist.
+        let mut accounts: HashMap<String, usize> = HashMap::new();
+        for (_, email, _) in &users {
+            *accounts.entry(normalize(email)).or_default() += 1;
+        }
+
+        let mut collisions: Vec<String> = accounts
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(email, _)| email)
+            .collect();
+
+        if !collisions.is_empty() {
+            collisions.sort();
+
+            return Err(DbErr::Migration(format!(
+                "Cannot normalize user emails, these addresses belong to more than one account: {}",
+                collisions.join(", ")
+            )));
+        }
+
+        for (id, email, pending_email) in users {
+            let normalized = normalize(&email);
+            let normalized_pending = pending_email.as_deref().map(normalize);
+
+            if normalized == email && normalized_pending == pending_email {
+                continue;
+            }
+
+            user::Entity::update_many()
+                .set(user::ActiveModel {
+                    email: Set(normalized),
+                    pending_email: Set(normalized_pending),
+                    ..Default::default()
+                })
+                .filter(user::Column::Id.eq(id))
+                .exec(db)
+                .await?;
+        }
+
+        // Guards against addresses stored without normalizing them first.
+        db.execute(Statement::from_string(
+            manager.get_database_backend(),
+            format!(
+                r#"CREATE UNIQUE INDEX "{}" ON "user" (LOWER(email))"#,
+                INDEX
+            ),
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(INDEX)
+                    .table(Alias::new("user"))
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,35 @@
+use entity::user_identity;
+use sea_orm_migration::{prelude::*, sea_orm::Schema};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let builder = manager.get_database_backend();
+        let schema = Schema::new(builder);
+
+        create_table_from_entity!(manager, schema, user_identity);
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-user_identity-provider-subject")
+                    .table(user_identity::Entity)
+                    .col(user_identity::Column::Provider)
+                    .col(user_identity::Column::Subject)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        drop_table_from_entity!(manager, user_identity);
+
+        Ok(())
+    }
+}
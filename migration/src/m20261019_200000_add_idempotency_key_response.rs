@@ -0,0 +1,63 @@
+use sea_orm_migration::prelude::*;
+
+use crate::add_column_if_missing;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum IdempotencyKey {
+    Table,
+    ResponseStatus,
+    ResponseBody,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Keys stored before responses were recorded cannot be replayed, drop them.
+        manager
+            .exec_stmt(Query::delete().from_table(IdempotencyKey::Table).to_owned())
+            .await?;
+
+        add_column_if_missing(
+            manager,
+            IdempotencyKey::Table,
+            ColumnDef::new(IdempotencyKey::ResponseStatus)
+                .integer()
+                .not_null()
+                .default(200)
+                .to_owned(),
+        )
+        .await?;
+
+        add_column_if_missing(
+            manager,
+            IdempotencyKey::Table,
+            ColumnDef::new(IdempotencyKey::ResponseBody)
+                .text()
+                .not_null()
+                .default("")
+                .to_owned(),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // SQLite only supports one column per ALTER TABLE statement.
+        for column in [IdempotencyKey::ResponseBody, IdempotencyKey::ResponseStatus] {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(IdempotencyKey::Table)
+                        .drop_column(column)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use sea_orm_migration::prelude::*;
+
+use crate::add_column_if_missing;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[derive(DeriveIden)]
+enum OidcAuthorization {
+    Table,
+    UserId,
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        add_column_if_missing(
+            manager,
+            OidcAuthorization::Table,
+            ColumnDef::new(OidcAuthorization::UserId)
+                .uuid()
+                .null()
+                .to_owned(),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(OidcAuthorization::Table)
+                    .drop_column(OidcAuthorization::UserId)
+                    .to_owned(),
+            )
+            .await
+    }
+}
@@ -0,0 +1,44 @@
+use entity::{apothecary_medication, reservation};
+use sea_orm_migration::{
+    prelude::*,
+    sea_orm::{ConnectionTrait, Statement},
+};
+
+use crate::make_nullable;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        make_nullable(
+            manager,
+            apothecary_medication::Entity,
+            apothecary_medication::Column::MedicationPrice,
+        )
+        .await?;
+
+        make_nullable(manager, reservation::Entity, reservation::Column::Price).await?;
+
+        // Stock of an unknown quantity was stored with a price of zero, which was never a real
+        // price. Neither were the prices copied from it into reservations.
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+
+        for statement in [
+            r#"UPDATE "apothecary_medication" SET medication_price = NULL WHERE medication_quantity_type = 'u'"#,
+            r#"UPDATE "reservation" SET price = NULL WHERE quantity_type = 'u'"#,
+        ] {
+            db.execute(Statement::from_string(backend, statement))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        // Unknown prices have no value to restore, so the columns stay nullable.
+        Ok(())
+    }
+}
@@ -0,0 +1,615 @@
+use std::{fmt::Display, sync::Arc};
+
+use dto::{
+    admin::{ApothecaryLink, UserSearch},
+    page::{Pageable, SortDirection},
+    staff::Membership,
+};
+use entity::{apothecary_user, audit_event::AuditEventKind, user::UserType, DatabaseConnection};
+use sea_orm::{
+    entity::prelude::*,
+    sea_query::{Func, LikeExpr},
+    Condition, Set, TransactionTrait,
+};
+use settings::Settings;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, AuditEvent},
+    hash::random_token,
+    mail::Mailer,
+    page::{Page, PageError},
+    staff::{find_membership, is_last_owner, revert_if_unaffiliated, role_name},
+    user::{hash_password, issue_password_reset, now, password_reset_mail, User},
+};
+
+pub enum AdminServiceError {
+    UserNotFound,
+    ApothecaryNotFound,
+    MembershipNotFound,
+    AlreadyMember,
+    LastOwner,
+    /// Admins cannot lock themselves out by changing, disabling or resetting their own account.
+    OwnAccount,
+    Page(PageError),
+    Anyhow(anyhow::Error),
+}
+
+impl From<DbErr> for AdminServiceError {
+    fn from(err: DbErr) -> Self {
+        Self::Anyhow(err.into())
+    }
+}
+
+impl From<PageError> for AdminServiceError {
+    fn from(err: PageError) -> Self {
+        Self::Page(err)
+    }
+}
+
+impl Display for AdminServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AdminServiceError::UserNotFound => write!(f, "User not found"),
+            AdminServiceError::ApothecaryNotFound => write!(f, "Apothecary not found"),
+            AdminServiceError::MembershipNotFound => {
+                write!(f, "User is not a member of the apothecary")
+            }
+            AdminServiceError::AlreadyMember => write!(f, "User is already a staff member"),
+            AdminServiceError::LastOwner => {
+                write!(f, "The last owner of an apothecary cannot be removed")
+            }
+            AdminServiceError::OwnAccount => write!(f, "Admins cannot change their own account"),
+            AdminServiceError::Page(e) => write!(f, "{}", e),
+            AdminServiceError::Anyhow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Account management for admins. Every change is written to the audit log of the affected
+/// user, together with the admin who made it.
+pub struct AdminService {
+    db: DatabaseConnection,
+    settings: Arc<Settings>,
+    mailer: Arc<dyn Mailer>,
+}
+
+impl AdminService {
+    pub fn new(db: DatabaseConnection, settings: Arc<Settings>, mailer: Arc<dyn Mailer>) -> Self {
+        Self {
+            db,
+            settings,
+            mailer,
+        }
+    }
+
+    pub async fn get_users(
+        &self,
+        search: UserSearch,
+        pageable: Option<Pageable>,
+    ) -> Result<Page<User>, AdminServiceError> {
+        let mut query = entity::user::Entity::find();
+
+        if let Some(term) = search.search.map(|s| s.trim().to_lowercase()) {
+            let pattern = LikeExpr::new(format!("%{}%", escape_like(&term))).escape('\\');
+
+            query = query.filter(
+                Condition::any()
+                    .add(
+                        Expr::expr(Func::lower(Expr::col(entity::user::Column::Name)))
+                            .like(pattern.clone()),
+                    )
+                    .add(Expr::col(entity::user::Column::Email).like(pattern)),
+            );
+        }
+
+        if let Some(user_type) = search.user_type {
+            query = query.filter(entity::user::Column::UserType.eq(UserType::from(user_type)));
+        }
+
+        match search.disabled {
+            Some(true) => query = query.filter(entity::user::Column::DisabledAt.is_not_null()),
+            Some(false) => query = query.filter(entity::user::Column::DisabledAt.is_null()),
+            None => {}
+        }
+
+        Ok(Page::paginate(&self.db, query, pageable).await?)
+    }
+
+    pub async fn get_user(&self, user_id: Uuid) -> Result<User, AdminServiceError> {
+        entity::user::Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AdminServiceError::UserNotFound)
+    }
+
+    pub async fn get_audit_events(
+        &self,
+        user_id: Uuid,
+        pageable: Option<Pageable>,
+    ) -> Result<Page<AuditEvent>, AdminServiceError> {
+        let user = self.get_user(user_id).await?;
+
+        let query = entity::audit_event::Entity::find()
+            .filter(entity::audit_event::Column::UserId.eq(user.id));
+        let pageable = pageable.map(|p| p.sorted_by_default("createdAt", SortDirection::Desc));
+
+        Ok(Page::paginate(&self.db, query, pageable).await?)
+    }
+
+    /// Changes the type of the account. It takes effect with the user's next access token.
+    pub async fn set_user_type(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        user_type: UserType,
+        ip: Option<String>,
+    ) -> Result<User, AdminServiceError> {
+        let user = self.get_other_user(admin_id, user_id).await?;
+
+        if user.user_type == user_type {
+            return Ok(user);
+        }
+
+        let detail = format!(
+            "Changed from {} to {}",
+            type_name(&user.user_type),
+            type_name(&user_type)
+        );
+
+        let txn = self.db.begin().await?;
+
+        let mut user: entity::user::ActiveModel = user.into();
+        user.user_type = Set(user_type);
+        let user = user.update(&txn).await?;
+
+        audit::record_admin_action(
+            &txn,
+            admin_id,
+            user.id,
+            AuditEventKind::UserTypeChanged,
+            ip,
+            detail,
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(user)
+    }
+
+    /// Disables or re-enables the account. The caller should revoke the sessions of a disabled
+    /// user; access tokens are rejected from now on anyway.
+    pub async fn set_disabled(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        disabled: bool,
+        ip: Option<String>,
+    ) -> Result<User, AdminServiceError> {
+        let user = self.get_other_user(admin_id, user_id).await?;
+
+        if user.disabled_at.is_some() == disabled {
+            return Ok(user);
+        }
+
+        let (disabled_at, kind, detail) = if disabled {
+            (
+                Some(now()),
+                AuditEventKind::AccountDisabled,
+                "Account disabled",
+            )
+        } else {
+            (None, AuditEventKind::AccountEnabled, "Account enabled")
+        };
+
+        let txn = self.db.begin().await?;
+
+        let mut user: entity::user::ActiveModel = user.into();
+        user.disabled_at = Set(disabled_at);
+        let user = user.update(&txn).await?;
+
+        audit::record_admin_action(&txn, admin_id, user.id, kind, ip, detail.to_owned()).await?;
+
+        txn.commit().await?;
+
+        Ok(user)
+    }
+
+    /// Replaces the password with an unusable one and mails the user a reset link. The caller
+    /// should revoke the user's sessions.
+    pub async fn force_password_reset(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        ip: Option<String>,
+    ) -> Result<User, AdminServiceError> {
+        let user = self.get_other_user(admin_id, user_id).await?;
+        let password = hash_password(&random_token()).map_err(|e| {
+            AdminServiceError::Anyhow(anyhow::anyhow!("Failed to hash password: {}", e))
+        })?;
+
+        let txn = self.db.begin().await?;
+
+        let mut user: entity::user::ActiveModel = user.into();
+        user.password = Set(password);
+        let user = user.update(&txn).await?;
+
+        audit::record_admin_action(
+            &txn,
+            admin_id,
+            user.id,
+            AuditEventKind::PasswordResetForced,
+            ip,
+            "Password reset forced".to_owned(),
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        let token = issue_password_reset(&self.db, &self.settings, user.id).await?;
+
+        if let Err(e) = self
+            .mailer
+            .send(password_reset_mail(&self.settings, &user, &token))
+            .await
+        {
+            tracing::error!("Failed to send password reset mail to {}: {}", user.id, e);
+        }
+
+        Ok(user)
+    }
+
+    /// Adds the user to the staff of an apothecary. Customers become apothecary users.
+    pub async fn link_apothecary(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        link: ApothecaryLink,
+        ip: Option<String>,
+    ) -> Result<Membership, AdminServiceError> {
+        let user = self.get_user(user_id).await?;
+
+        let apothecary = entity::apothecary::Entity::find_by_id(link.apothecary_id)
+            .one(&self.db)
+            .await?
+            .ok_or(AdminServiceError::ApothecaryNotFound)?;
+
+        if find_membership(&self.db, user.id, apothecary.id)
+            .await?
+            .is_some()
+        {
+            return Err(AdminServiceError::AlreadyMember);
+        }
+
+        let role = link.role.into();
+
+        let txn = self.db.begin().await?;
+
+        apothecary_user::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            apothecary_id: Set(apothecary.id),
+            user_id: Set(user.id),
+            role: Set(role),
+        }
+        .insert(&txn)
+        .await?;
+
+        if user.user_type == UserType::Customer {
+            let mut user: entity::user::ActiveModel = user.clone().into();
+            user.user_type = Set(UserType::Apothecary);
+            user.update(&txn).await?;
+        }
+
+        audit::record_admin_action(
+            &txn,
+            admin_id,
+            user.id,
+            AuditEventKind::ApothecaryLinked,
+            ip,
+            format!("Linked to {} as {}", apothecary.name, role_name(role)),
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(Membership {
+            apothecary_id: apothecary.id,
+            apothecary_name: apothecary.name,
+            role: role.into(),
+        })
+    }
+
+    /// Removes the user from the staff of an apothecary. Apothecaries keep at least one owner.
+    pub async fn unlink_apothecary(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        ip: Option<String>,
+    ) -> Result<(), AdminServiceError> {
+        let txn = self.db.begin().await?;
+
+        let membership = find_membership(&txn, user_id, apothecary_id)
+            .await?
+            .ok_or(AdminServiceError::MembershipNotFound)?;
+
+        if is_last_owner(&txn, &membership).await? {
+            return Err(AdminServiceError::LastOwner);
+        }
+
+        let apothecary = entity::apothecary::Entity::find_by_id(apothecary_id)
+            .one(&txn)
+            .await?
+            .ok_or(AdminServiceError::ApothecaryNotFound)?;
+
+        membership.delete(&txn).await?;
+
+        revert_if_unaffiliated(&txn, user_id).await?;
+
+        audit::record_admin_action(
+            &txn,
+            admin_id,
+            user_id,
+            AuditEventKind::ApothecaryUnlinked,
+            ip,
+            format!("Unlinked from {}", apothecary.name),
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_other_user(
+        &self,
+        admin_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<User, AdminServiceError> {
+        if admin_id == user_id {
+            return Err(AdminServiceError::OwnAccount);
+        }
+
+        self.get_user(user_id).await
+    }
+}
+
+fn type_name(user_type: &UserType) -> &'static str {
+    match user_type {
+        UserType::Admin => "admin",
+        UserType::Apothecary => "apothecary",
+        UserType::Customer => "customer",
+    }
+}
+
+/// Escapes the wildcards of a `LIKE` pattern, so search terms match literally.
+pub(crate) fn escape_like(term: &str) -> String {
+    term.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        mail::{InMemoryMailer, Mailer},
+        test_support,
+    };
+
+    use super::*;
+
+    const IP: &str = "203.0.113.7";
+
+    async fn service() -> (AdminService, Arc<InMemoryMailer>, DatabaseConnection) {
+        let db = test_support::db().await;
+        let mailer = Arc::new(InMemoryMailer::default());
+        let service = AdminService::new(
+            db.clone(),
+            Arc::new(test_support::settings()),
+            mailer.clone() as Arc<dyn Mailer>,
+        );
+
+        (service, mailer, db)
+    }
+
+    async fn admin(db: &DatabaseConnection) -> User {
+        entity::user::Entity::find()
+            .filter(entity::user::Column::Email.eq("admin@email.com"))
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn user_named(db: &DatabaseConnection, name: &str, email: &str) -> Uuid {
+        let id = test_support::customer(db, email).await;
+
+        entity::user::Entity::update_many()
+            .col_expr(entity::user::Column::Name, Expr::value(name))
+            .filter(entity::user::Column::Id.eq(id))
+            .exec(db)
+            .await
+            .unwrap();
+
+        id
+    }
+
+    async fn search(service: &AdminService, term: &str) -> Vec<String> {
+        let search = UserSearch {
+            search: Some(term.to_owned()),
+            ..Default::default()
+        };
+
+        let mut emails: Vec<_> = service
+            .get_users(search, None)
+            .await
+            .ok()
+            .unwrap()
+            .content
+            .into_iter()
+            .map(|u| u.email)
+            .collect();
+        emails.sort();
+
+        emails
+    }
+
+    #[tokio::test]
+    async fn search_terms_match_literally() {
+        let (service, _, db) = service().await;
+        user_named(&db, "Ann_Lee", "ann@example.com").await;
+        user_named(&db, "Annxlee", "annx@example.com").await;
+        user_named(&db, "Full 100% Care", "care@example.com").await;
+        user_named(&db, "Back\\slash", "back@example.com").await;
+
+        assert_eq!(search(&service, "ann_").await, ["ann@example.com"]);
+        assert_eq!(search(&service, " ANN_LEE ").await, ["ann@example.com"]);
+        assert_eq!(search(&service, "100%").await, ["care@example.com"]);
+        assert_eq!(search(&service, "%").await, ["care@example.com"]);
+        assert_eq!(search(&service, "k\\s").await, ["back@example.com"]);
+        assert_eq!(
+            search(&service, "ANNX@").await,
+            ["annx@example.com"],
+            "emails match as well"
+        );
+    }
+
+    #[tokio::test]
+    async fn admins_cannot_act_on_their_own_account() {
+        let (service, mailer, db) = service().await;
+        let admin = admin(&db).await;
+
+        assert!(matches!(
+            service
+                .set_user_type(admin.id, admin.id, UserType::Customer, None)
+                .await,
+            Err(AdminServiceError::OwnAccount)
+        ));
+        assert!(matches!(
+            service.set_disabled(admin.id, admin.id, true, None).await,
+            Err(AdminServiceError::OwnAccount)
+        ));
+        assert!(matches!(
+            service.force_password_reset(admin.id, admin.id, None).await,
+            Err(AdminServiceError::OwnAccount)
+        ));
+
+        assert_eq!(service.get_user(admin.id).await.ok().unwrap(), admin);
+        assert!(mailer.messages().is_empty());
+        assert_eq!(
+            service
+                .get_audit_events(admin.id, None)
+                .await
+                .ok()
+                .unwrap()
+                .total_elements,
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn admin_actions_are_audited() {
+        let (service, mailer, db) = service().await;
+        let admin = admin(&db).await;
+        let jane = test_support::customer(&db, "jane@example.com").await;
+        let rudolf = entity::apothecary::Entity::find()
+            .filter(entity::apothecary::Column::Name.eq("St. Rudolf"))
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        let ip = || Some(IP.to_owned());
+
+        service
+            .set_user_type(admin.id, jane, UserType::Admin, ip())
+            .await
+            .ok()
+            .unwrap();
+        // Changes to the current state are not recorded.
+        service
+            .set_user_type(admin.id, jane, UserType::Admin, ip())
+            .await
+            .ok()
+            .unwrap();
+        service
+            .set_user_type(admin.id, jane, UserType::Customer, ip())
+            .await
+            .ok()
+            .unwrap();
+        service
+            .set_disabled(admin.id, jane, true, ip())
+            .await
+            .ok()
+            .unwrap();
+        service
+            .set_disabled(admin.id, jane, false, ip())
+            .await
+            .ok()
+            .unwrap();
+        service
+            .force_password_reset(admin.id, jane, ip())
+            .await
+            .ok()
+            .unwrap();
+        service
+            .link_apothecary(
+                admin.id,
+                jane,
+                ApothecaryLink {
+                    apothecary_id: rudolf,
+                    role: dto::staff::StaffRole::Pharmacist,
+                },
+                ip(),
+            )
+            .await
+            .ok()
+            .unwrap();
+        service
+            .unlink_apothecary(admin.id, jane, rudolf, ip())
+            .await
+            .ok()
+            .unwrap();
+
+        let mut events = service
+            .get_audit_events(jane, None)
+            .await
+            .ok()
+            .unwrap()
+            .content;
+        events.sort_by_key(|e| e.created_at);
+
+        let recorded: Vec<_> = events.iter().map(|e| (e.kind, e.detail.as_str())).collect();
+        assert_eq!(
+            recorded,
+            [
+                (
+                    AuditEventKind::UserTypeChanged,
+                    "Changed from customer to admin"
+                ),
+                (
+                    AuditEventKind::UserTypeChanged,
+                    "Changed from admin to customer"
+                ),
+                (AuditEventKind::AccountDisabled, "Account disabled"),
+                (AuditEventKind::AccountEnabled, "Account enabled"),
+                (AuditEventKind::PasswordResetForced, "Password reset forced"),
+                (
+                    AuditEventKind::ApothecaryLinked,
+                    "Linked to St. Rudolf as pharmacist"
+                ),
+                (
+                    AuditEventKind::ApothecaryUnlinked,
+                    "Unlinked from St. Rudolf"
+                ),
+            ]
+        );
+        assert!(events.iter().all(|e| e.user_id == Some(jane)
+            && e.actor_id == Some(admin.id)
+            && e.ip.as_deref() == Some(IP)));
+
+        assert_eq!(mailer.messages().len(), 1);
+        assert_eq!(mailer.messages()[0].to, "jane@example.com");
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt::Display;
+
+use entity::{api_key, apothecary_user::StaffRole, DatabaseConnection};
+use sea_orm::{entity::prelude::*, sea_query::Expr, QueryOrder, Set};
+use time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    hash::{random_token, sha256_hex},
+    staff::find_membership,
+    user::now,
+};
+
+pub use entity::api_key::{ApiKeyScope, Model as ApiKey};
+
+/// Distinguishes keys from other tokens, e.g. for secret scanners.
+const KEY_PREFIX: &str = "ptk_";
+/// Characters of a key that are stored in the clear to identify it.
+const VISIBLE_LENGTH: usize = 12;
+
+pub enum ApiKeyServiceError {
+    /// The user is not an owner of the apothecary.
+    Forbidden,
+    KeyNotFound,
+    InvalidKey,
+    Anyhow(anyhow::Error),
+}
+
+impl From<DbErr> for ApiKeyServiceError {
+    fn from(err: DbErr) -> Self {
+        Self::Anyhow(err.into())
+    }
+}
+
+impl Display for ApiKeyServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiKeyServiceError::Forbidden => write!(f, "Not allowed for this apothecary"),
+            ApiKeyServiceError::KeyNotFound => write!(f, "API key not found"),
+            ApiKeyServiceError::InvalidKey => write!(f, "Invalid or expired API key"),
+            ApiKeyServiceError::Anyhow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// API keys let an apothecary's own systems, such as an ERP syncing stock, access its
+/// inventory without a user login. They are managed by the apothecary's owners.
+pub struct ApiKeyService {
+    db: DatabaseConnection,
+}
+
+impl ApiKeyService {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    pub async fn get_keys(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+    ) -> Result<Vec<ApiKey>, ApiKeyServiceError> {
+        self.require_owner(user_id, apothecary_id).await?;
+
+        Ok(api_key::Entity::find()
+            .filter(api_key::Column::ApothecaryId.eq(apothecary_id))
+            .order_by_asc(api_key::Column::CreatedAt)
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Creates a key and returns it along with the secret, which is not stored.
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        request: dto::api_key::ApiKeyRequest,
+    ) -> Result<(ApiKey, String), ApiKeyServiceError> {
+        self.require_owner(user_id, apothecary_id).await?;
+
+        let now = now();
+        let key = format!("{}{}", KEY_PREFIX, random_token());
+
+        let api_key = api_key::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            apothecary_id: Set(apothecary_id),
+            name: Set(request.name),
+            prefix: Set(key[..VISIBLE_LENGTH].to_owned()),
+            key_hash: Set(sha256_hex(&key)),
+            scope: Set(request.scope.into()),
+            created_by: Set(Some(user_id)),
+            created_at: Set(now),
+            expires_at: Set(request
+                .expires_in_days
+                .map(|days| now + Duration::days(days.into()))),
+            last_used_at: Set(None),
+        }
+        .insert(&self.db)
+        .await?;
+
+        Ok((api_key, key))
+    }
+
+    pub async fn revoke(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        key_id: Uuid,
+    ) -> Result<(), ApiKeyServiceError> {
+        self.require_owner(user_id, apothecary_id).await?;
+
+        let result = api_key::Entity::delete_many()
+            .filter(api_key::Column::Id.eq(key_id))
+            .filter(api_key::Column::ApothecaryId.eq(apothecary_id))
+            .exec(&self.db)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(ApiKeyServiceError::KeyNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a key presented by a client and records that it was used.
+    pub async fn authenticate(&self, key: &str) -> Result<ApiKey, ApiKeyServiceError> {
+        let now = now();
+
+        if !key.starts_with(KEY_PREFIX) {
+            return Err(ApiKeyServiceError::InvalidKey);
+        }
+
+        let api_key = api_key::Entity::find()
+            .filter(api_key::Column::KeyHash.eq(sha256_hex(key)))
+            .one(&self.db)
+            .await?
+            .filter(|k| k.expires_at.is_none_or(|expires_at| expires_at > now))
+            .ok_or(ApiKeyServiceError::InvalidKey)?;
+
+        api_key::Entity::update_many()
+            .col_expr(api_key::Column::LastUsedAt, Expr::value(Some(now)))
+            .filter(api_key::Column::Id.eq(api_key.id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(api_key)
+    }
+
+    async fn require_owner(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+    ) -> Result<(), ApiKeyServiceError> {
+        find_membership(&self.db, user_id, apothecary_id)
+            .await?
+            .filter(|membership| membership.role == StaffRole::Owner)
+            .ok_or(ApiKeyServiceError::Forbidden)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dto::api_key::ApiKeyRequest;
+    use entity::apothecary_user;
+
+    use super::*;
+    use crate::test_support;
+
+    /// The seeded owner `john@apo.com` and their apothecary.
+    async fn owner(db: &DatabaseConnection) -> (Uuid, Uuid) {
+        let membership = apothecary_user::Entity::find()
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        (membership.user_id, membership.apothecary_id)
+    }
+
+    fn request(scope: dto::api_key::ApiKeyScope) -> ApiKeyRequest {
+        ApiKeyRequest {
+            name: "ERP".to_owned(),
+            scope,
+            expires_in_days: Some(30),
+        }
+    }
+
+    #[tokio::test]
+    async fn keys_are_stored_hashed_and_record_their_use() {
+        let db = test_support::db().await;
+        let service = ApiKeyService::new(db.clone());
+        let (user_id, apothecary_id) = owner(&db).await;
+
+        let (created, key) = service
+            .create(
+                user_id,
+                apothecary_id,
+                request(dto::api_key::ApiKeyScope::InventoryRead),
+            )
+            .await
+            .ok()
+            .unwrap();
+
+        assert!(key.starts_with(KEY_PREFIX));
+        assert_eq!(created.prefix, key[..VISIBLE_LENGTH]);
+        assert_eq!(created.key_hash, sha256_hex(&key));
+        assert_ne!(created.key_hash, key);
+        assert!(created.last_used_at.is_none());
+
+        let authenticated = service.authenticate(&key).await.ok().unwrap();
+        assert_eq!(authenticated.id, created.id);
+        assert_eq!(authenticated.apothecary_id, apothecary_id);
+
+        let stored = api_key::Entity::find_by_id(created.id)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stored.last_used_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn unknown_and_malformed_keys_are_rejected() {
+        let db = test_support::db().await;
+        let service = ApiKeyService::new(db.clone());
+        let (user_id, apothecary_id) = owner(&db).await;
+
+        let (_, key) = service
+            .create(
+                user_id,
+                apothecary_id,
+                request(dto::api_key::ApiKeyScope::InventoryRead),
+            )
+            .await
+            .ok()
+            .unwrap();
+
+        let without_prefix = key.trim_start_matches(KEY_PREFIX);
+        let tampered = format!("{}x", key);
+
+        for key in [without_prefix, tampered.as_str(), ""] {
+            assert!(matches!(
+                service.authenticate(key).await,
+                Err(ApiKeyServiceError::InvalidKey)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_rejected() {
+        let db = test_support::db().await;
+        let service = ApiKeyService::new(db.clone());
+        let (user_id, apothecary_id) = owner(&db).await;
+
+        let (created, key) = service
+            .create(
+                user_id,
+                apothecary_id,
+                request(dto::api_key::ApiKeyScope::InventoryWrite),
+            )
+            .await
+            .ok()
+            .unwrap();
+
+        api_key::ActiveModel {
+            id: Set(created.id),
+            expires_at: Set(Some(now() - Duration::minutes(1))),
+            ..Default::default()
+        }
+        .update(&db)
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            service.authenticate(&key).await,
+            Err(ApiKeyServiceError::InvalidKey)
+        ));
+
+        let stored = api_key::Entity::find_by_id(created.id)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stored.last_used_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn only_owners_create_keys() {
+        let db = test_support::db().await;
+        let service = ApiKeyService::new(db.clone());
+        let (_, apothecary_id) = owner(&db).await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+
+        assert!(matches!(
+            service
+                .create(
+                    customer,
+                    apothecary_id,
+                    request(dto::api_key::ApiKeyScope::InventoryRead),
+                )
+                .await,
+            Err(ApiKeyServiceError::Forbidden)
+        ));
+    }
+
+    #[test]
+    fn write_scope_includes_read() {
+        assert!(ApiKeyScope::InventoryRead.allows(ApiKeyScope::InventoryRead));
+        assert!(!ApiKeyScope::InventoryRead.allows(ApiKeyScope::InventoryWrite));
+        assert!(ApiKeyScope::InventoryWrite.allows(ApiKeyScope::InventoryRead));
+        assert!(ApiKeyScope::InventoryWrite.allows(ApiKeyScope::InventoryWrite));
+    }
+}
@@ -1,37 +1,40 @@
 use std::{collections::HashMap, fmt::Display};
 
-use anyhow::anyhow;
 use dto::{
-    apothecary,
     medication::{
-        self, MedicationDetail, MedicationDetailWithQuantity, MedicationSearch,
+        ApothecaryInventory, MedicationDetailWithQuantity, MedicationQuantity, MedicationSearch,
         MedicationSearchCda, MedicationSearchResult, MedicationSearchResultList,
     },
-    page::Pageable,
+    page::{Pageable, SortDirection},
 };
-use quick_xml::NsReader;
 use sea_orm::{
-    sea_query::{
-        self, extension::postgres::PgExpr, Expr, Func, IntoCondition, LikeExpr, SimpleExpr,
-    },
-    ColumnTrait, Condition, DatabaseConnection, DbErr, EntityTrait, Iden, JoinType, ModelTrait,
-    QueryFilter, QuerySelect, RelationTrait, RuntimeErr, Values,
+    sea_query::{Expr, Func, LikeExpr, Query},
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, QuerySelect, Set, TransactionTrait,
 };
 
 pub use entity::apothecary::Model as Apothecary;
 pub use entity::schedule::Model as Schedule;
 use entity::{
     apothecary::{ApothecaryWithSchedules, Entity},
-    apothecary_medication,
+    apothecary_medication::{self, QuantityType},
+    reservation::ReservationStatus,
 };
-use tracing::{debug, field::debug, warn};
+use tracing::warn;
 use uuid::Uuid;
 
-use crate::page::{Page, PageError};
+use crate::{
+    admin::escape_like,
+    page::{Page, PageError},
+};
 
 pub enum ApothecaryServiceError {
     NotFound,
+    MedicationNotFound,
+    /// Stock can only be recorded in packages or as unknown.
+    UnsupportedQuantity,
     InvalidSortColumn(String),
+    InvalidCursor,
     InvalidXml,
     Anyhow(anyhow::Error),
 }
@@ -40,7 +43,12 @@ impl Display for ApothecaryServiceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ApothecaryServiceError::NotFound => write!(f, "Apothecary not found"),
+            ApothecaryServiceError::MedicationNotFound => write!(f, "Medication not found"),
+            ApothecaryServiceError::UnsupportedQuantity => {
+                write!(f, "Stock must be given in packages or as unknown")
+            }
             ApothecaryServiceError::InvalidSortColumn(e) => write!(f, "Invalid sort column: {}", e),
+            ApothecaryServiceError::InvalidCursor => write!(f, "Invalid or outdated cursor"),
             ApothecaryServiceError::InvalidXml => write!(f, "Invalid XML"),
             ApothecaryServiceError::Anyhow(e) => write!(f, "{}", e),
         }
@@ -57,7 +65,7 @@ impl From<PageError> for ApothecaryServiceError {
     fn from(err: PageError) -> Self {
         match err {
             PageError::InvalidColumnName(e) => Self::InvalidSortColumn(e),
-            PageError::InvalidDirectionName => Self::Anyhow(anyhow::anyhow!("Invalid direction")),
+            PageError::InvalidCursor => Self::InvalidCursor,
             PageError::DbErr(e) => Self::Anyhow(anyhow::Error::from(e)),
         }
     }
@@ -73,10 +81,6 @@ pub struct ApothecaryService {
     db: DatabaseConnection,
 }
 
-#[derive(Iden)]
-#[iden = "UPPER"]
-struct Upper;
-
 impl ApothecaryService {
     pub fn new(db: DatabaseConnection) -> Self {
         Self { db }
@@ -88,112 +92,105 @@ impl ApothecaryService {
     ) -> Result<Page<(Apothecary, Vec<Schedule>)>, ApothecaryServiceError> {
         Page::<(Apothecary, Vec<Schedule>)>::paginate_two_many(
             &self.db,
-            Entity::find().find_with_related(entity::schedule::Entity),
+            Entity::find(),
+            entity::schedule::Entity,
             pageable,
         )
         .await
         .map_err(|e| e.into())
     }
 
+    /// Finds medications by name that are stocked within the distance, sorted by name unless
+    /// sorted otherwise, each with the apothecaries that stock it.
     pub async fn get_medications(
         &self,
         search_dto: MedicationSearch,
-    ) -> Result<Vec<MedicationSearchResultList>, ApothecaryServiceError> {
-        let apothecary_medications = entity::apothecary_medication::Entity::find()
+        pageable: Option<Pageable>,
+    ) -> Result<Page<MedicationSearchResultList>, ApothecaryServiceError> {
+        // There are few apothecaries, so the distance is worked out here rather than in SQL.
+        let nearby: HashMap<Uuid, (Apothecary, Vec<Schedule>)> = Entity::find()
+            .find_with_related(entity::schedule::Entity)
             .all(&self.db)
-            .await?;
-
-        let mut result: Vec<MedicationSearchResultList> = vec![];
-
-        let mut hash = HashMap::<Uuid, Vec<apothecary_medication::Model>>::new();
-
-        for apothecary_medication in apothecary_medications {
-            debug!(
-                "Apothecary medication: {:?}",
-                apothecary_medication.medication_id
-            );
-            let medication_id = apothecary_medication.medication_id.clone();
-            let mut list = hash
-                .get(&apothecary_medication.medication_id)
-                .unwrap_or(&vec![])
-                .clone();
-
-            list.push(apothecary_medication);
-
-            hash.insert(medication_id, list);
-        }
-
-        for (medication_id, apothecary_medications) in hash.into_iter() {
-            let medication = entity::medication::Entity::find_by_id(medication_id)
-                .one(&self.db)
-                .await?
-                .ok_or(ApothecaryServiceError::NotFound)?;
-
-            debug!("Medication: {:?}", medication.name);
-
-            if !medication
-                .name
-                .to_uppercase()
-                .contains(search_dto.name.to_uppercase().as_str())
-            {
-                continue;
-            }
-
-            let mut list = MedicationSearchResultList {
-                medication: medication.into(),
-                results: vec![],
-            };
-
-            for apothecary_medication in apothecary_medications {
-                let apothecary_schedules =
-                    entity::apothecary::Entity::find_by_id(apothecary_medication.apothecary_id)
-                        .find_with_related(entity::schedule::Entity)
-                        .all(&self.db)
-                        .await?;
-
-                if apothecary_schedules.len() != 1 {
-                    return Err(ApothecaryServiceError::Anyhow(anyhow!(DbErr::Query(
-                        RuntimeErr::Internal("Too many apothecaries for one ID".to_owned())
-                    ))));
-                }
-
-                let apothecary = &apothecary_schedules.first().unwrap().0;
-
-                if apothecary_distance(
+            .await?
+            .into_iter()
+            .filter(|(apothecary, _)| {
+                apothecary_distance(
                     (apothecary.latitude, apothecary.longitude),
                     (search_dto.latitude, search_dto.longitude),
-                ) > search_dto.max_distance as f32
-                {
-                    continue;
-                }
-
-                let mut apothecary_schedules = apothecary_schedules;
-                let (apothecary, schedules) = apothecary_schedules.pop().unwrap();
-
-                let result = MedicationSearchResult {
-                    quantity: apothecary_medication.into(),
-                    aliases: vec![],
-                    apothecary: ApothecaryWithSchedules::from((apothecary, schedules)).into(),
-                };
+                ) <= search_dto.max_distance as f32
+            })
+            .map(|(apothecary, schedules)| (apothecary.id, (apothecary, schedules)))
+            .collect();
+
+        let pattern = LikeExpr::new(format!(
+            "%{}%",
+            escape_like(&search_dto.name.to_lowercase())
+        ))
+        .escape('\\');
+
+        let query = entity::medication::Entity::find()
+            .filter(
+                Expr::expr(Func::lower(Expr::col(entity::medication::Column::Name))).like(pattern),
+            )
+            .filter(
+                entity::medication::Column::Id.in_subquery(
+                    Query::select()
+                        .column(apothecary_medication::Column::MedicationId)
+                        .from(apothecary_medication::Entity)
+                        .and_where(
+                            apothecary_medication::Column::ApothecaryId
+                                .is_in(nearby.keys().copied()),
+                        )
+                        .to_owned(),
+                ),
+            );
+        let pageable = pageable.map(|p| p.sorted_by_default("name", SortDirection::Asc));
 
-                list.results.push(result);
-            }
+        let mut page = Page::paginate(&self.db, query, pageable).await?;
+        let medications = std::mem::take(&mut page.content);
 
-            if list.results.is_empty() {
-                continue;
-            }
+        let stock = apothecary_medication::Entity::find()
+            .filter(
+                apothecary_medication::Column::MedicationId
+                    .is_in(medications.iter().map(|medication| medication.id)),
+            )
+            .filter(apothecary_medication::Column::ApothecaryId.is_in(nearby.keys().copied()))
+            .all(&self.db)
+            .await?;
 
-            result.push(list);
-        }
+        let content = medications
+            .into_iter()
+            .map(|medication| MedicationSearchResultList {
+                results: stock
+                    .iter()
+                    .filter(|stock| stock.medication_id == medication.id)
+                    .filter_map(|stock| {
+                        let (apothecary, schedules) = nearby.get(&stock.apothecary_id)?;
+
+                        Some(MedicationSearchResult {
+                            quantity: stock.clone().into(),
+                            aliases: vec![],
+                            apothecary: ApothecaryWithSchedules::from((
+                                apothecary.clone(),
+                                schedules.clone(),
+                            ))
+                            .into(),
+                        })
+                    })
+                    .collect(),
+                medication: medication.into(),
+            })
+            .collect();
 
-        Ok(result)
+        Ok(page.with_content(content))
     }
 
     pub async fn get_medications_by_cda(
         &self,
         cda: String,
         search_dto: MedicationSearchCda,
-    ) -> Result<Vec<MedicationSearchResultList>, ApothecaryServiceError> {
+        pageable: Option<Pageable>,
+    ) -> Result<Page<MedicationSearchResultList>, ApothecaryServiceError> {
         warn!("content: {:?}", cda);
         let re = regex::Regex::new(r"<name>(.+)</name>").unwrap();
 
@@ -201,58 +198,193 @@ impl ApothecaryService {
             Some(captures) => {
                 let (_, [name]) = captures.extract();
                 warn!("Regex: {:?}", name);
-                return self
-                    .get_medications(MedicationSearch {
+                self.get_medications(
+                    MedicationSearch {
                         name: name.to_owned(),
                         latitude: search_dto.latitude,
                         longitude: search_dto.longitude,
                         max_distance: search_dto.max_distance,
-                    })
-                    .await;
+                    },
+                    pageable,
+                )
+                .await
             }
-            None => return Ok(vec![]),
+            None => Ok(Vec::new().into()),
         }
     }
 
+    /// Stock of the apothecaries the user works at, or of the one given.
     pub async fn get_own_medications(
         &self,
         user_id: Uuid,
-    ) -> Result<Vec<MedicationDetailWithQuantity>, ApothecaryServiceError> {
-        let apothecary = entity::user::Entity::find_by_id(user_id)
-            .find_with_related(entity::apothecary::Entity)
+        apothecary_id: Option<Uuid>,
+    ) -> Result<Vec<ApothecaryInventory>, ApothecaryServiceError> {
+        let mut memberships = entity::apothecary_user::Entity::find()
+            .filter(entity::apothecary_user::Column::UserId.eq(user_id));
+
+        if let Some(apothecary_id) = apothecary_id {
+            memberships =
+                memberships.filter(entity::apothecary_user::Column::ApothecaryId.eq(apothecary_id));
+        }
+
+        let apothecaries: Vec<Apothecary> = memberships
+            .find_also_related(entity::apothecary::Entity)
             .all(&self.db)
+            .await?
+            .into_iter()
+            .filter_map(|(_, apothecary)| apothecary)
+            .collect();
+
+        if apothecaries.is_empty() {
+            return Err(ApothecaryServiceError::NotFound);
+        }
+
+        self.inventories(apothecaries).await
+    }
+
+    /// Stock of one apothecary. Callers check that the user or API key may see it.
+    pub async fn get_inventory(
+        &self,
+        apothecary_id: Uuid,
+    ) -> Result<ApothecaryInventory, ApothecaryServiceError> {
+        let apothecary = Entity::find_by_id(apothecary_id)
+            .one(&self.db)
+            .await?
+            .ok_or(ApothecaryServiceError::NotFound)?;
+
+        self.inventories(vec![apothecary])
             .await?
             .pop()
-            .unwrap()
-            .1
-            .pop()
-            .unwrap();
+            .ok_or(ApothecaryServiceError::NotFound)
+    }
 
-        debug("got apo");
+    /// Sets the stock of a medication, adding it to the inventory if needed. `quantity` counts
+    /// the packages on hand, including those held by active reservations. The stored quantity
+    /// is what is left for new reservations, which is what the reservations release into again.
+    pub async fn set_stock(
+        &self,
+        apothecary_id: Uuid,
+        medication_id: Uuid,
+        quantity: MedicationQuantity,
+    ) -> Result<MedicationDetailWithQuantity, ApothecaryServiceError> {
+        let (quantity_type, amount, price) = match quantity {
+            MedicationQuantity::Package(package) => (
+                QuantityType::Package,
+                Some(
+                    i64::try_from(package.quantity)
+                        .map_err(|_| ApothecaryServiceError::UnsupportedQuantity)?,
+                ),
+                Some(package.price),
+            ),
+            MedicationQuantity::Unknown(_) => (QuantityType::Unknown, None, None),
+            MedicationQuantity::Liquid(_) => {
+                return Err(ApothecaryServiceError::UnsupportedQuantity)
+            }
+        };
 
-        let medications = apothecary
-            .find_related(entity::medication::Entity)
-            .all(&self.db)
+        Entity::find_by_id(apothecary_id)
+            .one(&self.db)
+            .await?
+            .ok_or(ApothecaryServiceError::NotFound)?;
+
+        let medication = entity::medication::Entity::find_by_id(medication_id)
+            .one(&self.db)
+            .await?
+            .ok_or(ApothecaryServiceError::MedicationNotFound)?;
+
+        let txn = self.db.begin().await?;
+
+        // Reservations take their packages from this row, locking it keeps them from doing so
+        // between counting what they hold and overwriting the quantity.
+        let exists = apothecary_medication::Entity::find_by_id((apothecary_id, medication_id))
+            .lock_exclusive()
+            .one(&txn)
+            .await?
+            .is_some();
+
+        let amount = match amount {
+            Some(amount) => Some(
+                amount
+                    .checked_sub(held_packages(&txn, apothecary_id, medication_id).await?)
+                    .ok_or(ApothecaryServiceError::UnsupportedQuantity)?,
+            ),
+            None => None,
+        };
+
+        let stock = apothecary_medication::ActiveModel {
+            apothecary_id: Set(apothecary_id),
+            medication_id: Set(medication_id),
+            medication_quantity_type: Set(quantity_type),
+            medication_quantity: Set(amount),
+            medication_price: Set(price),
+        };
+
+        let stock = if exists {
+            stock.update(&txn).await?
+        } else {
+            stock.insert(&txn).await?
+        };
+
+        txn.commit().await?;
+
+        Ok(MedicationDetailWithQuantity {
+            medication: medication.into(),
+            quantity: stock.into(),
+        })
+    }
+
+    pub async fn remove_stock(
+        &self,
+        apothecary_id: Uuid,
+        medication_id: Uuid,
+    ) -> Result<(), ApothecaryServiceError> {
+        let result = apothecary_medication::Entity::delete_by_id((apothecary_id, medication_id))
+            .exec(&self.db)
             .await?;
 
-        let mut result = vec![];
+        if result.rows_affected == 0 {
+            return Err(ApothecaryServiceError::MedicationNotFound);
+        }
 
-        for medication in medications {
-            let apothecary_medication = entity::apothecary_medication::Entity::find_by_id((
-                apothecary.id.clone(),
-                medication.id.clone(),
-            ))
-            .one(&self.db)
+        Ok(())
+    }
+
+    async fn inventories(
+        &self,
+        apothecaries: Vec<Apothecary>,
+    ) -> Result<Vec<ApothecaryInventory>, ApothecaryServiceError> {
+        let mut stock: HashMap<Uuid, Vec<MedicationDetailWithQuantity>> = HashMap::new();
+
+        for (apothecary_medication, medication) in apothecary_medication::Entity::find()
+            .filter(
+                apothecary_medication::Column::ApothecaryId
+                    .is_in(apothecaries.iter().map(|a| a.id)),
+            )
+            .find_also_related(entity::medication::Entity)
+            .all(&self.db)
             .await?
-            .unwrap();
+        {
+            let Some(medication) = medication else {
+                continue;
+            };
 
-            result.push(MedicationDetailWithQuantity {
-                medication: medication.into(),
-                quantity: apothecary_medication.into(),
-            });
+            stock
+                .entry(apothecary_medication.apothecary_id)
+                .or_default()
+                .push(MedicationDetailWithQuantity {
+                    medication: medication.into(),
+                    quantity: apothecary_medication.into(),
+                });
         }
 
-        Ok(result)
+        Ok(apothecaries
+            .into_iter()
+            .map(|apothecary| ApothecaryInventory {
+                apothecary_id: apothecary.id,
+                medications: stock.remove(&apothecary.id).unwrap_or_default(),
+                apothecary_name: apothecary.name,
+            })
+            .collect())
     }
 }
 
@@ -274,5 +406,132 @@ fn apothecary_distance(a: (f32, f32), b: (f32, f32)) -> f32 {
     .sqrt()
     .atan2(sin_lat1 * sin_lat2 + cos_lat1 * cos_lat2 * cos_delta_lng);
 
-    return EARTH_RADIUS * d;
+    EARTH_RADIUS * d
+}
+
+/// Packages of a medication that active reservations hold at the apothecary.
+async fn held_packages<C: ConnectionTrait>(
+    db: &C,
+    apothecary_id: Uuid,
+    medication_id: Uuid,
+) -> Result<i64, ApothecaryServiceError> {
+    entity::reservation::Entity::find()
+        .select_only()
+        .column(entity::reservation::Column::Quantity)
+        .filter(entity::reservation::Column::ApothecaryId.eq(apothecary_id))
+        .filter(entity::reservation::Column::MedicationId.eq(medication_id))
+        .filter(entity::reservation::Column::Status.eq(ReservationStatus::Active))
+        .filter(entity::reservation::Column::QuantityType.eq(QuantityType::Package))
+        .into_tuple::<Option<i64>>()
+        .all(db)
+        .await?
+        .into_iter()
+        .flatten()
+        .try_fold(0i64, i64::checked_add)
+        .ok_or(ApothecaryServiceError::UnsupportedQuantity)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use dto::medication::{MedicationQuantityPackage, MedicationQuantityUnknown};
+    use sea_orm::prelude::Decimal;
+
+    use super::*;
+    use crate::{reservation::ReservationService, test_support};
+
+    #[tokio::test]
+    async fn synced_stock_leaves_out_held_packages() {
+        let db = test_support::db().await;
+        let settings = Arc::new(test_support::settings());
+        let service = ApothecaryService::new(db.clone());
+        let reservations = ReservationService::new(db.clone(), settings);
+        let stock = test_support::seeded_stock(&db).await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+
+        reservations
+            .reserve(customer, None, test_support::reservation_request(&stock, 3))
+            .await
+            .ok()
+            .unwrap();
+        let reservation = test_support::reservation_of(&db, customer).await;
+
+        // The apothecary still has all 10 packages on the shelf, 3 of them held.
+        let synced = service
+            .set_stock(
+                stock.apothecary_id,
+                stock.medication_id,
+                MedicationQuantity::Package(MedicationQuantityPackage {
+                    quantity: 10,
+                    price: Decimal::new(1099, 2),
+                }),
+            )
+            .await
+            .ok()
+            .unwrap();
+        assert!(matches!(
+            synced.quantity,
+            MedicationQuantity::Package(MedicationQuantityPackage { quantity: 7, .. })
+        ));
+
+        reservations
+            .cancel(customer, reservation.id, Default::default())
+            .await
+            .ok()
+            .unwrap();
+
+        let available =
+            apothecary_medication::Entity::find_by_id((stock.apothecary_id, stock.medication_id))
+                .one(&db)
+                .await
+                .unwrap()
+                .unwrap()
+                .medication_quantity;
+        assert_eq!(available, Some(10));
+    }
+
+    #[tokio::test]
+    async fn unknown_stock_has_no_price() {
+        let db = test_support::db().await;
+        let service = ApothecaryService::new(db.clone());
+        let stock = apothecary_medication::Entity::find()
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        let find_price = || async {
+            apothecary_medication::Entity::find_by_id((stock.apothecary_id, stock.medication_id))
+                .one(&db)
+                .await
+                .unwrap()
+                .unwrap()
+                .medication_price
+        };
+
+        service
+            .set_stock(
+                stock.apothecary_id,
+                stock.medication_id,
+                MedicationQuantity::Unknown(MedicationQuantityUnknown),
+            )
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(find_price().await, None);
+
+        service
+            .set_stock(
+                stock.apothecary_id,
+                stock.medication_id,
+                MedicationQuantity::Package(MedicationQuantityPackage {
+                    quantity: 5,
+                    price: Decimal::new(1250, 2),
+                }),
+            )
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(find_price().await, Some(Decimal::new(1250, 2)));
+    }
 }
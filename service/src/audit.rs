@@ -0,0 +1,52 @@
+use entity::audit_event::AuditEventKind;
+use sea_orm::{ActiveModelTrait, ConnectionTrait, DbErr, Set};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+pub use entity::audit_event::Model as AuditEvent;
+
+/// Appends an event to the audit log.
+pub(crate) async fn record<C: ConnectionTrait>(
+    db: &C,
+    user_id: Option<Uuid>,
+    kind: AuditEventKind,
+    ip: Option<String>,
+    detail: String,
+) -> Result<AuditEvent, DbErr> {
+    insert(db, user_id, None, kind, ip, detail).await
+}
+
+/// Appends an event about an action an admin took on someone's account.
+pub(crate) async fn record_admin_action<C: ConnectionTrait>(
+    db: &C,
+    admin_id: Uuid,
+    user_id: Uuid,
+    kind: AuditEventKind,
+    ip: Option<String>,
+    detail: String,
+) -> Result<AuditEvent, DbErr> {
+    insert(db, Some(user_id), Some(admin_id), kind, ip, detail).await
+}
+
+async fn insert<C: ConnectionTrait>(
+    db: &C,
+    user_id: Option<Uuid>,
+    actor_id: Option<Uuid>,
+    kind: AuditEventKind,
+    ip: Option<String>,
+    detail: String,
+) -> Result<AuditEvent, DbErr> {
+    let now = OffsetDateTime::now_utc();
+
+    entity::audit_event::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user_id),
+        actor_id: Set(actor_id),
+        kind: Set(kind),
+        ip: Set(ip),
+        detail: Set(detail),
+        created_at: Set(PrimitiveDateTime::new(now.date(), now.time())),
+    }
+    .insert(db)
+    .await
+}
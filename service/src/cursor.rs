@@ -0,0 +1,311 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sea_orm::{prelude::Decimal, ColumnTrait, Condition, ModelTrait, Order, Value};
+use serde::{Deserialize, Serialize};
+use time::{Date, PrimitiveDateTime};
+use uuid::Uuid;
+
+/// A column of a query's ordering, together with its direction.
+pub(crate) type SortKey<C> = (C, Order);
+
+/// Points at a row of a page by the values of its sort keys. Clients only see it base64
+/// encoded, see [`encode`] and [`decode`].
+#[derive(Deserialize, Serialize)]
+struct Cursor {
+    /// The ordering the values belong to, so a cursor is not applied to a different one.
+    sort: Vec<String>,
+    values: Vec<CursorValue>,
+    /// Whether the page lies before the row rather than after it.
+    before: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+enum CursorValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Uuid(Uuid),
+    Date(Date),
+    DateTime(PrimitiveDateTime),
+    Decimal(Decimal),
+}
+
+/// Creates a cursor for the page before or after `model`. There is none if a sort key is
+/// nullable, as rows with `NULL` cannot be compared to find their neighbours.
+pub(crate) fn encode<M: ModelTrait>(
+    model: &M,
+    sort: &[SortKey<<M::Entity as sea_orm::EntityTrait>::Column>],
+    before: bool,
+) -> Option<String> {
+    let values = sort
+        .iter()
+        .map(|(column, _)| {
+            if column.def().is_null() {
+                None
+            } else {
+                CursorValue::from_value(model.get(*column))
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let cursor = Cursor {
+        sort: fingerprint(sort),
+        values,
+        before,
+    };
+
+    serde_json::to_vec(&cursor)
+        .ok()
+        .map(|json| URL_SAFE_NO_PAD.encode(json))
+}
+
+/// Returns the sort key values and direction of a cursor, if it was made for this ordering.
+pub(crate) fn decode<C: ColumnTrait>(
+    cursor: &str,
+    sort: &[SortKey<C>],
+) -> Option<(Vec<Value>, bool)> {
+    let json = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let cursor: Cursor = serde_json::from_slice(&json).ok()?;
+
+    if cursor.sort != fingerprint(sort) || cursor.values.len() != sort.len() {
+        return None;
+    }
+
+    Some((
+        cursor.values.into_iter().map(Value::from).collect(),
+        cursor.before,
+    ))
+}
+
+/// Matches the rows that come after, or before, the row with the given sort key values.
+pub(crate) fn condition<C: ColumnTrait>(
+    sort: &[SortKey<C>],
+    values: &[Value],
+    before: bool,
+) -> Condition {
+    let mut condition = Condition::any();
+
+    for (index, ((column, order), value)) in sort.iter().zip(values).enumerate() {
+        let mut tied = Condition::all();
+
+        for ((previous, _), previous_value) in sort[..index].iter().zip(values) {
+            tied = tied.add(previous.eq(previous_value.clone()));
+        }
+
+        let ascending = matches!(order, Order::Asc) != before;
+        tied = tied.add(if ascending {
+            column.gt(value.clone())
+        } else {
+            column.lt(value.clone())
+        });
+
+        condition = condition.add(tied);
+    }
+
+    condition
+}
+
+fn fingerprint<C: ColumnTrait>(sort: &[SortKey<C>]) -> Vec<String> {
+    sort.iter()
+        .map(|(column, order)| {
+            let direction = match order {
+                Order::Asc => "asc",
+                _ => "desc",
+            };
+
+            format!("{},{}", column.as_str(), direction)
+        })
+        .collect()
+}
+
+impl CursorValue {
+    fn from_value(value: Value) -> Option<Self> {
+        Some(match value {
+            Value::Bool(Some(v)) => Self::Bool(v),
+            Value::TinyInt(Some(v)) => Self::Int(v.into()),
+            Value::SmallInt(Some(v)) => Self::Int(v.into()),
+            Value::Int(Some(v)) => Self::Int(v.into()),
+            Value::BigInt(Some(v)) => Self::Int(v),
+            Value::TinyUnsigned(Some(v)) => Self::Int(v.into()),
+            Value::SmallUnsigned(Some(v)) => Self::Int(v.into()),
+            Value::Unsigned(Some(v)) => Self::Int(v.into()),
+            Value::BigUnsigned(Some(v)) => Self::Int(v.try_into().ok()?),
+            Value::Float(Some(v)) => Self::Float(v.into()),
+            Value::Double(Some(v)) => Self::Float(v),
+            Value::String(Some(v)) => Self::String(*v),
+            Value::Char(Some(v)) => Self::String(v.to_string()),
+            Value::Uuid(Some(v)) => Self::Uuid(*v),
+            Value::TimeDate(Some(v)) => Self::Date(*v),
+            Value::TimeDateTime(Some(v)) => Self::DateTime(*v),
+            Value::Decimal(Some(v)) => Self::Decimal(*v),
+            _ => return None,
+        })
+    }
+}
+
+impl From<CursorValue> for Value {
+    fn from(value: CursorValue) -> Self {
+        match value {
+            CursorValue::Bool(v) => v.into(),
+            CursorValue::Int(v) => v.into(),
+            CursorValue::Float(v) => v.into(),
+            CursorValue::String(v) => v.into(),
+            CursorValue::Uuid(v) => v.into(),
+            CursorValue::Date(v) => v.into(),
+            CursorValue::DateTime(v) => v.into(),
+            CursorValue::Decimal(v) => v.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dto::page::{Pageable, SortCriterion, SortDirection};
+    use entity::{apothecary_medication, medication};
+    use sea_orm::{ActiveModelTrait, EntityTrait, Set};
+
+    use super::*;
+    use crate::{page::Page, test_support};
+
+    fn by_name() -> Vec<SortKey<medication::Column>> {
+        vec![
+            (medication::Column::Name, Order::Asc),
+            (medication::Column::Id, Order::Asc),
+        ]
+    }
+
+    fn ibuprofen() -> medication::Model {
+        medication::Model {
+            id: Uuid::new_v4(),
+            name: "Ibuprofen".to_owned(),
+        }
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let model = ibuprofen();
+
+        for before in [false, true] {
+            let cursor = encode(&model, &by_name(), before).unwrap();
+
+            assert_eq!(
+                decode(&cursor, &by_name()),
+                Some((vec![model.name.clone().into(), model.id.into()], before))
+            );
+        }
+    }
+
+    #[test]
+    fn cursors_only_apply_to_their_ordering() {
+        let cursor = encode(&ibuprofen(), &by_name(), false).unwrap();
+
+        let descending = vec![
+            (medication::Column::Name, Order::Desc),
+            (medication::Column::Id, Order::Asc),
+        ];
+        assert_eq!(decode(&cursor, &descending), None);
+        assert_eq!(decode(&cursor, &by_name()[1..]), None);
+    }
+
+    #[test]
+    fn invalid_cursors_are_rejected() {
+        let tampered = URL_SAFE_NO_PAD.encode(
+            serde_json::to_vec(&Cursor {
+                sort: fingerprint(&by_name()),
+                values: vec![CursorValue::String("Ibuprofen".to_owned())],
+                before: false,
+            })
+            .unwrap(),
+        );
+
+        for cursor in [
+            "",
+            "not a cursor",
+            &URL_SAFE_NO_PAD.encode("{}"),
+            &URL_SAFE_NO_PAD.encode("not json"),
+            &tampered,
+        ] {
+            assert_eq!(decode(cursor, &by_name()), None, "{cursor:?}");
+        }
+    }
+
+    #[test]
+    fn nullable_sort_keys_give_no_cursor() {
+        let stock = apothecary_medication::Model {
+            apothecary_id: Uuid::new_v4(),
+            medication_id: Uuid::new_v4(),
+            medication_quantity_type: apothecary_medication::QuantityType::Package,
+            medication_quantity: Some(1),
+            medication_price: Some(Decimal::ONE),
+        };
+
+        let sort = vec![
+            (apothecary_medication::Column::MedicationPrice, Order::Asc),
+            (apothecary_medication::Column::MedicationId, Order::Asc),
+        ];
+
+        assert_eq!(encode(&stock, &sort, false), None);
+    }
+
+    #[tokio::test]
+    async fn cursors_walk_through_every_row_once() {
+        let db = test_support::db().await;
+
+        // Equal names are ordered by id.
+        for name in ["Aspirin", "Ibuprofen", "Ibuprofen", "Zinc"] {
+            medication::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                name: Set(name.to_owned()),
+            }
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+
+        let pageable = |cursor: Option<String>| {
+            Some(Pageable {
+                page: 0,
+                size: 2,
+                sort: vec![SortCriterion {
+                    field: "name".to_owned(),
+                    direction: SortDirection::Asc,
+                }],
+                cursor,
+            })
+        };
+
+        let mut expected = medication::Entity::find().all(&db).await.unwrap();
+        expected.sort_by(|a, b| (&a.name, a.id).cmp(&(&b.name, b.id)));
+
+        let mut page = Page::paginate(&db, medication::Entity::find(), pageable(None))
+            .await
+            .ok()
+            .unwrap();
+        let mut forward = page.content.clone();
+
+        while let Some(next) = page.next.clone() {
+            page = Page::paginate(&db, medication::Entity::find(), pageable(Some(next)))
+                .await
+                .ok()
+                .unwrap();
+            assert_eq!(page.total_elements, 6);
+            forward.extend(page.content.clone());
+        }
+
+        assert_eq!(forward, expected);
+        assert!(page.last);
+
+        let mut backward = page.content.clone();
+
+        while let Some(prev) = page.prev.clone() {
+            page = Page::paginate(&db, medication::Entity::find(), pageable(Some(prev)))
+                .await
+                .ok()
+                .unwrap();
+            backward.splice(0..0, page.content.clone());
+        }
+
+        assert_eq!(backward, expected);
+        assert!(page.first);
+    }
+}
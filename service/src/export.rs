@@ -0,0 +1,103 @@
+use std::fmt::Display;
+
+use dto::export::UserExport;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, QueryOrder};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+use crate::reservation::{load_details, ReservationServiceError};
+
+pub enum ExportServiceError {
+    UserNotFound,
+    Anyhow(anyhow::Error),
+}
+
+impl From<DbErr> for ExportServiceError {
+    fn from(err: DbErr) -> Self {
+        Self::Anyhow(err.into())
+    }
+}
+
+impl From<ReservationServiceError> for ExportServiceError {
+    fn from(err: ReservationServiceError) -> Self {
+        match err {
+            ReservationServiceError::Anyhow(e) => Self::Anyhow(e),
+            e => Self::Anyhow(anyhow::anyhow!("{}", e)),
+        }
+    }
+}
+
+impl Display for ExportServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportServiceError::UserNotFound => write!(f, "User not found"),
+            ExportServiceError::Anyhow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub struct ExportService {
+    db: DatabaseConnection,
+}
+
+impl ExportService {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Collects the user's profile, reservation history, missed pickups, sessions, linked
+    /// identities and audit events. Secrets such as the password hash and token hashes are left
+    /// out.
+    pub async fn export(&self, user_id: Uuid) -> Result<UserExport, ExportServiceError> {
+        let user = entity::user::Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(ExportServiceError::UserNotFound)?;
+
+        let reservations = entity::reservation::Entity::find()
+            .filter(entity::reservation::Column::UserId.eq(user_id))
+            .order_by_asc(entity::reservation::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        let no_shows = entity::no_show::Entity::find()
+            .filter(entity::no_show::Column::UserId.eq(user_id))
+            .order_by_asc(entity::no_show::Column::MissedAt)
+            .all(&self.db)
+            .await?;
+
+        let sessions = entity::refresh_token::Entity::find()
+            .filter(entity::refresh_token::Column::UserId.eq(user_id))
+            .order_by_asc(entity::refresh_token::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        let identities = entity::user_identity::Entity::find()
+            .filter(entity::user_identity::Column::UserId.eq(user_id))
+            .order_by_asc(entity::user_identity::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        let audit_events = entity::audit_event::Entity::find()
+            .filter(entity::audit_event::Column::UserId.eq(user_id))
+            .order_by_asc(entity::audit_event::Column::CreatedAt)
+            .all(&self.db)
+            .await?;
+
+        let now = OffsetDateTime::now_utc();
+
+        Ok(UserExport {
+            exported_at: PrimitiveDateTime::new(now.date(), now.time()),
+            profile: user.into(),
+            reservations: load_details(&self.db, reservations)
+                .await?
+                .into_iter()
+                .map(Into::into)
+                .collect(),
+            no_shows: no_shows.into_iter().map(Into::into).collect(),
+            sessions: sessions.into_iter().map(Into::into).collect(),
+            identities: identities.into_iter().map(Into::into).collect(),
+            audit_events: audit_events.into_iter().map(Into::into).collect(),
+        })
+    }
+}
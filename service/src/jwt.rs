@@ -1,11 +1,21 @@
 use std::{fmt::Display, sync::Arc};
 
 use entity::DatabaseConnection;
-use jsonwebtoken::{Algorithm, Validation};
+use jsonwebtoken::{Header, Validation};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, DbErr, EntityTrait, QueryFilter, QueryOrder,
+    Set, TransactionTrait,
+};
 use serde::{Deserialize, Serialize};
 use settings::Settings;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
 use uuid::Uuid;
 
+use crate::{
+    hash::{random_token, sha256_hex},
+    jwks::{KeyError, KeySet},
+};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenDetails {
     pub token: Option<String>,
@@ -14,16 +24,50 @@ pub struct TokenDetails {
     pub expires_in: Option<i64>,
 }
 
+/// An access token together with the refresh token that can be used to renew it.
+#[derive(Debug)]
+pub struct Session {
+    pub access_token: TokenDetails,
+    pub refresh_token: String,
+}
+
+impl From<Session> for dto::user::AuthTokens {
+    fn from(session: Session) -> Self {
+        Self {
+            token_type: "Bearer".to_owned(),
+            access_token: session.access_token.token.unwrap_or_default(),
+            expires_in: session.access_token.expires_in.unwrap_or_default(),
+            refresh_token: session.refresh_token,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenClaims {
     pub sub: String,
     pub exp: i64,
+    pub iat: i64,
+    pub jti: Uuid,
     pub roles: Vec<Role>,
+    /// When the user logged in, kept across refreshes. Zero for tokens issued before it was
+    /// recorded.
+    #[serde(default)]
+    pub auth_time: i64,
 }
 
 pub enum TokenError {
     Jwt(jsonwebtoken::errors::Error),
     Uuid(uuid::Error),
+    UnknownKey,
+    InvalidRefreshToken,
+    RefreshTokenReused,
+    Db(DbErr),
+}
+
+impl From<DbErr> for TokenError {
+    fn from(err: DbErr) -> Self {
+        Self::Db(err)
+    }
 }
 
 impl Display for TokenError {
@@ -31,6 +75,10 @@ impl Display for TokenError {
         match self {
             TokenError::Jwt(e) => write!(f, "Failed to parse JWT: {}", e),
             TokenError::Uuid(e) => write!(f, "Failed to parse UUID: {}", e),
+            TokenError::UnknownKey => write!(f, "Token was signed with an unknown key"),
+            TokenError::InvalidRefreshToken => write!(f, "Invalid refresh token"),
+            TokenError::RefreshTokenReused => write!(f, "Refresh token was already used"),
+            TokenError::Db(e) => write!(f, "{}", e),
         }
     }
 }
@@ -42,47 +90,397 @@ pub enum Role {
     Admin,
 }
 
+impl Role {
+    /// The roles an account of the given type holds.
+    pub fn for_user_type(user_type: &entity::user::UserType) -> Vec<Role> {
+        match user_type {
+            entity::user::UserType::Admin => vec![Role::Customer, Role::Apothecary, Role::Admin],
+            entity::user::UserType::Customer => vec![Role::Customer],
+            entity::user::UserType::Apothecary => vec![Role::Apothecary],
+        }
+    }
+}
+
 pub struct JwtService {
-    #[allow(unused)]
     db: DatabaseConnection,
     settings: Arc<Settings>,
+    keys: KeySet,
 }
 
 impl JwtService {
-    pub fn new(db: DatabaseConnection, settings: Arc<Settings>) -> Self {
-        Self { db, settings }
+    pub fn new(db: DatabaseConnection, settings: Arc<Settings>) -> Result<Self, KeyError> {
+        let keys = KeySet::load(&settings.jwt)?;
+
+        Ok(Self { db, settings, keys })
     }
 
     pub fn claims(&self, token: &str) -> Result<TokenClaims, TokenError> {
-        let validation = Validation::new(Algorithm::HS256);
+        let header = jsonwebtoken::decode_header(token).map_err(TokenError::Jwt)?;
+        let (algorithm, key) = self
+            .keys
+            .verifying_key(header.kid.as_deref())
+            .ok_or(TokenError::UnknownKey)?;
 
-        Ok(jsonwebtoken::decode::<TokenClaims>(
-            token,
-            &jsonwebtoken::DecodingKey::from_secret(self.settings.jwt.secret.as_bytes()),
-            &validation,
+        Ok(
+            jsonwebtoken::decode::<TokenClaims>(token, key, &Validation::new(algorithm))
+                .map_err(TokenError::Jwt)?
+                .claims,
         )
-        .map_err(|e| TokenError::Jwt(e))?
-        .claims)
     }
 
-    pub fn create_token(&self, user: crate::user::User) -> Result<String, TokenError> {
+    pub fn jwks(&self) -> &jsonwebtoken::jwk::JwkSet {
+        self.keys.jwks()
+    }
+
+    /// Creates an access token for a session the user logged in to at `auth_time`.
+    pub fn create_token(
+        &self,
+        user: &crate::user::User,
+        auth_time: i64,
+    ) -> Result<TokenDetails, TokenError> {
+        let now = OffsetDateTime::now_utc();
+        let expires_in = self.settings.jwt.access_token_lifetime;
+        let token_uuid = Uuid::new_v4();
+
         let claims = TokenClaims {
             sub: user.id.to_string(),
-            exp: 2000000000,
-            roles: match user.user_type {
-                entity::user::UserType::Admin => {
-                    vec![Role::Customer, Role::Apothecary, Role::Admin]
-                }
-                entity::user::UserType::Customer => vec![Role::Customer],
-                entity::user::UserType::Apothecary => vec![Role::Apothecary],
-            },
+            exp: (now + Duration::seconds(expires_in)).unix_timestamp(),
+            iat: now.unix_timestamp(),
+            jti: token_uuid,
+            roles: Role::for_user_type(&user.user_type),
+            auth_time,
         };
 
-        Ok(jsonwebtoken::encode(
-            &jsonwebtoken::Header::new(Algorithm::HS256),
-            &claims,
-            &jsonwebtoken::EncodingKey::from_secret(self.settings.jwt.secret.as_bytes()),
-        )
-        .map_err(|e| TokenError::Jwt(e))?)
+        let signing_key = self.keys.signing_key();
+        let header = Header {
+            kid: signing_key.kid.clone(),
+            ..Header::new(signing_key.algorithm)
+        };
+
+        let token =
+            jsonwebtoken::encode(&header, &claims, &signing_key.key).map_err(TokenError::Jwt)?;
+
+        Ok(TokenDetails {
+            token: Some(token),
+            token_uuid,
+            user_id: user.id,
+            expires_in: Some(expires_in),
+        })
+    }
+
+    /// Starts a new login session with a fresh refresh token family.
+    pub async fn create_session(&self, user: &crate::user::User) -> Result<Session, TokenError> {
+        let refresh_token = self
+            .insert_refresh_token(&self.db, user.id, Uuid::new_v4())
+            .await?
+            .0;
+
+        Ok(Session {
+            access_token: self.create_token(user, OffsetDateTime::now_utc().unix_timestamp())?,
+            refresh_token,
+        })
+    }
+
+    /// Exchanges a refresh token for a new session. The presented token is rotated out; if it
+    /// was already rotated out before, the whole family is revoked since the token leaked.
+    pub async fn refresh(&self, refresh_token: &str) -> Result<Session, TokenError> {
+        let existing = entity::refresh_token::Entity::find()
+            .filter(entity::refresh_token::Column::TokenHash.eq(sha256_hex(refresh_token)))
+            .one(&self.db)
+            .await?
+            .ok_or(TokenError::InvalidRefreshToken)?;
+
+        if existing.revoked_at.is_some() {
+            if existing.replaced_by.is_some() {
+                tracing::warn!(
+                    "Refresh token reuse detected for user {}, revoking family {}",
+                    existing.user_id,
+                    existing.family_id
+                );
+                self.revoke_family(existing.family_id).await?;
+                return Err(TokenError::RefreshTokenReused);
+            }
+
+            return Err(TokenError::InvalidRefreshToken);
+        }
+
+        if existing.expires_at <= now() {
+            return Err(TokenError::InvalidRefreshToken);
+        }
+
+        let user = entity::user::Entity::find_by_id(existing.user_id)
+            .one(&self.db)
+            .await?
+            .filter(|user| user.disabled_at.is_none())
+            .ok_or(TokenError::InvalidRefreshToken)?;
+
+        // The family started with the login, its first token tells when that was.
+        let auth_time = entity::refresh_token::Entity::find()
+            .filter(entity::refresh_token::Column::FamilyId.eq(existing.family_id))
+            .order_by_asc(entity::refresh_token::Column::CreatedAt)
+            .one(&self.db)
+            .await?
+            .map_or(existing.created_at, |first| first.created_at)
+            .assume_utc()
+            .unix_timestamp();
+
+        let txn = self.db.begin().await?;
+
+        let (refresh_token, replacement) = self
+            .insert_refresh_token(&txn, user.id, existing.family_id)
+            .await?;
+
+        // Only rotate if nobody else did concurrently.
+        let rotated = entity::refresh_token::Entity::update_many()
+            .col_expr(
+                entity::refresh_token::Column::RevokedAt,
+                Expr::value(Some(now())),
+            )
+            .col_expr(
+                entity::refresh_token::Column::ReplacedBy,
+                Expr::value(Some(replacement)),
+            )
+            .filter(entity::refresh_token::Column::Id.eq(existing.id))
+            .filter(entity::refresh_token::Column::RevokedAt.is_null())
+            .exec(&txn)
+            .await?;
+
+        // The token was rotated concurrently, so it was presented twice: treat it as reused.
+        if rotated.rows_affected == 0 {
+            txn.rollback().await?;
+            tracing::warn!(
+                "Concurrent refresh token use detected for user {}, revoking family {}",
+                existing.user_id,
+                existing.family_id
+            );
+            self.revoke_family(existing.family_id).await?;
+            return Err(TokenError::RefreshTokenReused);
+        }
+
+        txn.commit().await?;
+
+        Ok(Session {
+            access_token: self.create_token(&user, auth_time)?,
+            refresh_token,
+        })
+    }
+
+    /// Revokes the login session the refresh token belongs to.
+    pub async fn logout(&self, refresh_token: &str) -> Result<(), TokenError> {
+        let existing = entity::refresh_token::Entity::find()
+            .filter(entity::refresh_token::Column::TokenHash.eq(sha256_hex(refresh_token)))
+            .one(&self.db)
+            .await?
+            .ok_or(TokenError::InvalidRefreshToken)?;
+
+        self.revoke_family(existing.family_id).await
+    }
+
+    /// Revokes every login session of the user.
+    pub async fn revoke_all(&self, user_id: Uuid) -> Result<(), TokenError> {
+        entity::refresh_token::Entity::update_many()
+            .col_expr(
+                entity::refresh_token::Column::RevokedAt,
+                Expr::value(Some(now())),
+            )
+            .filter(entity::refresh_token::Column::UserId.eq(user_id))
+            .filter(entity::refresh_token::Column::RevokedAt.is_null())
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn revoke_family(&self, family_id: Uuid) -> Result<(), TokenError> {
+        entity::refresh_token::Entity::update_many()
+            .col_expr(
+                entity::refresh_token::Column::RevokedAt,
+                Expr::value(Some(now())),
+            )
+            .filter(entity::refresh_token::Column::FamilyId.eq(family_id))
+            .filter(entity::refresh_token::Column::RevokedAt.is_null())
+            .exec(&self.db)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn insert_refresh_token<C: sea_orm::ConnectionTrait>(
+        &self,
+        db: &C,
+        user_id: Uuid,
+        family_id: Uuid,
+    ) -> Result<(String, Uuid), TokenError> {
+        let token = random_token();
+        let now = now();
+
+        let inserted = entity::refresh_token::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            family_id: Set(family_id),
+            user_id: Set(user_id),
+            token_hash: Set(sha256_hex(&token)),
+            created_at: Set(now),
+            expires_at: Set(now + Duration::seconds(self.settings.jwt.refresh_token_lifetime)),
+            revoked_at: Set(None),
+            replaced_by: Set(None),
+        }
+        .insert(db)
+        .await?;
+
+        Ok((token, inserted.id))
+    }
+}
+
+fn now() -> PrimitiveDateTime {
+    let now = OffsetDateTime::now_utc();
+    PrimitiveDateTime::new(now.date(), now.time())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    async fn service_and_user(db: &DatabaseConnection) -> (JwtService, crate::user::User) {
+        let service = JwtService::new(db.clone(), Arc::new(test_support::settings()))
+            .ok()
+            .unwrap();
+        let user_id = test_support::customer(db, "jane@example.com").await;
+        let user = entity::user::Entity::find_by_id(user_id)
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        (service, user)
+    }
+
+    async fn stored(db: &DatabaseConnection, token: &str) -> entity::refresh_token::Model {
+        entity::refresh_token::Entity::find()
+            .filter(entity::refresh_token::Column::TokenHash.eq(sha256_hex(token)))
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn refresh_rotates_the_token() {
+        let db = test_support::db().await;
+        let (service, user) = service_and_user(&db).await;
+        let session = service.create_session(&user).await.ok().unwrap();
+
+        let refreshed = service.refresh(&session.refresh_token).await.ok().unwrap();
+        assert_ne!(refreshed.refresh_token, session.refresh_token);
+
+        let claims = service
+            .claims(refreshed.access_token.token.as_deref().unwrap())
+            .ok()
+            .unwrap();
+        assert_eq!(claims.sub, user.id.to_string());
+
+        let old = stored(&db, &session.refresh_token).await;
+        let new = stored(&db, &refreshed.refresh_token).await;
+        assert!(old.revoked_at.is_some());
+        assert_eq!(old.replaced_by, Some(new.id));
+        assert_eq!(new.family_id, old.family_id);
+        assert!(new.revoked_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn reused_token_revokes_the_family() {
+        let db = test_support::db().await;
+        let (service, user) = service_and_user(&db).await;
+        let session = service.create_session(&user).await.ok().unwrap();
+        let refreshed = service.refresh(&session.refresh_token).await.ok().unwrap();
+
+        assert!(matches!(
+            service.refresh(&session.refresh_token).await,
+            Err(TokenError::RefreshTokenReused)
+        ));
+
+        // The token handed out by the legitimate rotation is gone as well.
+        assert!(stored(&db, &refreshed.refresh_token)
+            .await
+            .revoked_at
+            .is_some());
+        assert!(matches!(
+            service.refresh(&refreshed.refresh_token).await,
+            Err(TokenError::InvalidRefreshToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn expired_and_unknown_tokens_are_rejected() {
+        let db = test_support::db().await;
+        let (service, user) = service_and_user(&db).await;
+        let session = service.create_session(&user).await.ok().unwrap();
+
+        let mut expired: entity::refresh_token::ActiveModel =
+            stored(&db, &session.refresh_token).await.into();
+        expired.expires_at = Set(now() - Duration::seconds(1));
+        expired.update(&db).await.unwrap();
+
+        assert!(matches!(
+            service.refresh(&session.refresh_token).await,
+            Err(TokenError::InvalidRefreshToken)
+        ));
+        assert!(matches!(
+            service.refresh("unknown").await,
+            Err(TokenError::InvalidRefreshToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn disabled_users_cannot_refresh() {
+        let db = test_support::db().await;
+        let (service, user) = service_and_user(&db).await;
+        let session = service.create_session(&user).await.ok().unwrap();
+
+        let mut disabled: entity::user::ActiveModel = user.into();
+        disabled.disabled_at = Set(Some(now()));
+        disabled.update(&db).await.unwrap();
+
+        assert!(matches!(
+            service.refresh(&session.refresh_token).await,
+            Err(TokenError::InvalidRefreshToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn logout_revokes_the_family_only() {
+        let db = test_support::db().await;
+        let (service, user) = service_and_user(&db).await;
+        let session = service.create_session(&user).await.ok().unwrap();
+        let other = service.create_session(&user).await.ok().unwrap();
+        let refreshed = service.refresh(&session.refresh_token).await.ok().unwrap();
+
+        // Logging out with an already rotated token still ends the session.
+        service.logout(&session.refresh_token).await.ok().unwrap();
+
+        assert!(matches!(
+            service.refresh(&refreshed.refresh_token).await,
+            Err(TokenError::InvalidRefreshToken)
+        ));
+        assert!(service.refresh(&other.refresh_token).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn revoke_all_ends_every_session() {
+        let db = test_support::db().await;
+        let (service, user) = service_and_user(&db).await;
+        let sessions = [
+            service.create_session(&user).await.ok().unwrap(),
+            service.create_session(&user).await.ok().unwrap(),
+        ];
+
+        service.revoke_all(user.id).await.ok().unwrap();
+
+        for session in sessions {
+            assert!(matches!(
+                service.refresh(&session.refresh_token).await,
+                Err(TokenError::InvalidRefreshToken)
+            ));
+        }
     }
 }
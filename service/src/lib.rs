@@ -1,5 +1,21 @@
+pub mod admin;
+pub mod api_key;
 pub mod apothecary;
+pub mod audit;
+mod cursor;
+pub mod export;
+mod hash;
+pub mod jwks;
 pub mod jwt;
+pub mod mail;
+pub mod notification;
+pub mod oidc;
 pub mod page;
+pub mod password;
 pub mod reservation;
+pub mod staff;
+#[cfg(test)]
+mod test_support;
+mod throttle;
+pub mod totp;
 pub mod user;
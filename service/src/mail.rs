@@ -0,0 +1,150 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+};
+use settings::Mail;
+
+#[derive(Clone, Debug)]
+pub struct MailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: MailMessage) -> anyhow::Result<()>;
+}
+
+/// Creates the SMTP mailer if a server is configured, otherwise an [`InMemoryMailer`].
+pub fn from_settings(settings: &Mail) -> anyhow::Result<Arc<dyn Mailer>> {
+    Ok(match &settings.smtp {
+        Some(_) => Arc::new(SmtpMailer::new(settings)?),
+        None => {
+            tracing::warn!("No SMTP server configured, mails are only logged");
+            Arc::new(InMemoryMailer::default())
+        }
+    })
+}
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(settings: &Mail) -> anyhow::Result<Self> {
+        let smtp = settings
+            .smtp
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No SMTP server configured"))?;
+
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?;
+
+        if let Some(port) = smtp.port {
+            transport = transport.port(port);
+        }
+
+        if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+            transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            from: settings.from.parse()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, message: MailMessage) -> anyhow::Result<()> {
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(message.to.parse()?)
+            .subject(message.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(message.body)?;
+
+        self.transport.send(email).await?;
+
+        Ok(())
+    }
+}
+
+/// Keeps sent mails in memory, for tests and local development.
+#[derive(Default)]
+pub struct InMemoryMailer {
+    messages: Mutex<Vec<MailMessage>>,
+}
+
+impl InMemoryMailer {
+    pub fn messages(&self) -> Vec<MailMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for InMemoryMailer {
+    async fn send(&self, message: MailMessage) -> anyhow::Result<()> {
+        tracing::debug!(
+            "Mail to {}: {}\n{}",
+            message.to,
+            message.subject,
+            message.body
+        );
+
+        self.messages.lock().unwrap().push(message);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(to: &str, subject: &str) -> MailMessage {
+        MailMessage {
+            to: to.to_owned(),
+            subject: subject.to_owned(),
+            body: format!("Hello {to}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_mailer_keeps_sent_messages_in_order() {
+        let mailer = InMemoryMailer::default();
+        assert!(mailer.messages().is_empty());
+
+        mailer
+            .send(message("a@example.com", "First"))
+            .await
+            .unwrap();
+        mailer
+            .send(message("b@example.com", "Second"))
+            .await
+            .unwrap();
+
+        let messages = mailer.messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].to, "a@example.com");
+        assert_eq!(messages[0].subject, "First");
+        assert_eq!(messages[0].body, "Hello a@example.com");
+        assert_eq!(messages[1].to, "b@example.com");
+        assert_eq!(messages[1].subject, "Second");
+    }
+
+    #[tokio::test]
+    async fn from_settings_without_smtp_does_not_need_a_server() {
+        let settings = crate::test_support::settings();
+
+        let mailer = from_settings(&settings.mail).unwrap();
+
+        mailer.send(message("a@example.com", "Test")).await.unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use dto::page::{Pageable, SortDirection};
+use entity::notification::NotificationKind;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, Set,
+};
+use time::{OffsetDateTime, PrimitiveDateTime};
+use uuid::Uuid;
+
+use crate::page::{Page, PageError};
+
+pub use entity::notification::Model as Notification;
+
+pub struct NotificationService {
+    db: DatabaseConnection,
+}
+
+impl NotificationService {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self { db }
+    }
+
+    /// Lists notifications for all apothecaries the user works at, newest first unless sorted
+    /// otherwise.
+    pub async fn get_for_staff(
+        &self,
+        user_id: Uuid,
+        pageable: Option<Pageable>,
+    ) -> Result<Page<Notification>, PageError> {
+        let apothecary_ids = entity::apothecary_user::Entity::find()
+            .filter(entity::apothecary_user::Column::UserId.eq(user_id))
+            .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|a| a.apothecary_id);
+
+        let query = entity::notification::Entity::find()
+            .filter(entity::notification::Column::ApothecaryId.is_in(apothecary_ids));
+        let pageable = pageable.map(|p| p.sorted_by_default("createdAt", SortDirection::Desc));
+
+        Page::paginate(&self.db, query, pageable).await
+    }
+}
+
+/// Records a notification for the staff of an apothecary. Takes a connection so it can be
+/// part of the transaction that caused it.
+pub(crate) async fn notify_apothecary<C: ConnectionTrait>(
+    db: &C,
+    apothecary_id: Uuid,
+    reservation_id: Option<Uuid>,
+    kind: NotificationKind,
+    message: String,
+) -> Result<Notification, DbErr> {
+    let now = OffsetDateTime::now_utc();
+
+    entity::notification::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        apothecary_id: Set(apothecary_id),
+        reservation_id: Set(reservation_id),
+        kind: Set(kind),
+        message: Set(message),
+        created_at: Set(PrimitiveDateTime::new(now.date(), now.time())),
+    }
+    .insert(db)
+    .await
+}
@@ -0,0 +1,554 @@
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use dto::oidc::OidcAuthorization;
+use entity::{user::UserType, user_identity, DatabaseConnection};
+use jsonwebtoken::{
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use sea_orm::{entity::prelude::*, Set, TransactionTrait};
+use serde::Deserialize;
+use settings::{OidcProvider, Settings};
+use sha2::{Digest, Sha256};
+use time::Duration;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::{
+    hash::{random_token, sha256_hex},
+    user::{hash_password, normalize_email, now, User},
+};
+
+pub enum OidcServiceError {
+    UnknownProvider,
+    InvalidState,
+    /// The provider rejected the code or returned an ID token that does not verify.
+    InvalidResponse(String),
+    EmailNotVerified,
+    /// Only customers can log in with an external identity.
+    NotAllowed,
+    /// An account with the identity's email address exists, the identity must be linked to it
+    /// from that account.
+    AccountExists,
+    /// The identity is linked to another account.
+    IdentityInUse,
+    IdentityNotFound,
+    Anyhow(anyhow::Error),
+}
+
+impl From<DbErr> for OidcServiceError {
+    fn from(err: DbErr) -> Self {
+        Self::Anyhow(err.into())
+    }
+}
+
+impl From<anyhow::Error> for OidcServiceError {
+    fn from(err: anyhow::Error) -> Self {
+        Self::Anyhow(err)
+    }
+}
+
+impl From<reqwest::Error> for OidcServiceError {
+    fn from(err: reqwest::Error) -> Self {
+        Self::Anyhow(err.into())
+    }
+}
+
+impl Display for OidcServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OidcServiceError::UnknownProvider => write!(f, "Unknown identity provider"),
+            OidcServiceError::InvalidState => write!(f, "Invalid or expired login, start again"),
+            OidcServiceError::InvalidResponse(reason) => {
+                write!(f, "Login at the identity provider failed: {}", reason)
+            }
+            OidcServiceError::EmailNotVerified => {
+                write!(f, "The identity provider did not verify the email address")
+            }
+            OidcServiceError::NotAllowed => {
+                write!(f, "This account cannot log in with an identity provider")
+            }
+            OidcServiceError::AccountExists => write!(
+                f,
+                "An account with this email address exists, log in and link the identity to it"
+            ),
+            OidcServiceError::IdentityInUse => {
+                write!(f, "The identity is linked to another account")
+            }
+            OidcServiceError::IdentityNotFound => write!(f, "Identity not found"),
+            OidcServiceError::Anyhow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Accounts created through a provider have no password to log in with until the user sets
+/// one with a reset.
+fn unusable_password() -> Result<String, OidcServiceError> {
+    hash_password(&random_token())
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e).into())
+}
+
+/// The parts of the provider's discovery document that are used.
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+pub struct OidcService {
+    db: DatabaseConnection,
+    settings: Arc<Settings>,
+    http: reqwest::Client,
+    metadata: RwLock<HashMap<String, Arc<ProviderMetadata>>>,
+    /// Signing keys by JWKS URL, fetched again when an ID token names an unknown key.
+    jwks: RwLock<HashMap<String, Arc<JwkSet>>>,
+}
+
+impl OidcService {
+    pub fn new(db: DatabaseConnection, settings: Arc<Settings>) -> Self {
+        Self {
+            db,
+            settings,
+            http: reqwest::Client::new(),
+            metadata: RwLock::default(),
+            jwks: RwLock::default(),
+        }
+    }
+
+    /// Starts an authorization code flow with PKCE. The user is sent to the returned URL and
+    /// comes back to the provider's `redirect_url` with `code` and `state`. With a `user_id`
+    /// the flow links the identity to that account and must be finished with [`Self::link`],
+    /// otherwise it logs in with [`Self::callback`].
+    pub async fn authorize(
+        &self,
+        provider: &str,
+        user_id: Option<Uuid>,
+    ) -> Result<OidcAuthorization, OidcServiceError> {
+        let provider = self.provider(provider)?;
+        let metadata = self.metadata(provider).await?;
+        let now = now();
+
+        entity::oidc_authorization::Entity::delete_many()
+            .filter(entity::oidc_authorization::Column::ExpiresAt.lte(now))
+            .exec(&self.db)
+            .await?;
+
+        let state = random_token();
+        let nonce = random_token();
+        let code_verifier = random_token();
+
+        entity::oidc_authorization::ActiveModel {
+            state_hash: Set(sha256_hex(&state)),
+            provider: Set(provider.name.clone()),
+            code_verifier: Set(code_verifier.clone()),
+            nonce: Set(nonce.clone()),
+            user_id: Set(user_id),
+            created_at: Set(now),
+            expires_at: Set(now + Duration::seconds(self.settings.oidc.authorization_lifetime)),
+        }
+        .insert(&self.db)
+        .await?;
+
+        let mut url =
+            reqwest::Url::parse(&metadata.authorization_endpoint).map_err(anyhow::Error::from)?;
+
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &provider.client_id)
+            .append_pair("redirect_uri", &provider.redirect_url)
+            .append_pair("scope", &provider.scopes.join(" "))
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair(
+                "code_challenge",
+                &URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes())),
+            )
+            .append_pair("code_challenge_method", "S256");
+
+        Ok(OidcAuthorization {
+            authorization_url: url.into(),
+            state,
+        })
+    }
+
+    /// Exchanges the code for an ID token and returns the user the identity belongs to. An
+    /// unknown identity gets a new customer account, unless an account with its email address
+    /// exists already. That account has to log in and link the identity itself.
+    pub async fn callback(
+        &self,
+        provider: &str,
+        callback: dto::oidc::OidcCallback,
+    ) -> Result<User, OidcServiceError> {
+        let provider = self.provider(provider)?;
+        let claims = self.exchange(provider, callback, None).await?;
+        let user = self.login(provider, claims).await?;
+
+        if user.user_type != UserType::Customer {
+            return Err(OidcServiceError::NotAllowed);
+        }
+
+        Ok(user)
+    }
+
+    /// Finishes a flow started by `user_id` and links the identity to their account.
+    pub async fn link(
+        &self,
+        provider: &str,
+        user_id: Uuid,
+        callback: dto::oidc::OidcCallback,
+    ) -> Result<user_identity::Model, OidcServiceError> {
+        let provider = self.provider(provider)?;
+        let claims = self.exchange(provider, callback, Some(user_id)).await?;
+        let now = now();
+        let txn = self.db.begin().await?;
+
+        let user = entity::user::Entity::find_by_id(user_id)
+            .one(&txn)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+
+        if user.user_type != UserType::Customer {
+            return Err(OidcServiceError::NotAllowed);
+        }
+
+        let email = claims.email.as_deref().map(normalize_email);
+
+        let identity = user_identity::Entity::find()
+            .filter(user_identity::Column::Provider.eq(&provider.name))
+            .filter(user_identity::Column::Subject.eq(&claims.sub))
+            .one(&txn)
+            .await?;
+
+        let identity = match identity {
+            Some(identity) if identity.user_id != user_id => {
+                return Err(OidcServiceError::IdentityInUse);
+            }
+            Some(identity) => {
+                let mut identity: user_identity::ActiveModel = identity.into();
+                identity.email = Set(email);
+                identity.update(&txn).await?
+            }
+            None => {
+                user_identity::ActiveModel {
+                    id: Set(Uuid::new_v4()),
+                    user_id: Set(user_id),
+                    provider: Set(provider.name.clone()),
+                    subject: Set(claims.sub),
+                    email: Set(email),
+                    created_at: Set(now),
+                    last_login_at: Set(now),
+                }
+                .insert(&txn)
+                .await?
+            }
+        };
+
+        txn.commit().await?;
+
+        Ok(identity)
+    }
+
+    pub async fn get_identities(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<user_identity::Model>, OidcServiceError> {
+        Ok(user_identity::Entity::find()
+            .filter(user_identity::Column::UserId.eq(user_id))
+            .all(&self.db)
+            .await?)
+    }
+
+    /// Removes the link, the account itself stays. Without a password set, it can then only be
+    /// accessed after a password reset.
+    pub async fn unlink(&self, user_id: Uuid, identity_id: Uuid) -> Result<(), OidcServiceError> {
+        let result = user_identity::Entity::delete_many()
+            .filter(user_identity::Column::Id.eq(identity_id))
+            .filter(user_identity::Column::UserId.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(OidcServiceError::IdentityNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Checks the state of a flow started with `user_id`, exchanges the code and returns the
+    /// verified claims of the ID token.
+    async fn exchange(
+        &self,
+        provider: &OidcProvider,
+        callback: dto::oidc::OidcCallback,
+        user_id: Option<Uuid>,
+    ) -> Result<IdTokenClaims, OidcServiceError> {
+        let now = now();
+
+        let authorization =
+            entity::oidc_authorization::Entity::find_by_id(sha256_hex(&callback.state))
+                .one(&self.db)
+                .await?
+                .filter(|a| a.provider == provider.name && a.user_id == user_id)
+                .ok_or(OidcServiceError::InvalidState)?;
+
+        // Deleting it first makes the state single use, even with concurrent callbacks.
+        let deleted =
+            entity::oidc_authorization::Entity::delete_by_id(authorization.state_hash.clone())
+                .exec(&self.db)
+                .await?;
+
+        if deleted.rows_affected != 1 || authorization.expires_at <= now {
+            return Err(OidcServiceError::InvalidState);
+        }
+
+        let metadata = self.metadata(provider).await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", callback.code.as_str()),
+            ("redirect_uri", provider.redirect_url.as_str()),
+            ("client_id", provider.client_id.as_str()),
+            ("code_verifier", authorization.code_verifier.as_str()),
+        ];
+
+        if let Some(client_secret) = &provider.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+
+        let response = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(OidcServiceError::InvalidResponse(format!(
+                "token endpoint returned {}",
+                response.status()
+            )));
+        }
+
+        let token: TokenResponse = response.json().await?;
+        let claims = self
+            .verify_id_token(provider, &metadata, &token.id_token)
+            .await?;
+
+        if claims.nonce.as_deref() != Some(authorization.nonce.as_str()) {
+            return Err(OidcServiceError::InvalidResponse(
+                "nonce mismatch".to_owned(),
+            ));
+        }
+
+        Ok(claims)
+    }
+
+    fn provider(&self, name: &str) -> Result<&OidcProvider, OidcServiceError> {
+        self.settings
+            .oidc
+            .providers
+            .iter()
+            .find(|p| p.name == name)
+            .ok_or(OidcServiceError::UnknownProvider)
+    }
+
+    async fn metadata(
+        &self,
+        provider: &OidcProvider,
+    ) -> Result<Arc<ProviderMetadata>, OidcServiceError> {
+        if let Some(metadata) = self.metadata.read().await.get(&provider.name) {
+            return Ok(metadata.clone());
+        }
+
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            provider.issuer.trim_end_matches('/')
+        );
+
+        let metadata: Arc<ProviderMetadata> = Arc::new(
+            self.http
+                .get(url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?,
+        );
+
+        self.metadata
+            .write()
+            .await
+            .insert(provider.name.clone(), metadata.clone());
+
+        Ok(metadata)
+    }
+
+    async fn verify_id_token(
+        &self,
+        provider: &OidcProvider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> Result<IdTokenClaims, OidcServiceError> {
+        let invalid = |reason: &str| OidcServiceError::InvalidResponse(reason.to_owned());
+
+        let header =
+            jsonwebtoken::decode_header(id_token).map_err(|_| invalid("malformed ID token"))?;
+
+        // Symmetric algorithms would verify with the client secret, which is not supported.
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(invalid("unsupported ID token algorithm"));
+        }
+
+        let cached = self.jwks.read().await.get(&metadata.jwks_uri).cloned();
+
+        // Providers rotate their keys, a key that is not known yet may have been added since.
+        let jwk = match cached
+            .as_deref()
+            .and_then(|jwks| find_key(jwks, &header.kid))
+        {
+            Some(jwk) => jwk,
+            None => {
+                let jwks: Arc<JwkSet> = Arc::new(
+                    self.http
+                        .get(&metadata.jwks_uri)
+                        .send()
+                        .await?
+                        .error_for_status()?
+                        .json()
+                        .await?,
+                );
+
+                self.jwks
+                    .write()
+                    .await
+                    .insert(metadata.jwks_uri.clone(), jwks.clone());
+
+                find_key(&jwks, &header.kid).ok_or_else(|| invalid("unknown ID token key"))?
+            }
+        };
+
+        let key = DecodingKey::from_jwk(&jwk).map_err(|_| invalid("unusable ID token key"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&provider.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+
+        jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| invalid(&e.to_string()))
+    }
+
+    async fn login(
+        &self,
+        provider: &OidcProvider,
+        claims: IdTokenClaims,
+    ) -> Result<User, OidcServiceError> {
+        let now = now();
+        let txn = self.db.begin().await?;
+
+        let identity = user_identity::Entity::find()
+            .filter(user_identity::Column::Provider.eq(&provider.name))
+            .filter(user_identity::Column::Subject.eq(&claims.sub))
+            .one(&txn)
+            .await?;
+
+        let email = claims.email.as_deref().map(normalize_email);
+
+        if let Some(identity) = identity {
+            let user_id = identity.user_id;
+
+            let mut identity: user_identity::ActiveModel = identity.into();
+            identity.last_login_at = Set(now);
+            identity.email = Set(email);
+            identity.update(&txn).await?;
+
+            let user = entity::user::Entity::find_by_id(user_id)
+                .one(&txn)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Identity without user"))?;
+
+            txn.commit().await?;
+
+            return Ok(user);
+        }
+
+        // The new account is created as verified, so the provider has to vouch for the address.
+        let Some(email) = email.filter(|_| claims.email_verified) else {
+            return Err(OidcServiceError::EmailNotVerified);
+        };
+
+        // Never take over an existing account by its address, whoever owns it has to link the
+        // identity while logged in.
+        let existing = entity::user::Entity::find()
+            .filter(entity::user::Column::Email.eq(&email))
+            .one(&txn)
+            .await?;
+
+        if existing.is_some() {
+            return Err(OidcServiceError::AccountExists);
+        }
+
+        let user = entity::user::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            name: Set(claims
+                .name
+                .clone()
+                .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_owned())),
+            email: Set(email.clone()),
+            password: Set(unusable_password()?),
+            user_type: Set(UserType::Customer),
+            email_verified_at: Set(Some(now)),
+            pending_email: Set(None),
+            disabled_at: Set(None),
+        }
+        .insert(&txn)
+        .await?;
+
+        user_identity::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(user.id),
+            provider: Set(provider.name.clone()),
+            subject: Set(claims.sub),
+            email: Set(Some(email)),
+            created_at: Set(now),
+            last_login_at: Set(now),
+        }
+        .insert(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(user)
+    }
+}
+
+/// The key an ID token was signed with, a token without `kid` needs a set with a single key.
+fn find_key(jwks: &JwkSet, kid: &Option<String>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .cloned()
+}
@@ -1,12 +1,15 @@
 use std::{fmt::Display, str::FromStr};
 
-use dto::page::{Pageable, PageableOptions, SortDirection};
+use dto::page::{Pageable, SortCriterion, SortDirection};
 use sea_orm::{
-    DatabaseConnection, DbErr, EntityTrait, FromQueryResult, PaginatorTrait, QueryOrder,
-    QuerySelect, Select, SelectTwoMany,
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, FromQueryResult, Iterable, ModelTrait,
+    Order, PaginatorTrait, PrimaryKeyToColumn, QueryFilter, QueryOrder, QuerySelect, Related,
+    Select, Value,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::cursor::{self, SortKey};
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Page<T> {
@@ -19,11 +22,13 @@ pub struct Page<T> {
     pub first: bool,
     pub number_of_elements: u64,
     pub empty: bool,
+    pub next: Option<String>,
+    pub prev: Option<String>,
 }
 
 pub enum PageError {
     InvalidColumnName(String),
-    InvalidDirectionName,
+    InvalidCursor,
     DbErr(DbErr),
 }
 
@@ -31,7 +36,7 @@ impl Display for PageError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             PageError::InvalidColumnName(e) => write!(f, "Invalid column name: {}", e),
-            PageError::InvalidDirectionName => write!(f, "Invalid direction"),
+            PageError::InvalidCursor => write!(f, "Invalid or outdated cursor"),
             PageError::DbErr(e) => write!(f, "{}", e),
         }
     }
@@ -44,13 +49,16 @@ impl From<DbErr> for PageError {
 }
 
 impl<T> Page<T> {
+    /// Fetches a page by its number, or the one next to a cursor if the pageable has one. Pages
+    /// link to their neighbours with `next` and `prev` cursors, as long as the sort columns are
+    /// not nullable.
     pub async fn paginate<'db, E: EntityTrait<Model = T>>(
         db: &'db DatabaseConnection,
         operation: Select<E>,
         pageable: Option<Pageable>,
     ) -> Result<Self, PageError>
     where
-        T: FromQueryResult + Sized + Send + Sync + 'db,
+        T: FromQueryResult + ModelTrait<Entity = E> + Sized + Send + Sync + 'db,
     {
         let Some(pageable) = pageable else {
             let items = operation.all(db).await?;
@@ -65,67 +73,120 @@ impl<T> Page<T> {
                 first: true,
                 number_of_elements,
                 empty,
+                next: None,
+                prev: None,
             });
         };
 
-        let mut operation = operation;
-
-        if let Some(sort) = pageable.sort {
-            for criterion in sort.criteria {
-                operation = operation.order_by(
-                    E::Column::from_str(&criterion.field)
-                        .map_err(|_| PageError::InvalidColumnName(criterion.field))?,
-                    match criterion.direction {
-                        SortDirection::Asc => sea_orm::Order::Asc,
-                        SortDirection::Desc => sea_orm::Order::Desc,
-                    },
-                );
-            }
+        let sort = sort_keys::<E>(pageable.sort)?;
+
+        if let Some(cursor) = pageable.cursor {
+            return Self::paginate_from_cursor(db, operation, sort, &cursor, pageable.size).await;
         }
 
-        let operation = operation;
-
-        match pageable.options {
-            PageableOptions::OffsetAndLimit((offset, limit)) => {
-                let items = operation
-                    .offset(offset)
-                    .limit(limit)
-                    .paginate(db, limit)
-                    .fetch()
-                    .await?;
-
-                Ok(Self {
-                    content: items,
-                    last: true,
-                    total_elements: 0,
-                    total_pages: 0,
-                    size: 0,
-                    number: 0,
-                    first: false,
-                    number_of_elements: 0,
-                    empty: false,
-                })
-            }
-            PageableOptions::Page(page) => {
-                let paginator = operation.paginate(db, page.per_page);
-                let items = paginator.fetch().await?;
-
-                let num_items_and_pages = paginator.num_items_and_pages().await?;
-
-                let (number_of_elements, empty) = (items.len() as u64, items.is_empty());
-
-                Ok(Self {
-                    content: items,
-                    last: page.index == num_items_and_pages.number_of_pages - 1,
-                    total_elements: num_items_and_pages.number_of_items,
-                    total_pages: num_items_and_pages.number_of_pages,
-                    size: page.per_page,
-                    number: page.index,
-                    first: page.index == 0,
-                    number_of_elements,
-                    empty,
-                })
-            }
+        let paginator = ordered(operation, &sort, false).paginate(db, pageable.size);
+        let items = paginator.fetch_page(pageable.page).await?;
+
+        let num_items_and_pages = paginator.num_items_and_pages().await?;
+
+        let (number_of_elements, empty) = (items.len() as u64, items.is_empty());
+        let (first, last) = (
+            pageable.page == 0,
+            pageable.page + 1 >= num_items_and_pages.number_of_pages,
+        );
+
+        Ok(Self {
+            next: items
+                .last()
+                .filter(|_| !last)
+                .and_then(|item| cursor::encode(item, &sort, false)),
+            prev: items
+                .first()
+                .filter(|_| !first)
+                .and_then(|item| cursor::encode(item, &sort, true)),
+            content: items,
+            last,
+            total_elements: num_items_and_pages.number_of_items,
+            total_pages: num_items_and_pages.number_of_pages,
+            size: pageable.size,
+            number: pageable.page,
+            first,
+            number_of_elements,
+            empty,
+        })
+    }
+
+    /// Fetches the rows next to the one a cursor points at by comparing sort keys instead of
+    /// skipping an offset, which stays fast on large tables and does not shift when rows are
+    /// added or removed in between. The page number is not known and always 0.
+    async fn paginate_from_cursor<'db, E: EntityTrait<Model = T>>(
+        db: &'db DatabaseConnection,
+        operation: Select<E>,
+        sort: Vec<SortKey<E::Column>>,
+        cursor: &str,
+        size: u64,
+    ) -> Result<Self, PageError>
+    where
+        T: FromQueryResult + ModelTrait<Entity = E> + Sized + Send + Sync + 'db,
+    {
+        let (values, before) = cursor::decode(cursor, &sort).ok_or(PageError::InvalidCursor)?;
+
+        let total_elements = operation.clone().count(db).await?;
+
+        let mut items = ordered(operation, &sort, before)
+            .filter(cursor::condition(&sort, &values, before))
+            .limit(size + 1)
+            .all(db)
+            .await?;
+
+        let more = items.len() as u64 > size;
+        items.truncate(size as usize);
+
+        if before {
+            items.reverse();
+        }
+
+        let (has_prev, has_next) = if before { (more, true) } else { (true, more) };
+        let next = items
+            .last()
+            .filter(|_| has_next)
+            .and_then(|item| cursor::encode(item, &sort, false));
+        let prev = items
+            .first()
+            .filter(|_| has_prev)
+            .and_then(|item| cursor::encode(item, &sort, true));
+
+        let (number_of_elements, empty) = (items.len() as u64, items.is_empty());
+
+        Ok(Self {
+            content: items,
+            last: next.is_none(),
+            total_elements,
+            total_pages: total_elements.div_ceil(size),
+            size,
+            number: 0,
+            first: prev.is_none(),
+            number_of_elements,
+            empty,
+            next,
+            prev,
+        })
+    }
+
+    /// Replaces the content of the page, keeping its metadata.
+    pub fn with_content<U>(self, content: Vec<U>) -> Page<U> {
+        Page {
+            content,
+            last: self.last,
+            total_elements: self.total_elements,
+            total_pages: self.total_pages,
+            size: self.size,
+            number: self.number,
+            first: self.first,
+            number_of_elements: self.number_of_elements,
+            empty: self.empty,
+            next: self.next,
+            prev: self.prev,
         }
     }
 
@@ -140,96 +201,92 @@ impl<T> Page<T> {
             first: self.first,
             number_of_elements: self.number_of_elements,
             empty: self.empty,
+            next: self.next,
+            prev: self.prev,
         }
     }
 }
 
 impl<T, U> Page<(T, Vec<U>)> {
-    pub async fn paginate_two_many<'db, E: EntityTrait<Model = T>, F: EntityTrait<Model = U>>(
+    /// Paginates the entities of `operation` and loads their related `F`. Counting and limiting
+    /// a join would apply to the joined rows, cutting off parents and their related entities.
+    /// `E` has to have a single column primary key.
+    pub async fn paginate_two_many<'db, E, F>(
         db: &'db DatabaseConnection,
-        operation: SelectTwoMany<E, F>,
+        operation: Select<E>,
+        related: F,
         pageable: Option<Pageable>,
     ) -> Result<Self, PageError>
     where
-        T: FromQueryResult + Sized + Send + Sync + 'db,
+        E: EntityTrait<Model = T> + Related<F>,
+        F: EntityTrait<Model = U>,
+        T: FromQueryResult + ModelTrait<Entity = E> + Sized + Send + Sync + 'db,
         U: FromQueryResult + Sized + Send + Sync + 'db,
     {
-        let Some(pageable) = pageable else {
-            let items = operation.all(db).await?;
-            let (number_of_elements, empty) = (items.len() as u64, items.is_empty());
-            return Ok(Self {
-                content: items,
-                last: true,
-                total_elements: number_of_elements,
-                total_pages: 1,
-                size: number_of_elements,
-                number: 0,
-                first: true,
-                number_of_elements,
-                empty,
-            });
+        let page = Page::paginate(db, operation, pageable).await?;
+
+        let Some(key) = E::PrimaryKey::iter()
+            .next()
+            .map(PrimaryKeyToColumn::into_column)
+        else {
+            return Ok(page.with_content(Vec::new()));
         };
+        let ids: Vec<Value> = page.content.iter().map(|parent| parent.get(key)).collect();
 
-        let mut operation = operation;
-
-        if let Some(sort) = pageable.sort {
-            for criterion in sort.criteria {
-                operation = operation.order_by(
-                    E::Column::from_str(&criterion.field)
-                        .map_err(|_| PageError::InvalidColumnName(criterion.field))?,
-                    match criterion.direction {
-                        SortDirection::Asc => sea_orm::Order::Asc,
-                        SortDirection::Desc => sea_orm::Order::Desc,
-                    },
-                );
-            }
-        }
+        let mut content = E::find()
+            .filter(key.is_in(ids.clone()))
+            .find_with_related(related)
+            .all(db)
+            .await?;
+        content.sort_by_key(|(parent, _)| ids.iter().position(|id| *id == parent.get(key)));
 
-        let operation = operation;
-
-        match pageable.options {
-            PageableOptions::OffsetAndLimit((offset, limit)) => {
-                let items = operation.offset(offset).limit(limit).all(db).await?;
-
-                let total_elements = items.len() as u64;
-
-                Ok(Self {
-                    content: items,
-                    last: true,
-                    total_elements,
-                    total_pages: 1,
-                    size: 0,
-                    number: 0,
-                    first: true,
-                    number_of_elements: total_elements,
-                    empty: total_elements == 0,
-                })
-            }
-            PageableOptions::Page(page) => {
-                let items = operation
-                    .offset(page.index * page.per_page)
-                    .limit(page.per_page)
-                    .all(db)
-                    .await?;
-
-                let total_elements = items.len() as u64;
-
-                Ok(Self {
-                    content: items,
-                    last: false,
-                    total_elements,
-                    total_pages: 1,
-                    size: page.per_page,
-                    number: page.index,
-                    first: page.index == 0,
-                    number_of_elements: total_elements,
-                    empty: total_elements == 0,
-                })
-            }
-        }
+        Ok(page.with_content(content))
     }
 }
 
+/// Resolves the requested sort of `E`, whose columns may be named in snake or camel case. The
+/// primary key comes last, so rows keep their order from one page to the next.
+fn sort_keys<E: EntityTrait>(
+    sort: Vec<SortCriterion>,
+) -> Result<Vec<SortKey<E::Column>>, PageError> {
+    let mut keys = sort
+        .into_iter()
+        .map(|criterion| {
+            let column = E::Column::from_str(&criterion.field)
+                .map_err(|_| PageError::InvalidColumnName(criterion.field))?;
+            let order = match criterion.direction {
+                SortDirection::Asc => Order::Asc,
+                SortDirection::Desc => Order::Desc,
+            };
+
+            Ok((column, order))
+        })
+        .collect::<Result<Vec<_>, PageError>>()?;
+
+    keys.extend(E::PrimaryKey::iter().map(|key| (key.into_column(), Order::Asc)));
+
+    Ok(keys)
+}
+
+/// Orders the query by the sort keys, or the opposite way to fetch rows before a cursor.
+fn ordered<E: EntityTrait>(
+    mut query: Select<E>,
+    sort: &[SortKey<E::Column>],
+    reverse: bool,
+) -> Select<E> {
+    for (column, order) in sort {
+        let order = match (order, reverse) {
+            (Order::Asc, true) => Order::Desc,
+            (Order::Desc, true) => Order::Asc,
+            (order, _) => order.clone(),
+        };
+
+        query = query.order_by(*column, order);
+    }
+
+    query
+}
+
 impl<T> From<Vec<T>> for Page<T> {
     fn from(vec: Vec<T>) -> Self {
         let (number_of_elements, empty) = (vec.len() as u64, vec.is_empty());
@@ -243,6 +300,8 @@ impl<T> From<Vec<T>> for Page<T> {
             first: true,
             number_of_elements,
             empty,
+            next: None,
+            prev: None,
         }
     }
 }
@@ -262,6 +321,163 @@ where
             first: page.first,
             number_of_elements: page.number_of_elements,
             empty: page.empty,
+            next: page.next,
+            prev: page.prev,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dto::page::{Pageable, SortCriterion, SortDirection};
+    use entity::{apothecary, medication, schedule};
+    use sea_orm::{ActiveModelTrait, Set};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::test_support;
+
+    fn by_name(page: u64, size: u64) -> Option<Pageable> {
+        Some(Pageable {
+            page,
+            size,
+            sort: vec![SortCriterion {
+                field: "name".to_owned(),
+                direction: SortDirection::Asc,
+            }],
+            cursor: None,
+        })
+    }
+
+    /// Adds an apothecary without schedules, it sorts before the seeded ones.
+    async fn add_apothecary(db: &DatabaseConnection) {
+        apothecary::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            name: Set("Apotheke am Markt".to_owned()),
+            latitude: Set(48.2),
+            longitude: Set(16.37),
+            street: Set("Marktgasse".to_owned()),
+            number: Set("1".to_owned()),
+            post_code: Set(1090),
+            city: Set("Wien".to_owned()),
+            country: Set("AT".to_owned()),
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn pages_with_related_entities_count_parents() {
+        let db = test_support::db().await;
+        add_apothecary(&db).await;
+
+        let mut pages = Vec::new();
+        for number in 0..3 {
+            let page = Page::paginate_two_many(
+                &db,
+                apothecary::Entity::find(),
+                schedule::Entity,
+                by_name(number, 1),
+            )
+            .await
+            .ok()
+            .unwrap();
+
+            assert_eq!(page.total_elements, 3);
+            assert_eq!(page.total_pages, 3);
+            assert_eq!(page.number_of_elements, 1);
+            assert_eq!(page.first, number == 0);
+            assert_eq!(page.last, number == 2);
+
+            pages.extend(page.content);
         }
+
+        // The seeded schedules alternate between the two apothecaries, limiting the joined rows
+        // would have cut them off.
+        let schedules: Vec<(&str, usize)> = pages
+            .iter()
+            .map(|(apothecary, schedules)| (apothecary.name.as_str(), schedules.len()))
+            .collect();
+        assert_eq!(
+            schedules,
+            [
+                ("Apotheke am Markt", 0),
+                ("St. Rudolf", 4),
+                ("Zur goldenen Krone", 3)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_with_related_entities_keep_the_sort() {
+        let db = test_support::db().await;
+
+        let page = Page::paginate_two_many(
+            &db,
+            apothecary::Entity::find(),
+            schedule::Entity,
+            Some(Pageable {
+                sort: vec![SortCriterion {
+                    field: "name".to_owned(),
+                    direction: SortDirection::Desc,
+                }],
+                ..Pageable::default()
+            }),
+        )
+        .await
+        .ok()
+        .unwrap();
+
+        let names: Vec<&str> = page
+            .content
+            .iter()
+            .map(|(apothecary, _)| apothecary.name.as_str())
+            .collect();
+        assert_eq!(names, ["Zur goldenen Krone", "St. Rudolf"]);
+        assert!(page.first && page.last);
+        assert_eq!(page.total_elements, 2);
+    }
+
+    #[tokio::test]
+    async fn offset_pages_report_their_position() {
+        let db = test_support::db().await;
+
+        let page = Page::paginate(&db, medication::Entity::find(), by_name(1, 1))
+            .await
+            .ok()
+            .unwrap();
+
+        assert_eq!(page.content[0].name, "Oleovit D3 TR");
+        assert_eq!(page.total_elements, 2);
+        assert_eq!(page.total_pages, 2);
+        assert_eq!(page.size, 1);
+        assert_eq!(page.number, 1);
+        assert!(!page.first);
+        assert!(page.last);
+        assert!(page.prev.is_some());
+        assert!(page.next.is_none());
+    }
+
+    #[tokio::test]
+    async fn unknown_sort_columns_are_rejected() {
+        let db = test_support::db().await;
+
+        let result = Page::paginate(
+            &db,
+            medication::Entity::find(),
+            Some(Pageable {
+                sort: vec![SortCriterion {
+                    field: "password".to_owned(),
+                    direction: SortDirection::Asc,
+                }],
+                ..Pageable::default()
+            }),
+        )
+        .await;
+
+        assert!(
+            matches!(result, Err(PageError::InvalidColumnName(column)) if column == "password")
+        );
     }
 }
@@ -0,0 +1,217 @@
+use std::{fmt::Display, io::ErrorKind, path::Path};
+
+use settings::PasswordPolicy;
+use sha1::{Digest, Sha1};
+
+#[derive(Clone, Debug)]
+pub enum PasswordViolation {
+    TooShort(usize),
+    TooLong(usize),
+    TooPredictable,
+    Breached,
+}
+
+impl PasswordViolation {
+    /// Stable identifier for clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            PasswordViolation::TooShort(_) => "tooShort",
+            PasswordViolation::TooLong(_) => "tooLong",
+            PasswordViolation::TooPredictable => "tooPredictable",
+            PasswordViolation::Breached => "breached",
+        }
+    }
+}
+
+impl Display for PasswordViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PasswordViolation::TooShort(min) => {
+                write!(f, "Password must be at least {} characters long", min)
+            }
+            PasswordViolation::TooLong(max) => {
+                write!(f, "Password must be at most {} characters long", max)
+            }
+            PasswordViolation::TooPredictable => {
+                write!(
+                    f,
+                    "Password is too predictable, use more or different characters"
+                )
+            }
+            PasswordViolation::Breached => {
+                write!(
+                    f,
+                    "Password appeared in a data breach, choose a different one"
+                )
+            }
+        }
+    }
+}
+
+/// Checks a new password against the policy. The breach check only looks at the corpus file
+/// for the first five hex digits of the SHA-1 hash, the same k-anonymity scheme the Pwned
+/// Passwords range API uses, so the corpus can be a mirror of it.
+pub(crate) async fn validate(
+    policy: &PasswordPolicy,
+    password: &str,
+) -> anyhow::Result<Vec<PasswordViolation>> {
+    let mut violations = vec![];
+    let length = password.chars().count();
+
+    if length < policy.min_length {
+        violations.push(PasswordViolation::TooShort(policy.min_length));
+    }
+
+    if length > policy.max_length {
+        violations.push(PasswordViolation::TooLong(policy.max_length));
+    }
+
+    if estimate_entropy(password) < policy.min_entropy_bits {
+        violations.push(PasswordViolation::TooPredictable);
+    }
+
+    if let Some(dir) = &policy.breached_passwords_dir {
+        if is_breached(Path::new(dir), password).await? {
+            violations.push(PasswordViolation::Breached);
+        }
+    }
+
+    Ok(violations)
+}
+
+/// Rough estimate: the size of the character classes in use to the power of the length,
+/// where a character repeating the previous one does not count.
+fn estimate_entropy(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().collect();
+
+    let mut pool = 0;
+
+    if chars.iter().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if chars.iter().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if chars.iter().any(|c| c.is_ascii_punctuation() || *c == ' ') {
+        pool += 33;
+    }
+    if chars.iter().any(|c| !c.is_ascii()) {
+        pool += 100;
+    }
+
+    if pool == 0 {
+        return 0.0;
+    }
+
+    let effective_length = chars
+        .iter()
+        .enumerate()
+        .filter(|(i, c)| *i == 0 || chars[i - 1] != **c)
+        .count();
+
+    effective_length as f64 * f64::from(pool).log2()
+}
+
+async fn is_breached(dir: &Path, password: &str) -> anyhow::Result<bool> {
+    let hash: String = Sha1::digest(password.as_bytes())
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    let (prefix, suffix) = hash.split_at(5);
+
+    let range = match tokio::fs::read_to_string(dir.join(prefix)).await {
+        Ok(range) => range,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(range.lines().any(|line| {
+        line.split(':')
+            .next()
+            .is_some_and(|s| s.trim().eq_ignore_ascii_case(suffix))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    async fn violations(policy: &PasswordPolicy, password: &str) -> Vec<&'static str> {
+        validate(policy, password)
+            .await
+            .unwrap()
+            .iter()
+            .map(PasswordViolation::code)
+            .collect()
+    }
+
+    /// A corpus directory with the range file of `password`, whose SHA-1 hash is
+    /// 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8.
+    fn breached_passwords_dir() -> String {
+        let dir = std::env::temp_dir().join(format!("breached-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(
+            dir.join("5BAA6"),
+            "1E4C0B93F3F0682250B6CF8331B7EE68FD8:1\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n",
+        )
+        .unwrap();
+
+        dir.to_str().unwrap().to_owned()
+    }
+
+    #[tokio::test]
+    async fn lengths_are_counted_in_characters() {
+        let policy = PasswordPolicy::default();
+
+        assert_eq!(
+            violations(&policy, "Ab1!x").await,
+            ["tooShort", "tooPredictable"]
+        );
+        assert_eq!(violations(&policy, &"Ab1!".repeat(33)).await, ["tooLong"]);
+        assert!(violations(&policy, &"Ab1!".repeat(32)).await.is_empty());
+
+        // Ten characters, but twenty bytes.
+        assert!(violations(&policy, "Grüße-Wi9").await.contains(&"tooShort"));
+        assert!(violations(&policy, "Grüße-Wie9").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn predictable_passwords_are_rejected() {
+        let policy = PasswordPolicy::default();
+
+        for password in ["1234567890", "aaaaaaaaaaaaaaaa", "aabbccddeeffgghh"] {
+            assert_eq!(
+                violations(&policy, password).await,
+                ["tooPredictable"],
+                "{password}"
+            );
+        }
+
+        for password in ["correcthorse", "Correct-Horse-Battery-9"] {
+            assert!(violations(&policy, password).await.is_empty(), "{password}");
+        }
+    }
+
+    #[tokio::test]
+    async fn breached_passwords_are_rejected() {
+        let policy = PasswordPolicy {
+            min_length: 1,
+            min_entropy_bits: 0.0,
+            breached_passwords_dir: Some(breached_passwords_dir()),
+            ..Default::default()
+        };
+
+        assert_eq!(violations(&policy, "password").await, ["breached"]);
+
+        // Passwords whose range has no file are fine.
+        assert!(violations(&policy, "Password").await.is_empty());
+        assert!(violations(&policy, "Correct-Horse-Battery-9")
+            .await
+            .is_empty());
+    }
+}
@@ -1,30 +1,71 @@
 use anyhow::anyhow;
-use dto::{medication::MedicationQuantity, reservation::MedicationReservationRequest};
+use dto::{
+    medication::MedicationQuantity,
+    page::{Pageable, SortDirection},
+    reservation::{
+        MedicationReservationCancellationRequest, MedicationReservationRequest,
+        MedicationReservationStatus, ReservationFilter,
+    },
+};
 use entity::{
-    apothecary_medication::QuantityType, reservation::ReservationWithApothecaryAndMedication,
+    apothecary_medication::QuantityType,
+    notification::NotificationKind,
+    reservation::{CancellationReason, ReservationStatus, ReservationWithApothecaryAndMedication},
 };
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, ModelTrait, QueryFilter,
-    Set,
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseConnection,
+    DbErr, EntityTrait, LoaderTrait, QueryFilter, QueryOrder, QuerySelect, Set, SqlErr,
+    TransactionTrait,
 };
-use std::fmt::Display;
+use settings::Settings;
+use std::{collections::HashMap, fmt::Display, sync::Arc};
 use time::{Duration, OffsetDateTime, PrimitiveDateTime};
 use uuid::Uuid;
 
 pub use entity::apothecary::Model as Apothecary;
 pub use entity::medication::Model as Medication;
 pub use entity::reservation::Model as Reservation;
+pub use entity::user::Model as User;
 
-use crate::page::{Page, PageError};
+use crate::{
+    hash::sha256_hex,
+    notification::notify_apothecary,
+    page::{Page, PageError},
+};
 
 pub enum ReservationServiceError {
     UserNotFound,
+    EmailNotVerified,
     MedicationNotFound,
     ReservationNotFound,
     NotEnoughAvailable,
+    IdempotencyKeyReused,
+    LimitExceeded(ReservationLimit),
+    NoShowCooldown(Duration),
+    Banned(PrimitiveDateTime),
+    NotCancellable,
+    InvalidSortColumn(String),
+    InvalidCursor,
     Anyhow(anyhow::Error),
 }
 
+/// Status recorded for a reservation created through an idempotency key.
+const CREATED_RESPONSE_STATUS: i32 = 200;
+
+pub enum ReserveOutcome {
+    Created(Box<ReservationWithApothecaryAndMedication>),
+    /// Response recorded for the first request with the same idempotency key.
+    Replayed {
+        status: u16,
+        body: String,
+    },
+}
+
+pub enum ReservationLimit {
+    ActiveReservations(u64),
+    PackagesPerMedication(u64),
+}
+
 impl From<DbErr> for ReservationServiceError {
     fn from(err: DbErr) -> Self {
         Self::Anyhow(err.into())
@@ -39,7 +80,11 @@ impl From<anyhow::Error> for ReservationServiceError {
 
 impl From<PageError> for ReservationServiceError {
     fn from(err: PageError) -> Self {
-        Self::Anyhow(anyhow!(err.to_string()))
+        match err {
+            PageError::InvalidColumnName(e) => Self::InvalidSortColumn(e),
+            PageError::InvalidCursor => Self::InvalidCursor,
+            PageError::DbErr(e) => Self::Anyhow(e.into()),
+        }
     }
 }
 
@@ -47,78 +92,324 @@ impl Display for ReservationServiceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ReservationServiceError::UserNotFound => write!(f, "User not found"),
+            ReservationServiceError::EmailNotVerified => {
+                write!(f, "Email address must be verified before reserving")
+            }
             ReservationServiceError::MedicationNotFound => write!(f, "Medication not found"),
             ReservationServiceError::ReservationNotFound => write!(f, "Reservation not found"),
             ReservationServiceError::NotEnoughAvailable => write!(f, "Not enough available"),
+            ReservationServiceError::IdempotencyKeyReused => {
+                write!(
+                    f,
+                    "Idempotency key was already used for a different request"
+                )
+            }
+            ReservationServiceError::LimitExceeded(ReservationLimit::ActiveReservations(max)) => {
+                write!(f, "At most {} active reservations are allowed", max)
+            }
+            ReservationServiceError::LimitExceeded(ReservationLimit::PackagesPerMedication(
+                max,
+            )) => {
+                write!(
+                    f,
+                    "At most {} packages of a medication can be reserved at once",
+                    max
+                )
+            }
+            ReservationServiceError::NoShowCooldown(remaining) => write!(
+                f,
+                "Reservations are blocked for {} more minutes after a missed pickup",
+                remaining.whole_minutes() + 1
+            ),
+            ReservationServiceError::Banned(until) => write!(
+                f,
+                "Reservations are suspended until {} because of repeated missed pickups",
+                until
+            ),
+            ReservationServiceError::NotCancellable => {
+                write!(f, "Reservation can no longer be cancelled")
+            }
+            ReservationServiceError::InvalidSortColumn(e) => {
+                write!(f, "Invalid sort column: {}", e)
+            }
+            ReservationServiceError::InvalidCursor => write!(f, "Invalid or outdated cursor"),
             ReservationServiceError::Anyhow(e) => write!(f, "{}", e),
         }
     }
 }
 
+/// Pickup history of a customer as seen by apothecary staff.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Reliability {
+    pub completed: u64,
+    pub no_shows: u64,
+}
+
+impl Reliability {
+    /// Share of picked up reservations in percent. Customers without history start at 100.
+    pub fn score(&self) -> u8 {
+        ((self.completed + 1) * 100 / (self.completed + self.no_shows + 1)) as u8
+    }
+}
+
+pub struct StaffReservation {
+    pub reservation: ReservationWithApothecaryAndMedication,
+    pub customer: User,
+    pub reliability: Reliability,
+}
+
+impl From<StaffReservation> for dto::reservation::ApothecaryReservation {
+    fn from(staff_reservation: StaffReservation) -> Self {
+        let reliability = staff_reservation.reliability;
+
+        Self {
+            reservation: staff_reservation.reservation.into(),
+            customer: dto::reservation::Customer {
+                id: staff_reservation.customer.id,
+                name: staff_reservation.customer.name,
+                reliability: dto::reservation::CustomerReliability {
+                    completed: reliability.completed,
+                    no_shows: reliability.no_shows,
+                    score: reliability.score(),
+                },
+            },
+        }
+    }
+}
+
 pub struct ReservationService {
     db: DatabaseConnection,
+    settings: Arc<Settings>,
 }
 
 impl ReservationService {
-    pub fn new(db: DatabaseConnection) -> Self {
-        Self { db }
+    pub fn new(db: DatabaseConnection, settings: Arc<Settings>) -> Self {
+        Self { db, settings }
     }
 
+    /// Returns a page of the user's reservations, newest first unless a sort is requested.
     pub async fn get(
         &self,
         user_id: Uuid,
+        filter: ReservationFilter,
+        pageable: Option<Pageable>,
     ) -> Result<Page<ReservationWithApothecaryAndMedication>, ReservationServiceError> {
-        let reservations = entity::reservation::Entity::find()
-            .filter(entity::reservation::Column::UserId.eq(user_id))
-            .find_with_related(entity::apothecary::Entity)
-            .all(&self.db)
-            .await?;
-
-        let mut ret = vec![];
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
 
-        for (reservation, mut apothecary) in reservations {
-            let apothecary = apothecary.pop().unwrap();
+        let mut query = entity::reservation::Entity::find()
+            .filter(entity::reservation::Column::UserId.eq(user_id));
 
-            let medication = entity::medication::Entity::find_by_id(reservation.medication_id)
-                .one(&self.db)
-                .await?
-                .ok_or(ReservationServiceError::MedicationNotFound)?;
+        if let Some(status) = filter.status {
+            query = query.filter(status_condition(status, now));
+        }
 
-            let schedules = apothecary
-                .find_related(entity::schedule::Entity)
-                .all(&self.db)
-                .await?;
+        if let Some(from) = filter.from {
+            query = query.filter(entity::reservation::Column::CreatedAt.gte(from.midnight()));
+        }
 
-            ret.push(ReservationWithApothecaryAndMedication::from((
-                reservation,
-                apothecary,
-                schedules,
-                medication,
-            )));
+        if let Some(to) = filter.to {
+            let to = to.next_day().ok_or(anyhow!("Date out of range"))?;
+            query = query.filter(entity::reservation::Column::CreatedAt.lt(to.midnight()));
         }
 
-        Ok(Page::from(ret))
+        // Sorted through the pageable rather than the query, so that cursors follow the order.
+        let pageable = pageable.map(|p| p.sorted_by_default("createdAt", SortDirection::Desc));
+
+        let mut page = Page::paginate(&self.db, query, pageable).await?;
+        let reservations = std::mem::take(&mut page.content);
+
+        Ok(page.with_content(self.with_details_many(reservations).await?))
     }
 
+    /// Creates a reservation. If an idempotency key is given, retries of the same request
+    /// replay the response of the first attempt instead of creating a new reservation.
     pub async fn reserve(
         &self,
         user_id: Uuid,
+        idempotency_key: Option<String>,
         request: MedicationReservationRequest,
+    ) -> Result<ReserveOutcome, ReservationServiceError> {
+        let Some(idempotency_key) = idempotency_key else {
+            let txn = self.db.begin().await?;
+            let reservation = self.insert_reservation(&txn, user_id, request).await?;
+            txn.commit().await?;
+
+            return Ok(ReserveOutcome::Created(Box::new(
+                self.with_details(reservation).await?,
+            )));
+        };
+
+        let request_hash = hash_request(&request)?;
+
+        if let Some(existing) = self.find_idempotency_key(user_id, &idempotency_key).await? {
+            return replay(existing, &request_hash);
+        }
+
+        let txn = self.db.begin().await?;
+
+        // A key that outlived its TTL but was not swept yet would block the insert below.
+        entity::idempotency_key::Entity::delete_many()
+            .filter(entity::idempotency_key::Column::UserId.eq(user_id))
+            .filter(entity::idempotency_key::Column::Key.eq(&idempotency_key))
+            .filter(entity::idempotency_key::Column::CreatedAt.lt(self.idempotency_key_cutoff()))
+            .exec(&txn)
+            .await?;
+
+        let reservation = self.insert_reservation(&txn, user_id, request).await?;
+        let reservation_id = reservation.id;
+
+        let details = load_details(&txn, vec![reservation])
+            .await?
+            .pop()
+            .ok_or(ReservationServiceError::ReservationNotFound)?;
+
+        let body = serde_json::to_string(&dto::reservation::MedicationReservation::from(
+            details.clone(),
+        ))
+        .map_err(anyhow::Error::from)?;
+
+        let now = OffsetDateTime::now_utc();
+
+        // Only successful responses are recorded. Failures roll back the transaction, so a
+        // retry runs the request again.
+        let inserted = entity::idempotency_key::ActiveModel {
+            user_id: Set(user_id),
+            key: Set(idempotency_key.clone()),
+            request_hash: Set(request_hash.clone()),
+            reservation_id: Set(reservation_id),
+            response_status: Set(CREATED_RESPONSE_STATUS),
+            response_body: Set(body),
+            created_at: Set(PrimitiveDateTime::new(now.date(), now.time())),
+        }
+        .insert(&txn)
+        .await;
+
+        match inserted {
+            Ok(_) => {
+                txn.commit().await?;
+                Ok(ReserveOutcome::Created(Box::new(details)))
+            }
+            Err(e) => {
+                txn.rollback().await?;
+
+                // A concurrent retry won the race; answer with its response.
+                if let Some(SqlErr::UniqueConstraintViolation(_)) = e.sql_err() {
+                    let existing = self
+                        .find_idempotency_key(user_id, &idempotency_key)
+                        .await?
+                        .ok_or(ReservationServiceError::ReservationNotFound)?;
+
+                    replay(existing, &request_hash)
+                } else {
+                    Err(e.into())
+                }
+            }
+        }
+    }
+
+    /// Forgets idempotency keys older than the configured TTL.
+    pub async fn delete_expired_idempotency_keys(&self) -> Result<u64, ReservationServiceError> {
+        Ok(entity::idempotency_key::Entity::delete_many()
+            .filter(entity::idempotency_key::Column::CreatedAt.lt(self.idempotency_key_cutoff()))
+            .exec(&self.db)
+            .await?
+            .rows_affected)
+    }
+
+    fn idempotency_key_cutoff(&self) -> PrimitiveDateTime {
+        let cutoff = OffsetDateTime::now_utc()
+            - Duration::hours(self.settings.reservation.idempotency_key_ttl_hours);
+
+        PrimitiveDateTime::new(cutoff.date(), cutoff.time())
+    }
+
+    async fn find_idempotency_key(
+        &self,
+        user_id: Uuid,
+        key: &str,
+    ) -> Result<Option<entity::idempotency_key::Model>, ReservationServiceError> {
+        Ok(
+            entity::idempotency_key::Entity::find_by_id((user_id, key.to_owned()))
+                .filter(
+                    entity::idempotency_key::Column::CreatedAt.gte(self.idempotency_key_cutoff()),
+                )
+                .one(&self.db)
+                .await?,
+        )
+    }
+
+    async fn with_details(
+        &self,
+        reservation: Reservation,
     ) -> Result<ReservationWithApothecaryAndMedication, ReservationServiceError> {
+        self.with_details_many(vec![reservation])
+            .await?
+            .pop()
+            .ok_or(ReservationServiceError::ReservationNotFound)
+    }
+
+    async fn with_details_many(
+        &self,
+        reservations: Vec<Reservation>,
+    ) -> Result<Vec<ReservationWithApothecaryAndMedication>, ReservationServiceError> {
+        load_details(&self.db, reservations).await
+    }
+
+    async fn insert_reservation<C: ConnectionTrait + TransactionTrait>(
+        &self,
+        db: &C,
+        user_id: Uuid,
+        request: MedicationReservationRequest,
+    ) -> Result<Reservation, ReservationServiceError> {
         let apothecary_medicine = entity::apothecary_medication::Entity::find_by_id((
             request.apothecary_id,
             request.medication_id,
         ))
-        .one(&self.db)
+        .one(db)
         .await?
         .ok_or(ReservationServiceError::MedicationNotFound)?;
 
+        // Locking the user serializes their reservations, so concurrent requests can't all pass
+        // the limits below before any of them is inserted. SQLite has a single writer anyway.
+        let user = entity::user::Entity::find_by_id(user_id)
+            .lock_exclusive()
+            .one(db)
+            .await?
+            .ok_or(ReservationServiceError::UserNotFound)?;
+
+        if user.email_verified_at.is_none() {
+            return Err(ReservationServiceError::EmailNotVerified);
+        }
+
+        self.check_limits(db, user_id, &request).await?;
+
         let reservation = match (
             request.quantity,
             apothecary_medicine.medication_quantity_type,
         ) {
             (MedicationQuantity::Package(package), QuantityType::Package) => {
-                if package.quantity > apothecary_medicine.medication_quantity.unwrap() as u64 {
+                let quantity = package_quantity(package.quantity)?;
+
+                let held = entity::apothecary_medication::Entity::update_many()
+                    .col_expr(
+                        entity::apothecary_medication::Column::MedicationQuantity,
+                        Expr::col(entity::apothecary_medication::Column::MedicationQuantity)
+                            .sub(quantity),
+                    )
+                    .filter(
+                        entity::apothecary_medication::Column::ApothecaryId
+                            .eq(request.apothecary_id),
+                    )
+                    .filter(
+                        entity::apothecary_medication::Column::MedicationId
+                            .eq(request.medication_id),
+                    )
+                    .filter(entity::apothecary_medication::Column::MedicationQuantity.gte(quantity))
+                    .exec(db)
+                    .await?;
+
+                if held.rows_affected == 0 {
                     return Err(ReservationServiceError::NotEnoughAvailable);
                 }
 
@@ -129,9 +420,9 @@ impl ReservationService {
                     id: Set(Uuid::new_v4()),
                     apothecary_id: Set(request.apothecary_id),
                     medication_id: Set(request.medication_id),
-                    user_id: Set(user_id),
+                    user_id: Set(Some(user_id)),
                     quantity_type: Set(apothecary_medicine.medication_quantity_type),
-                    quantity: Set(Some(package.quantity as _)),
+                    quantity: Set(Some(quantity)),
                     price: Set(apothecary_medicine.medication_price),
                     status: Set(entity::reservation::ReservationStatus::Active),
                     start_date_time: Set(Some(now)),
@@ -139,20 +430,32 @@ impl ReservationService {
                         now.checked_add(Duration::minutes(30))
                             .ok_or(anyhow!("Failed to add 30min".to_owned()))?,
                     )),
+                    cancellation_reason: Set(None),
+                    cancellation_note: Set(None),
+                    cancelled_at: Set(None),
+                    created_at: Set(now),
                 }
             }
             (MedicationQuantity::Package(package), QuantityType::Unknown) => {
+                let quantity = package_quantity(package.quantity)?;
+                let now = OffsetDateTime::now_utc();
+                let now = PrimitiveDateTime::new(now.date(), now.time());
+
                 entity::reservation::ActiveModel {
                     id: Set(Uuid::new_v4()),
                     apothecary_id: Set(request.apothecary_id),
                     medication_id: Set(request.medication_id),
-                    user_id: Set(user_id),
+                    user_id: Set(Some(user_id)),
                     quantity_type: Set(apothecary_medicine.medication_quantity_type),
-                    quantity: Set(Some(package.quantity as _)),
+                    quantity: Set(Some(quantity)),
                     price: Set(apothecary_medicine.medication_price),
                     status: Set(entity::reservation::ReservationStatus::Pending),
                     start_date_time: Set(None),
                     end_date_time: Set(None),
+                    cancellation_reason: Set(None),
+                    cancellation_note: Set(None),
+                    cancelled_at: Set(None),
+                    created_at: Set(now),
                 }
             }
             (_, _) => {
@@ -160,38 +463,1289 @@ impl ReservationService {
             }
         };
 
-        let apothecary = entity::apothecary::Entity::find_by_id(request.apothecary_id)
-            .one(&self.db)
-            .await?
-            .ok_or(ReservationServiceError::MedicationNotFound)?;
+        Ok(reservation.insert(db).await?)
+    }
+
+    /// Marks active reservations whose pickup window has passed as expired and records a
+    /// no-show for each of them.
+    pub async fn expire_overdue(&self) -> Result<u64, ReservationServiceError> {
+        self.expire_overdue_where(&self.db, Condition::all()).await
+    }
+
+    async fn expire_overdue_where<C: ConnectionTrait + TransactionTrait>(
+        &self,
+        db: &C,
+        condition: Condition,
+    ) -> Result<u64, ReservationServiceError> {
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
+
+        let overdue = entity::reservation::Entity::find()
+            .filter(condition)
+            .filter(entity::reservation::Column::Status.eq(ReservationStatus::Active))
+            .filter(entity::reservation::Column::EndDateTime.lte(now))
+            .all(db)
+            .await?;
+
+        if overdue.is_empty() {
+            return Ok(0);
+        }
+
+        let txn = db.begin().await?;
+        let mut expired = 0;
+
+        for reservation in &overdue {
+            // The sweep and the limit check of a reserving user may race for the same
+            // reservation. Only the one that actually changes the status records the no-show
+            // and releases the stock.
+            let updated = entity::reservation::Entity::update_many()
+                .col_expr(
+                    entity::reservation::Column::Status,
+                    Expr::value(ReservationStatus::Expired),
+                )
+                .filter(entity::reservation::Column::Id.eq(reservation.id))
+                .filter(entity::reservation::Column::Status.eq(ReservationStatus::Active))
+                .exec(&txn)
+                .await?;
 
-        let schedules = apothecary
-            .find_related(entity::schedule::Entity)
+            if updated.rows_affected != 1 {
+                continue;
+            }
+
+            expired += 1;
+
+            release_stock(&txn, reservation).await?;
+
+            let Some(user_id) = reservation.user_id else {
+                continue;
+            };
+
+            entity::no_show::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                user_id: Set(user_id),
+                reservation_id: Set(reservation.id),
+                apothecary_id: Set(reservation.apothecary_id),
+                missed_at: Set(reservation.end_date_time.unwrap_or(now)),
+            }
+            .insert(&txn)
+            .await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(expired)
+    }
+
+    /// Lists open reservations at the apothecaries the user works at, together with each
+    /// customer's pickup history. Oldest first unless sorted otherwise, which for active
+    /// reservations is the order they have to be picked up in.
+    pub async fn get_for_staff(
+        &self,
+        user_id: Uuid,
+        pageable: Option<Pageable>,
+    ) -> Result<Page<StaffReservation>, ReservationServiceError> {
+        let apothecary_ids = entity::apothecary_user::Entity::find()
+            .filter(entity::apothecary_user::Column::UserId.eq(user_id))
             .all(&self.db)
+            .await?
+            .into_iter()
+            .map(|a| a.apothecary_id);
+
+        let query = entity::reservation::Entity::find()
+            .filter(entity::reservation::Column::ApothecaryId.is_in(apothecary_ids))
+            .filter(
+                entity::reservation::Column::Status
+                    .is_in([ReservationStatus::Active, ReservationStatus::Pending]),
+            );
+        let pageable = pageable.map(|p| p.sorted_by_default("createdAt", SortDirection::Asc));
+
+        let mut page = Page::paginate(&self.db, query, pageable).await?;
+        let reservations = std::mem::take(&mut page.content);
+
+        let customers = reservations
+            .load_one(entity::user::Entity, &self.db)
             .await?;
 
-        let medication = entity::medication::Entity::find_by_id(request.medication_id)
-            .one(&self.db)
+        let reliabilities = self
+            .reliabilities(reservations.iter().filter_map(|r| r.user_id).collect())
+            .await?;
+
+        let content = self
+            .with_details_many(reservations)
             .await?
-            .ok_or(ReservationServiceError::MedicationNotFound)?;
+            .into_iter()
+            .zip(customers)
+            .map(|(reservation, customer)| {
+                let customer = customer.ok_or(ReservationServiceError::UserNotFound)?;
+                let reliability = reliabilities.get(&customer.id).copied().unwrap_or_default();
 
-        Ok(ReservationWithApothecaryAndMedication::from((
-            reservation.insert(&self.db).await?,
-            apothecary,
-            schedules,
-            medication,
-        )))
+                Ok(StaffReservation {
+                    reservation,
+                    customer,
+                    reliability,
+                })
+            })
+            .collect::<Result<_, ReservationServiceError>>()?;
+
+        Ok(page.with_content(content))
     }
 
-    pub async fn delete(&self, user_id: Uuid, id: Uuid) -> Result<(), ReservationServiceError> {
+    async fn reliabilities(
+        &self,
+        user_ids: Vec<Uuid>,
+    ) -> Result<HashMap<Uuid, Reliability>, ReservationServiceError> {
+        let mut reliabilities = HashMap::<Uuid, Reliability>::new();
+
+        let no_shows: Vec<(Uuid, i64)> = entity::no_show::Entity::find()
+            .select_only()
+            .column(entity::no_show::Column::UserId)
+            .column_as(Expr::col(entity::no_show::Column::Id).count(), "count")
+            .filter(entity::no_show::Column::UserId.is_in(user_ids.clone()))
+            .group_by(entity::no_show::Column::UserId)
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+
+        for (user_id, count) in no_shows {
+            reliabilities.entry(user_id).or_default().no_shows = count as u64;
+        }
+
+        let completed: Vec<(Uuid, i64)> = entity::reservation::Entity::find()
+            .select_only()
+            .column(entity::reservation::Column::UserId)
+            .column_as(Expr::col(entity::reservation::Column::Id).count(), "count")
+            .filter(entity::reservation::Column::UserId.is_in(user_ids))
+            .filter(entity::reservation::Column::Status.eq(ReservationStatus::Done))
+            .group_by(entity::reservation::Column::UserId)
+            .into_tuple()
+            .all(&self.db)
+            .await?;
+
+        for (user_id, count) in completed {
+            reliabilities.entry(user_id).or_default().completed = count as u64;
+        }
+
+        Ok(reliabilities)
+    }
+
+    async fn check_limits<C: ConnectionTrait + TransactionTrait>(
+        &self,
+        db: &C,
+        user_id: Uuid,
+        request: &MedicationReservationRequest,
+    ) -> Result<(), ReservationServiceError> {
+        let limits = &self.settings.reservation;
+
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
+
+        self.expire_overdue_where(
+            db,
+            Condition::all().add(entity::reservation::Column::UserId.eq(user_id)),
+        )
+        .await?;
+
+        let no_shows = entity::no_show::Entity::find()
+            .filter(entity::no_show::Column::UserId.eq(user_id))
+            .filter(
+                entity::no_show::Column::MissedAt
+                    .gte(now - Duration::days(limits.no_show_ban_window_days)),
+            )
+            .order_by_desc(entity::no_show::Column::MissedAt)
+            .all(db)
+            .await?;
+
+        if let Some(last_no_show) = no_shows.first() {
+            let banned_until = last_no_show.missed_at + Duration::days(limits.no_show_ban_days);
+
+            if no_shows.len() as u64 >= limits.no_show_ban_threshold && banned_until > now {
+                return Err(ReservationServiceError::Banned(banned_until));
+            }
+
+            let remaining =
+                last_no_show.missed_at + Duration::minutes(limits.no_show_cooldown_minutes) - now;
+
+            if remaining.is_positive() {
+                return Err(ReservationServiceError::NoShowCooldown(remaining));
+            }
+        }
+
+        let active = entity::reservation::Entity::find()
+            .filter(entity::reservation::Column::UserId.eq(user_id))
+            .filter(
+                entity::reservation::Column::Status
+                    .is_in([ReservationStatus::Active, ReservationStatus::Pending]),
+            )
+            .filter(
+                Condition::any()
+                    .add(entity::reservation::Column::EndDateTime.is_null())
+                    .add(entity::reservation::Column::EndDateTime.gt(now)),
+            )
+            .all(db)
+            .await?;
+
+        if active.len() as u64 >= limits.max_active_per_user {
+            return Err(ReservationServiceError::LimitExceeded(
+                ReservationLimit::ActiveReservations(limits.max_active_per_user),
+            ));
+        }
+
+        let requested = match &request.quantity {
+            MedicationQuantity::Package(package) => package.quantity,
+            _ => 0,
+        };
+
+        // A total that does not fit into u64 is over any limit as well.
+        let total = active
+            .iter()
+            .filter(|r| r.medication_id == request.medication_id)
+            .filter_map(|r| r.quantity)
+            .try_fold(requested, |sum, q| sum.checked_add(u64::try_from(q).ok()?));
+
+        if total.is_none_or(|total| total > limits.max_packages_per_medication) {
+            return Err(ReservationServiceError::LimitExceeded(
+                ReservationLimit::PackagesPerMedication(limits.max_packages_per_medication),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Cancels an open reservation of the user, releases the held stock and notifies the
+    /// apothecary. The reservation stays visible in the user's history.
+    pub async fn cancel(
+        &self,
+        user_id: Uuid,
+        id: Uuid,
+        request: MedicationReservationCancellationRequest,
+    ) -> Result<ReservationWithApothecaryAndMedication, ReservationServiceError> {
         let reservation = entity::reservation::Entity::find_by_id(id)
             .filter(entity::reservation::Column::UserId.eq(user_id))
             .one(&self.db)
             .await?
             .ok_or(ReservationServiceError::ReservationNotFound)?;
 
-        reservation.delete(&self.db).await?;
+        let now = OffsetDateTime::now_utc();
+        let now = PrimitiveDateTime::new(now.date(), now.time());
 
-        Ok(())
+        // An active reservation past its end is about to expire as a no-show.
+        let overdue = reservation.end_date_time.is_some_and(|end| end <= now);
+
+        if overdue
+            || !matches!(
+                reservation.status,
+                ReservationStatus::Active | ReservationStatus::Pending
+            )
+        {
+            return Err(ReservationServiceError::NotCancellable);
+        }
+
+        let reason = request.reason.map(CancellationReason::from);
+
+        let txn = self.db.begin().await?;
+
+        // Guarded on the status read above, so that a concurrent cancellation or the expiry
+        // sweep cannot release the same stock twice.
+        let updated = entity::reservation::Entity::update_many()
+            .col_expr(
+                entity::reservation::Column::Status,
+                Expr::value(ReservationStatus::Cancelled),
+            )
+            .col_expr(
+                entity::reservation::Column::CancellationReason,
+                Expr::value(reason),
+            )
+            .col_expr(
+                entity::reservation::Column::CancellationNote,
+                Expr::value(request.note),
+            )
+            .col_expr(entity::reservation::Column::CancelledAt, Expr::value(now))
+            .filter(entity::reservation::Column::Id.eq(reservation.id))
+            .filter(entity::reservation::Column::Status.eq(reservation.status.clone()))
+            .exec(&txn)
+            .await?;
+
+        if updated.rows_affected != 1 {
+            txn.rollback().await?;
+            return Err(ReservationServiceError::NotCancellable);
+        }
+
+        if reservation.status == ReservationStatus::Active {
+            release_stock(&txn, &reservation).await?;
+        }
+
+        notify_apothecary(
+            &txn,
+            reservation.apothecary_id,
+            Some(reservation.id),
+            NotificationKind::ReservationCancelled,
+            match reason {
+                Some(reason) => format!(
+                    "Reservation {} was cancelled by the customer ({:?})",
+                    reservation.id, reason
+                ),
+                None => format!(
+                    "Reservation {} was cancelled by the customer",
+                    reservation.id
+                ),
+            },
+        )
+        .await?;
+
+        let cancelled = entity::reservation::Entity::find_by_id(reservation.id)
+            .one(&txn)
+            .await?
+            .ok_or(ReservationServiceError::ReservationNotFound)?;
+
+        txn.commit().await?;
+
+        self.with_details(cancelled).await
+    }
+}
+
+/// Loads apothecary, schedules and medication for all reservations in three queries,
+/// independent of the number of reservations.
+pub(crate) async fn load_details<C: ConnectionTrait>(
+    db: &C,
+    reservations: Vec<Reservation>,
+) -> Result<Vec<ReservationWithApothecaryAndMedication>, ReservationServiceError> {
+    let apothecaries = reservations
+        .load_one(entity::apothecary::Entity, db)
+        .await?
+        .into_iter()
+        .map(|a| a.ok_or(ReservationServiceError::MedicationNotFound))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let medications = reservations
+        .load_one(entity::medication::Entity, db)
+        .await?;
+
+    let schedules = apothecaries
+        .load_many_to_many(
+            entity::schedule::Entity,
+            entity::apothecary_schedule::Entity,
+            db,
+        )
+        .await?;
+
+    reservations
+        .into_iter()
+        .zip(apothecaries)
+        .zip(schedules)
+        .zip(medications)
+        .map(|(((reservation, apothecary), schedules), medication)| {
+            Ok(ReservationWithApothecaryAndMedication::from((
+                reservation,
+                apothecary,
+                schedules,
+                medication.ok_or(ReservationServiceError::MedicationNotFound)?,
+            )))
+        })
+        .collect()
+}
+
+/// Returns the packages held by an active reservation to the apothecary's stock.
+async fn release_stock<C: ConnectionTrait>(db: &C, reservation: &Reservation) -> Result<(), DbErr> {
+    let (QuantityType::Package, Some(quantity)) = (reservation.quantity_type, reservation.quantity)
+    else {
+        return Ok(());
+    };
+
+    entity::apothecary_medication::Entity::update_many()
+        .col_expr(
+            entity::apothecary_medication::Column::MedicationQuantity,
+            Expr::col(entity::apothecary_medication::Column::MedicationQuantity).add(quantity),
+        )
+        .filter(entity::apothecary_medication::Column::ApothecaryId.eq(reservation.apothecary_id))
+        .filter(entity::apothecary_medication::Column::MedicationId.eq(reservation.medication_id))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Detaches a deleting user from their reservations. Open reservations are cancelled and their
+/// stock released; the history stays with the apothecaries without anything pointing back to
+/// the person.
+pub(crate) async fn anonymize_reservations<C: ConnectionTrait>(
+    db: &C,
+    user_id: Uuid,
+) -> Result<(), DbErr> {
+    let now = OffsetDateTime::now_utc();
+    let now = PrimitiveDateTime::new(now.date(), now.time());
+
+    let open = entity::reservation::Entity::find()
+        .filter(entity::reservation::Column::UserId.eq(user_id))
+        .filter(
+            entity::reservation::Column::Status
+                .is_in([ReservationStatus::Active, ReservationStatus::Pending]),
+        )
+        .all(db)
+        .await?;
+
+    for reservation in &open {
+        entity::reservation::ActiveModel {
+            id: Set(reservation.id),
+            status: Set(ReservationStatus::Cancelled),
+            cancellation_reason: Set(Some(CancellationReason::Other)),
+            cancelled_at: Set(Some(now)),
+            ..Default::default()
+        }
+        .update(db)
+        .await?;
+
+        if reservation.status == ReservationStatus::Active {
+            release_stock(db, reservation).await?;
+        }
+
+        notify_apothecary(
+            db,
+            reservation.apothecary_id,
+            Some(reservation.id),
+            NotificationKind::ReservationCancelled,
+            format!(
+                "Reservation {} was cancelled because the customer deleted their account",
+                reservation.id
+            ),
+        )
+        .await?;
+    }
+
+    entity::reservation::Entity::update_many()
+        .col_expr(
+            entity::reservation::Column::UserId,
+            Expr::value(Option::<Uuid>::None),
+        )
+        .col_expr(
+            entity::reservation::Column::CancellationNote,
+            Expr::value(Option::<String>::None),
+        )
+        .filter(entity::reservation::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    entity::idempotency_key::Entity::delete_many()
+        .filter(entity::idempotency_key::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    entity::no_show::Entity::delete_many()
+        .filter(entity::no_show::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+fn status_condition(status: MedicationReservationStatus, now: PrimitiveDateTime) -> Condition {
+    use entity::reservation::Column;
+
+    match status {
+        MedicationReservationStatus::Active => Condition::all()
+            .add(Column::Status.eq(ReservationStatus::Active))
+            .add(Column::EndDateTime.gt(now)),
+        MedicationReservationStatus::Expired => Condition::any()
+            .add(Column::Status.eq(ReservationStatus::Expired))
+            .add(
+                Condition::all()
+                    .add(Column::Status.eq(ReservationStatus::Active))
+                    .add(Column::EndDateTime.lte(now)),
+            ),
+        MedicationReservationStatus::Pending => {
+            Condition::all().add(Column::Status.eq(ReservationStatus::Pending))
+        }
+        MedicationReservationStatus::Done => {
+            Condition::all().add(Column::Status.eq(ReservationStatus::Done))
+        }
+        MedicationReservationStatus::Rejected => {
+            Condition::all().add(Column::Status.eq(ReservationStatus::Rejected))
+        }
+        MedicationReservationStatus::Cancelled => {
+            Condition::all().add(Column::Status.eq(ReservationStatus::Cancelled))
+        }
+    }
+}
+
+fn package_quantity(quantity: u64) -> Result<i64, ReservationServiceError> {
+    Ok(i64::try_from(quantity).map_err(|_| anyhow!("Package quantity {quantity} out of range"))?)
+}
+
+fn replay(
+    idempotency_key: entity::idempotency_key::Model,
+    request_hash: &str,
+) -> Result<ReserveOutcome, ReservationServiceError> {
+    if idempotency_key.request_hash != request_hash {
+        return Err(ReservationServiceError::IdempotencyKeyReused);
+    }
+
+    Ok(ReserveOutcome::Replayed {
+        status: u16::try_from(idempotency_key.response_status).map_err(anyhow::Error::from)?,
+        body: idempotency_key.response_body,
+    })
+}
+
+fn hash_request(request: &MedicationReservationRequest) -> Result<String, ReservationServiceError> {
+    let body = serde_json::to_vec(request).map_err(anyhow::Error::from)?;
+
+    Ok(sha256_hex(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::PaginatorTrait;
+
+    use super::*;
+    use crate::test_support;
+
+    async fn service() -> (ReservationService, DatabaseConnection) {
+        service_with(test_support::settings()).await
+    }
+
+    async fn service_with(settings: Settings) -> (ReservationService, DatabaseConnection) {
+        let db = test_support::db().await;
+
+        (ReservationService::new(db.clone(), Arc::new(settings)), db)
+    }
+
+    async fn add_no_show(db: &DatabaseConnection, reservation: &Reservation, days_ago: i64) {
+        let missed_at = OffsetDateTime::now_utc() - Duration::days(days_ago);
+
+        entity::no_show::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(reservation.user_id.unwrap()),
+            reservation_id: Set(reservation.id),
+            apothecary_id: Set(reservation.apothecary_id),
+            missed_at: Set(PrimitiveDateTime::new(missed_at.date(), missed_at.time())),
+        }
+        .insert(db)
+        .await
+        .unwrap();
+    }
+
+    /// Moves the end of the pickup window of the reservation a minute into the past.
+    async fn make_overdue(db: &DatabaseConnection, id: Uuid) {
+        let ended = OffsetDateTime::now_utc() - Duration::minutes(1);
+
+        entity::reservation::Entity::update_many()
+            .col_expr(
+                entity::reservation::Column::EndDateTime,
+                Expr::value(PrimitiveDateTime::new(ended.date(), ended.time())),
+            )
+            .filter(entity::reservation::Column::Id.eq(id))
+            .exec(db)
+            .await
+            .unwrap();
+    }
+
+    async fn no_shows_of(db: &DatabaseConnection, reservation_id: Uuid) -> u64 {
+        entity::no_show::Entity::find()
+            .filter(entity::no_show::Column::ReservationId.eq(reservation_id))
+            .count(db)
+            .await
+            .unwrap()
+    }
+
+    async fn quantity_of(
+        db: &DatabaseConnection,
+        stock: &entity::apothecary_medication::Model,
+    ) -> i64 {
+        entity::apothecary_medication::Entity::find_by_id((
+            stock.apothecary_id,
+            stock.medication_id,
+        ))
+        .one(db)
+        .await
+        .unwrap()
+        .unwrap()
+        .medication_quantity
+        .unwrap()
+    }
+
+    async fn reservations_of(db: &DatabaseConnection, user_id: Uuid) -> Vec<Reservation> {
+        entity::reservation::Entity::find()
+            .filter(entity::reservation::Column::UserId.eq(user_id))
+            .all(db)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn retries_with_the_same_key_replay_the_response() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+        let key = Some("retry-key".to_owned());
+
+        let Ok(ReserveOutcome::Created(created)) = service
+            .reserve(
+                customer,
+                key.clone(),
+                test_support::reservation_request(&stock, 1),
+            )
+            .await
+        else {
+            panic!("the first request creates the reservation");
+        };
+
+        let Ok(ReserveOutcome::Replayed { status, body }) = service
+            .reserve(customer, key, test_support::reservation_request(&stock, 1))
+            .await
+        else {
+            panic!("the retry is answered from the stored response");
+        };
+
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            serde_json::to_string(&dto::reservation::MedicationReservation::from(*created))
+                .unwrap()
+        );
+        assert_eq!(reservations_of(&db, customer).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn keys_cannot_be_reused_for_another_request() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+        let key = Some("retry-key".to_owned());
+
+        service
+            .reserve(
+                customer,
+                key.clone(),
+                test_support::reservation_request(&stock, 1),
+            )
+            .await
+            .ok()
+            .unwrap();
+
+        assert!(matches!(
+            service
+                .reserve(customer, key, test_support::reservation_request(&stock, 2))
+                .await,
+            Err(ReservationServiceError::IdempotencyKeyReused)
+        ));
+        assert_eq!(reservations_of(&db, customer).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn expired_keys_are_treated_as_new() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+        let key = Some("retry-key".to_owned());
+
+        service
+            .reserve(
+                customer,
+                key.clone(),
+                test_support::reservation_request(&stock, 1),
+            )
+            .await
+            .ok()
+            .unwrap();
+
+        let expired = service.idempotency_key_cutoff() - Duration::minutes(1);
+        entity::idempotency_key::Entity::update_many()
+            .col_expr(
+                entity::idempotency_key::Column::CreatedAt,
+                Expr::value(expired),
+            )
+            .exec(&db)
+            .await
+            .unwrap();
+
+        // Even with a different body, the key is free again.
+        assert!(matches!(
+            service
+                .reserve(customer, key, test_support::reservation_request(&stock, 2))
+                .await,
+            Ok(ReserveOutcome::Created(_))
+        ));
+        assert_eq!(reservations_of(&db, customer).await.len(), 2);
+        assert_eq!(
+            entity::idempotency_key::Entity::find()
+                .count(&db)
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn active_reservations_are_limited() {
+        let mut settings = test_support::settings();
+        settings.reservation.max_active_per_user = 1;
+        let (service, db) = service_with(settings).await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        service
+            .reserve(customer, None, test_support::reservation_request(&stock, 1))
+            .await
+            .ok()
+            .unwrap();
+
+        assert!(matches!(
+            service
+                .reserve(customer, None, test_support::reservation_request(&stock, 1))
+                .await,
+            Err(ReservationServiceError::LimitExceeded(
+                ReservationLimit::ActiveReservations(1)
+            ))
+        ));
+
+        // Cancelled reservations no longer count.
+        let reservation = test_support::reservation_of(&db, customer).await;
+        service
+            .cancel(customer, reservation.id, Default::default())
+            .await
+            .ok()
+            .unwrap();
+
+        assert!(service
+            .reserve(customer, None, test_support::reservation_request(&stock, 1))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn packages_per_medication_are_limited() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        service
+            .reserve(customer, None, test_support::reservation_request(&stock, 2))
+            .await
+            .ok()
+            .unwrap();
+
+        assert!(matches!(
+            service
+                .reserve(customer, None, test_support::reservation_request(&stock, 2))
+                .await,
+            Err(ReservationServiceError::LimitExceeded(
+                ReservationLimit::PackagesPerMedication(3)
+            ))
+        ));
+        assert!(service
+            .reserve(customer, None, test_support::reservation_request(&stock, 1))
+            .await
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn overflowing_totals_are_over_the_limit() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        service
+            .reserve(customer, None, test_support::reservation_request(&stock, 1))
+            .await
+            .ok()
+            .unwrap();
+
+        assert!(matches!(
+            service
+                .reserve(
+                    customer,
+                    None,
+                    test_support::reservation_request(&stock, u64::MAX)
+                )
+                .await,
+            Err(ReservationServiceError::LimitExceeded(
+                ReservationLimit::PackagesPerMedication(_)
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn repeated_no_shows_suspend_reservations() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        for days_ago in [3, 2, 1] {
+            service
+                .reserve(customer, None, test_support::reservation_request(&stock, 1))
+                .await
+                .ok()
+                .unwrap();
+            let reservation = test_support::reservation_of(&db, customer).await;
+            service
+                .cancel(customer, reservation.id, Default::default())
+                .await
+                .ok()
+                .unwrap();
+            add_no_show(&db, &reservation, days_ago).await;
+
+            // Keeps reservation_of down to the next one.
+            entity::reservation::Entity::update_many()
+                .col_expr(
+                    entity::reservation::Column::UserId,
+                    Expr::value(Option::<Uuid>::None),
+                )
+                .filter(entity::reservation::Column::Id.eq(reservation.id))
+                .exec(&db)
+                .await
+                .unwrap();
+        }
+
+        let Err(ReservationServiceError::Banned(until)) = service
+            .reserve(customer, None, test_support::reservation_request(&stock, 1))
+            .await
+        else {
+            panic!("three no-shows suspend reservations");
+        };
+
+        let expected = OffsetDateTime::now_utc() + Duration::days(29);
+        assert!((until.assume_utc() - expected).abs() < Duration::minutes(1));
+    }
+
+    #[tokio::test]
+    async fn overdue_reservations_get_exactly_one_no_show() {
+        let (service, db) = service().await;
+        let jane = test_support::customer(&db, "jane@example.com").await;
+        let john = test_support::customer(&db, "john@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        for customer in [jane, john] {
+            service
+                .reserve(customer, None, test_support::reservation_request(&stock, 2))
+                .await
+                .ok()
+                .unwrap();
+            make_overdue(&db, test_support::reservation_of(&db, customer).await.id).await;
+        }
+
+        // Concurrent sweeps expire each reservation only once.
+        let (first, second) = tokio::join!(service.expire_overdue(), service.expire_overdue());
+        assert_eq!(first.ok().unwrap() + second.ok().unwrap(), 2);
+        assert_eq!(service.expire_overdue().await.ok().unwrap(), 0);
+
+        // Neither does the sweep that runs before a reservation.
+        assert!(matches!(
+            service
+                .reserve(jane, None, test_support::reservation_request(&stock, 1))
+                .await,
+            Err(ReservationServiceError::NoShowCooldown(_))
+        ));
+
+        for customer in [jane, john] {
+            let reservation = test_support::reservation_of(&db, customer).await;
+
+            assert_eq!(reservation.status, ReservationStatus::Expired);
+            assert_eq!(no_shows_of(&db, reservation.id).await, 1);
+        }
+
+        assert_eq!(quantity_of(&db, &stock).await, 10);
+    }
+
+    #[tokio::test]
+    async fn no_shows_hold_off_reservations_for_the_cooldown() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        service
+            .reserve(customer, None, test_support::reservation_request(&stock, 1))
+            .await
+            .ok()
+            .unwrap();
+        let reservation = test_support::reservation_of(&db, customer).await;
+        make_overdue(&db, reservation.id).await;
+
+        let Err(ReservationServiceError::NoShowCooldown(remaining)) = service
+            .reserve(customer, None, test_support::reservation_request(&stock, 1))
+            .await
+        else {
+            panic!("a no-show starts the cooldown");
+        };
+
+        let expected = Duration::hours(24) - Duration::minutes(1);
+        assert!((remaining - expected).abs() < Duration::minutes(1));
+
+        // Once the cooldown has passed, a single no-show no longer matters.
+        service.expire_overdue().await.ok().unwrap();
+        let missed_at = OffsetDateTime::now_utc() - Duration::days(2);
+        entity::no_show::Entity::update_many()
+            .col_expr(
+                entity::no_show::Column::MissedAt,
+                Expr::value(PrimitiveDateTime::new(missed_at.date(), missed_at.time())),
+            )
+            .exec(&db)
+            .await
+            .unwrap();
+
+        assert!(service
+            .reserve(customer, None, test_support::reservation_request(&stock, 1))
+            .await
+            .is_ok());
+        assert_eq!(no_shows_of(&db, reservation.id).await, 1);
+    }
+
+    #[test]
+    fn scores_are_the_share_of_picked_up_reservations() {
+        for (completed, no_shows, score) in [
+            (0, 0, 100),
+            (0, 1, 50),
+            (0, 3, 25),
+            (1, 1, 66),
+            (3, 0, 100),
+            (3, 1, 80),
+            (9, 10, 50),
+        ] {
+            assert_eq!(
+                Reliability {
+                    completed,
+                    no_shows
+                }
+                .score(),
+                score,
+                "{completed} completed, {no_shows} no-shows"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn staff_see_the_pickup_history_of_customers() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+        let owner = entity::apothecary_user::Entity::find()
+            .filter(entity::apothecary_user::Column::ApothecaryId.eq(stock.apothecary_id))
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap()
+            .user_id;
+
+        for _ in 0..3 {
+            service
+                .reserve(customer, None, test_support::reservation_request(&stock, 1))
+                .await
+                .ok()
+                .unwrap();
+        }
+
+        let reservations = reservations_of(&db, customer).await;
+        entity::reservation::Entity::update_many()
+            .col_expr(
+                entity::reservation::Column::Status,
+                Expr::value(ReservationStatus::Done),
+            )
+            .filter(entity::reservation::Column::Id.eq(reservations[0].id))
+            .exec(&db)
+            .await
+            .unwrap();
+        make_overdue(&db, reservations[1].id).await;
+        service.expire_overdue().await.ok().unwrap();
+
+        let page = service.get_for_staff(owner, None).await.ok().unwrap();
+        let listed: Vec<_> = page
+            .content
+            .iter()
+            .filter(|r| r.customer.id == customer)
+            .collect();
+
+        assert_eq!(listed.len(), 1);
+        let reliability = listed[0].reliability;
+        assert_eq!(reliability.completed, 1);
+        assert_eq!(reliability.no_shows, 1);
+        assert_eq!(reliability.score(), 66);
+    }
+
+    #[tokio::test]
+    async fn cancelling_releases_the_stock_once() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        service
+            .reserve(customer, None, test_support::reservation_request(&stock, 3))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(quantity_of(&db, &stock).await, 7);
+
+        let reservation = test_support::reservation_of(&db, customer).await;
+        let (first, second) = tokio::join!(
+            service.cancel(customer, reservation.id, Default::default()),
+            service.cancel(customer, reservation.id, Default::default()),
+        );
+
+        assert_eq!([&first, &second].iter().filter(|r| r.is_ok()).count(), 1);
+        assert!([first, second]
+            .into_iter()
+            .any(|r| matches!(r, Err(ReservationServiceError::NotCancellable))));
+        assert_eq!(quantity_of(&db, &stock).await, 10);
+
+        assert!(matches!(
+            service
+                .cancel(customer, reservation.id, Default::default())
+                .await,
+            Err(ReservationServiceError::NotCancellable)
+        ));
+        assert_eq!(quantity_of(&db, &stock).await, 10);
+        assert_eq!(
+            test_support::reservation_of(&db, customer).await.status,
+            ReservationStatus::Cancelled
+        );
+    }
+
+    #[tokio::test]
+    async fn expired_reservations_cannot_be_cancelled() {
+        let (service, db) = service().await;
+        let customer = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        service
+            .reserve(customer, None, test_support::reservation_request(&stock, 2))
+            .await
+            .ok()
+            .unwrap();
+        let reservation = test_support::reservation_of(&db, customer).await;
+        make_overdue(&db, reservation.id).await;
+
+        // Past its end, even before the sweep got to it.
+        assert!(matches!(
+            service
+                .cancel(customer, reservation.id, Default::default())
+                .await,
+            Err(ReservationServiceError::NotCancellable)
+        ));
+
+        service.expire_overdue().await.ok().unwrap();
+
+        assert!(matches!(
+            service
+                .cancel(customer, reservation.id, Default::default())
+                .await,
+            Err(ReservationServiceError::NotCancellable)
+        ));
+        assert_eq!(quantity_of(&db, &stock).await, 10);
+        assert_eq!(no_shows_of(&db, reservation.id).await, 1);
+        assert_eq!(
+            test_support::reservation_of(&db, customer).await.status,
+            ReservationStatus::Expired
+        );
+    }
+
+    /// Reserves a package and moves the reservation to `created_at` in October 2026 with the
+    /// given status.
+    async fn past_reservation(
+        service: &ReservationService,
+        db: &DatabaseConnection,
+        customer: Uuid,
+        stock: &entity::apothecary_medication::Model,
+        (day, hour): (u8, u8),
+        status: ReservationStatus,
+    ) -> Uuid {
+        let Ok(ReserveOutcome::Created(reservation)) = service
+            .reserve(customer, None, test_support::reservation_request(stock, 1))
+            .await
+        else {
+            panic!("the reservation is created");
+        };
+        let id = dto::reservation::MedicationReservation::from(*reservation).id;
+
+        let created_at = time::Date::from_calendar_date(2026, time::Month::October, day)
+            .unwrap()
+            .with_hms(hour, 0, 0)
+            .unwrap();
+
+        entity::reservation::Entity::update_many()
+            .col_expr(
+                entity::reservation::Column::CreatedAt,
+                Expr::value(created_at),
+            )
+            .col_expr(entity::reservation::Column::Status, Expr::value(status))
+            .filter(entity::reservation::Column::Id.eq(id))
+            .exec(db)
+            .await
+            .unwrap();
+
+        id
+    }
+
+    /// A customer with a picked up, a cancelled, an active and an overdue reservation, created
+    /// on October 1st, 5th and twice on the 10th, in that order.
+    async fn history(db: &DatabaseConnection, service: &ReservationService) -> (Uuid, [Uuid; 4]) {
+        let customer = test_support::customer(db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(db).await;
+
+        let done = past_reservation(
+            service,
+            db,
+            customer,
+            &stock,
+            (1, 9),
+            ReservationStatus::Done,
+        )
+        .await;
+        let cancelled = past_reservation(
+            service,
+            db,
+            customer,
+            &stock,
+            (5, 9),
+            ReservationStatus::Cancelled,
+        )
+        .await;
+        let active = past_reservation(
+            service,
+            db,
+            customer,
+            &stock,
+            (10, 9),
+            ReservationStatus::Active,
+        )
+        .await;
+        let overdue = past_reservation(
+            service,
+            db,
+            customer,
+            &stock,
+            (10, 17),
+            ReservationStatus::Active,
+        )
+        .await;
+        make_overdue(db, overdue).await;
+
+        (customer, [done, cancelled, active, overdue])
+    }
+
+    fn ids(page: Page<ReservationWithApothecaryAndMedication>) -> Vec<Uuid> {
+        page.content
+            .into_iter()
+            .map(|r| dto::reservation::MedicationReservation::from(r).id)
+            .collect()
+    }
+
+    fn page_of(page: u64, size: u64, cursor: Option<String>) -> Option<Pageable> {
+        Some(Pageable {
+            page,
+            size,
+            sort: Vec::new(),
+            cursor,
+        })
+    }
+
+    #[tokio::test]
+    async fn history_is_filtered_by_status() {
+        let (service, db) = service().await;
+        let (customer, [done, cancelled, active, overdue]) = history(&db, &service).await;
+
+        for (status, expected) in [
+            (MedicationReservationStatus::Active, vec![active]),
+            (MedicationReservationStatus::Expired, vec![overdue]),
+            (MedicationReservationStatus::Done, vec![done]),
+            (MedicationReservationStatus::Cancelled, vec![cancelled]),
+            (MedicationReservationStatus::Pending, vec![]),
+            (MedicationReservationStatus::Rejected, vec![]),
+        ] {
+            let filter = ReservationFilter {
+                status: Some(status),
+                ..Default::default()
+            };
+            let page = service
+                .get(customer, filter, page_of(0, 10, None))
+                .await
+                .ok()
+                .unwrap();
+
+            assert_eq!(ids(page), expected, "{status:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn history_is_filtered_by_inclusive_creation_dates() {
+        let (service, db) = service().await;
+        let (customer, [done, cancelled, active, overdue]) = history(&db, &service).await;
+        let day =
+            |day| Some(time::Date::from_calendar_date(2026, time::Month::October, day).unwrap());
+
+        for ((from, to), expected) in [
+            ((day(5), day(10)), vec![overdue, active, cancelled]),
+            ((day(6), None), vec![overdue, active]),
+            ((None, day(4)), vec![done]),
+            ((day(5), day(5)), vec![cancelled]),
+            ((day(11), None), vec![]),
+        ] {
+            let filter = ReservationFilter {
+                status: None,
+                from,
+                to,
+            };
+            let page = service
+                .get(customer, filter, page_of(0, 10, None))
+                .await
+                .ok()
+                .unwrap();
+
+            assert_eq!(ids(page), expected, "{from:?} to {to:?}");
+        }
+
+        // Filters combine.
+        let filter = ReservationFilter {
+            status: Some(MedicationReservationStatus::Active),
+            from: day(1),
+            to: day(5),
+        };
+        let page = service
+            .get(customer, filter, page_of(0, 10, None))
+            .await
+            .ok()
+            .unwrap();
+        assert!(ids(page).is_empty());
+    }
+
+    #[tokio::test]
+    async fn history_is_paginated_newest_first() {
+        let (service, db) = service().await;
+        let (customer, [done, cancelled, active, overdue]) = history(&db, &service).await;
+
+        let first = service
+            .get(customer, Default::default(), page_of(0, 2, None))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(first.total_elements, 4);
+        assert_eq!(first.total_pages, 2);
+        assert!(first.first && !first.last);
+        let next = first.next.clone();
+        assert_eq!(ids(first), [overdue, active]);
+
+        let second = service
+            .get(customer, Default::default(), page_of(1, 2, None))
+            .await
+            .ok()
+            .unwrap();
+        assert!(second.last);
+        assert_eq!(ids(second), [cancelled, done]);
+
+        // The cursor of the first page leads to the same second page.
+        let continued = service
+            .get(customer, Default::default(), page_of(0, 2, next))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(ids(continued), [cancelled, done]);
+
+        // Pages only count what the filter lets through.
+        let filter = ReservationFilter {
+            status: Some(MedicationReservationStatus::Cancelled),
+            ..Default::default()
+        };
+        let filtered = service
+            .get(customer, filter, page_of(0, 2, None))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(filtered.total_elements, 1);
+        assert!(filtered.last);
     }
 }
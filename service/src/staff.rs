@@ -0,0 +1,813 @@
+use std::{fmt::Display, sync::Arc};
+
+use dto::{
+    page::{Pageable, SortDirection},
+    staff::{Membership, StaffMember},
+};
+use entity::{
+    apothecary_user::{self, StaffRole},
+    staff_invitation,
+    user::UserType,
+    DatabaseConnection,
+};
+use sea_orm::{entity::prelude::*, sea_query::Expr, ConnectionTrait, Set, TransactionTrait};
+use settings::Settings;
+use time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    hash::{random_token, sha256_hex},
+    mail::{MailMessage, Mailer},
+    page::{Page, PageError},
+    user::{normalize_email, now, User},
+};
+
+pub use entity::staff_invitation::Model as Invitation;
+
+pub enum StaffServiceError {
+    ApothecaryNotFound,
+    /// The user is not a member of the apothecary, or lacks the role for the action.
+    Forbidden,
+    MemberNotFound,
+    AlreadyMember,
+    InvitationNotFound,
+    InvalidInvitation,
+    /// Every apothecary keeps at least one owner.
+    LastOwner,
+    Page(PageError),
+    Anyhow(anyhow::Error),
+}
+
+impl From<DbErr> for StaffServiceError {
+    fn from(err: DbErr) -> Self {
+        Self::Anyhow(err.into())
+    }
+}
+
+impl From<PageError> for StaffServiceError {
+    fn from(err: PageError) -> Self {
+        Self::Page(err)
+    }
+}
+
+impl Display for StaffServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StaffServiceError::ApothecaryNotFound => write!(f, "Apothecary not found"),
+            StaffServiceError::Forbidden => write!(f, "Not allowed for this apothecary"),
+            StaffServiceError::MemberNotFound => write!(f, "Staff member not found"),
+            StaffServiceError::AlreadyMember => write!(f, "User is already a staff member"),
+            StaffServiceError::InvitationNotFound => write!(f, "Invitation not found"),
+            StaffServiceError::InvalidInvitation => {
+                write!(f, "Invalid or expired invitation")
+            }
+            StaffServiceError::LastOwner => {
+                write!(f, "The last owner of an apothecary cannot be removed")
+            }
+            StaffServiceError::Page(e) => write!(f, "{}", e),
+            StaffServiceError::Anyhow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub struct StaffService {
+    db: DatabaseConnection,
+    settings: Arc<Settings>,
+    mailer: Arc<dyn Mailer>,
+}
+
+impl StaffService {
+    pub fn new(db: DatabaseConnection, settings: Arc<Settings>, mailer: Arc<dyn Mailer>) -> Self {
+        Self {
+            db,
+            settings,
+            mailer,
+        }
+    }
+
+    pub async fn get_memberships(
+        &self,
+        user_id: Uuid,
+        pageable: Option<Pageable>,
+    ) -> Result<Page<Membership>, StaffServiceError> {
+        let mut page = Page::paginate(
+            &self.db,
+            apothecary_user::Entity::find().filter(apothecary_user::Column::UserId.eq(user_id)),
+            pageable,
+        )
+        .await?;
+        let memberships = std::mem::take(&mut page.content);
+
+        let apothecaries = memberships
+            .load_one(entity::apothecary::Entity, &self.db)
+            .await?;
+
+        Ok(page.with_content(
+            memberships
+                .into_iter()
+                .zip(apothecaries)
+                .filter_map(|(membership, apothecary)| {
+                    Some(Membership {
+                        apothecary_id: membership.apothecary_id,
+                        apothecary_name: apothecary?.name,
+                        role: membership.role.into(),
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    /// Lists the staff of an apothecary the user is a member of.
+    pub async fn get_members(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        pageable: Option<Pageable>,
+    ) -> Result<Page<StaffMember>, StaffServiceError> {
+        self.require_role(user_id, apothecary_id, None).await?;
+
+        let mut page = Page::paginate(
+            &self.db,
+            apothecary_user::Entity::find()
+                .filter(apothecary_user::Column::ApothecaryId.eq(apothecary_id)),
+            pageable,
+        )
+        .await?;
+        let memberships = std::mem::take(&mut page.content);
+
+        let users = memberships.load_one(entity::user::Entity, &self.db).await?;
+
+        Ok(page.with_content(
+            memberships
+                .into_iter()
+                .zip(users)
+                .filter_map(|(membership, user)| {
+                    let user = user?;
+
+                    Some(StaffMember {
+                        user_id: user.id,
+                        name: user.name,
+                        email: user.email,
+                        role: membership.role.into(),
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    pub async fn update_role(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        member_id: Uuid,
+        role: StaffRole,
+    ) -> Result<StaffMember, StaffServiceError> {
+        self.require_role(user_id, apothecary_id, Some(StaffRole::Owner))
+            .await?;
+
+        let txn = self.db.begin().await?;
+
+        let membership = find_membership(&txn, member_id, apothecary_id)
+            .await?
+            .ok_or(StaffServiceError::MemberNotFound)?;
+
+        if membership.role == StaffRole::Owner && role != StaffRole::Owner {
+            ensure_other_owner(&txn, &membership).await?;
+        }
+
+        let mut membership: apothecary_user::ActiveModel = membership.into();
+        membership.role = Set(role);
+        let membership = membership.update(&txn).await?;
+
+        let user = entity::user::Entity::find_by_id(member_id)
+            .one(&txn)
+            .await?
+            .ok_or(StaffServiceError::MemberNotFound)?;
+
+        txn.commit().await?;
+
+        Ok(StaffMember {
+            user_id: user.id,
+            name: user.name,
+            email: user.email,
+            role: membership.role.into(),
+        })
+    }
+
+    /// Removes a member. Owners can remove anyone, everybody else only themselves. A user who
+    /// no longer works at any apothecary becomes a customer again.
+    pub async fn remove_member(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        member_id: Uuid,
+    ) -> Result<(), StaffServiceError> {
+        let required = (user_id != member_id).then_some(StaffRole::Owner);
+        self.require_role(user_id, apothecary_id, required).await?;
+
+        let txn = self.db.begin().await?;
+
+        let membership = find_membership(&txn, member_id, apothecary_id)
+            .await?
+            .ok_or(StaffServiceError::MemberNotFound)?;
+
+        if membership.role == StaffRole::Owner {
+            ensure_other_owner(&txn, &membership).await?;
+        }
+
+        membership.delete(&txn).await?;
+
+        revert_if_unaffiliated(&txn, member_id).await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn get_invitations(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        pageable: Option<Pageable>,
+    ) -> Result<Page<Invitation>, StaffServiceError> {
+        self.require_role(user_id, apothecary_id, Some(StaffRole::Owner))
+            .await?;
+
+        let query = staff_invitation::Entity::find()
+            .filter(staff_invitation::Column::ApothecaryId.eq(apothecary_id))
+            .filter(staff_invitation::Column::AcceptedAt.is_null())
+            .filter(staff_invitation::Column::ExpiresAt.gt(now()));
+        let pageable = pageable.map(|p| p.sorted_by_default("createdAt", SortDirection::Asc));
+
+        Ok(Page::paginate(&self.db, query, pageable).await?)
+    }
+
+    /// Mails an invitation link to the address. An earlier pending invitation of the same
+    /// address to the same apothecary is replaced.
+    pub async fn invite(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        request: dto::staff::InvitationRequest,
+    ) -> Result<Invitation, StaffServiceError> {
+        self.require_role(user_id, apothecary_id, Some(StaffRole::Owner))
+            .await?;
+
+        let email = normalize_email(&request.email);
+
+        let apothecary = entity::apothecary::Entity::find_by_id(apothecary_id)
+            .one(&self.db)
+            .await?
+            .ok_or(StaffServiceError::ApothecaryNotFound)?;
+
+        let inviter = entity::user::Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(StaffServiceError::Forbidden)?;
+
+        if let Some(existing) = entity::user::Entity::find()
+            .filter(entity::user::Column::Email.eq(&email))
+            .one(&self.db)
+            .await?
+        {
+            if find_membership(&self.db, existing.id, apothecary_id)
+                .await?
+                .is_some()
+            {
+                return Err(StaffServiceError::AlreadyMember);
+            }
+        }
+
+        let now = now();
+        let token = random_token();
+
+        let txn = self.db.begin().await?;
+
+        staff_invitation::Entity::delete_many()
+            .filter(staff_invitation::Column::ApothecaryId.eq(apothecary_id))
+            .filter(staff_invitation::Column::Email.eq(&email))
+            .filter(staff_invitation::Column::AcceptedAt.is_null())
+            .exec(&txn)
+            .await?;
+
+        let invitation = staff_invitation::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            apothecary_id: Set(apothecary_id),
+            email: Set(email),
+            role: Set(request.role.into()),
+            token_hash: Set(sha256_hex(&token)),
+            invited_by: Set(Some(user_id)),
+            created_at: Set(now),
+            expires_at: Set(now + Duration::seconds(self.settings.mail.invitation_link_lifetime)),
+            accepted_at: Set(None),
+        }
+        .insert(&txn)
+        .await?;
+
+        txn.commit().await?;
+
+        // The invitation exists at this point, a failed mail can be retried by inviting again.
+        if let Err(e) = self
+            .mailer
+            .send(MailMessage {
+                to: invitation.email.clone(),
+                subject: format!("Join {} on PharmaTracker", apothecary.name),
+                body: format!(
+                    "Hello,\n\n{} invited you to join {} as {}. Open the link below to accept, you need an account with this email address:\n\n{}?token={}\n",
+                    inviter.name,
+                    apothecary.name,
+                    role_name(invitation.role),
+                    self.settings.mail.invitation_url,
+                    token
+                ),
+            })
+            .await
+        {
+            tracing::error!("Failed to send invitation {}: {}", invitation.id, e);
+        }
+
+        Ok(invitation)
+    }
+
+    pub async fn revoke_invitation(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        invitation_id: Uuid,
+    ) -> Result<(), StaffServiceError> {
+        self.require_role(user_id, apothecary_id, Some(StaffRole::Owner))
+            .await?;
+
+        let result = staff_invitation::Entity::delete_many()
+            .filter(staff_invitation::Column::Id.eq(invitation_id))
+            .filter(staff_invitation::Column::ApothecaryId.eq(apothecary_id))
+            .filter(staff_invitation::Column::AcceptedAt.is_null())
+            .exec(&self.db)
+            .await?;
+
+        if result.rows_affected == 0 {
+            return Err(StaffServiceError::InvitationNotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Adds the user to the apothecary of the invitation, if it was sent to their address.
+    /// Customers become apothecary users, so they need a new access token to see the staff
+    /// routes.
+    pub async fn accept(
+        &self,
+        user_id: Uuid,
+        token: &str,
+    ) -> Result<Membership, StaffServiceError> {
+        let now = now();
+
+        let user: User = entity::user::Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(StaffServiceError::InvalidInvitation)?;
+
+        let invitation = staff_invitation::Entity::find()
+            .filter(staff_invitation::Column::TokenHash.eq(sha256_hex(token)))
+            .one(&self.db)
+            .await?
+            .filter(|i| i.accepted_at.is_none() && i.expires_at > now && i.email == user.email)
+            .ok_or(StaffServiceError::InvalidInvitation)?;
+
+        let apothecary = entity::apothecary::Entity::find_by_id(invitation.apothecary_id)
+            .one(&self.db)
+            .await?
+            .ok_or(StaffServiceError::ApothecaryNotFound)?;
+
+        if find_membership(&self.db, user.id, apothecary.id)
+            .await?
+            .is_some()
+        {
+            return Err(StaffServiceError::AlreadyMember);
+        }
+
+        let txn = self.db.begin().await?;
+
+        let accepted = staff_invitation::Entity::update_many()
+            .col_expr(staff_invitation::Column::AcceptedAt, Expr::value(Some(now)))
+            .filter(staff_invitation::Column::Id.eq(invitation.id))
+            .filter(staff_invitation::Column::AcceptedAt.is_null())
+            .exec(&txn)
+            .await?;
+
+        if accepted.rows_affected != 1 {
+            return Err(StaffServiceError::InvalidInvitation);
+        }
+
+        apothecary_user::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            apothecary_id: Set(apothecary.id),
+            user_id: Set(user.id),
+            role: Set(invitation.role),
+        }
+        .insert(&txn)
+        .await?;
+
+        if user.user_type == UserType::Customer {
+            let mut user: entity::user::ActiveModel = user.into();
+            user.user_type = Set(UserType::Apothecary);
+            user.update(&txn).await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(Membership {
+            apothecary_id: apothecary.id,
+            apothecary_name: apothecary.name,
+            role: invitation.role.into(),
+        })
+    }
+
+    /// Checks that the user is a member of the apothecary, with the given role if any.
+    async fn require_role(
+        &self,
+        user_id: Uuid,
+        apothecary_id: Uuid,
+        role: Option<StaffRole>,
+    ) -> Result<apothecary_user::Model, StaffServiceError> {
+        find_membership(&self.db, user_id, apothecary_id)
+            .await?
+            .filter(|membership| role.is_none_or(|role| membership.role == role))
+            .ok_or(StaffServiceError::Forbidden)
+    }
+}
+
+pub(crate) async fn find_membership<C: ConnectionTrait>(
+    db: &C,
+    user_id: Uuid,
+    apothecary_id: Uuid,
+) -> Result<Option<apothecary_user::Model>, DbErr> {
+    apothecary_user::Entity::find()
+        .filter(apothecary_user::Column::UserId.eq(user_id))
+        .filter(apothecary_user::Column::ApothecaryId.eq(apothecary_id))
+        .one(db)
+        .await
+}
+
+async fn ensure_other_owner<C: ConnectionTrait>(
+    db: &C,
+    membership: &apothecary_user::Model,
+) -> Result<(), StaffServiceError> {
+    if is_last_owner(db, membership).await? {
+        return Err(StaffServiceError::LastOwner);
+    }
+
+    Ok(())
+}
+
+pub(crate) async fn is_last_owner<C: ConnectionTrait>(
+    db: &C,
+    membership: &apothecary_user::Model,
+) -> Result<bool, DbErr> {
+    if membership.role != StaffRole::Owner {
+        return Ok(false);
+    }
+
+    let owners = apothecary_user::Entity::find()
+        .filter(apothecary_user::Column::ApothecaryId.eq(membership.apothecary_id))
+        .filter(apothecary_user::Column::Role.eq(StaffRole::Owner))
+        .filter(apothecary_user::Column::Id.ne(membership.id))
+        .count(db)
+        .await?;
+
+    Ok(owners == 0)
+}
+
+/// Makes an apothecary user who no longer works at any apothecary a customer again.
+pub(crate) async fn revert_if_unaffiliated<C: ConnectionTrait>(
+    db: &C,
+    user_id: Uuid,
+) -> Result<(), DbErr> {
+    let remaining = apothecary_user::Entity::find()
+        .filter(apothecary_user::Column::UserId.eq(user_id))
+        .count(db)
+        .await?;
+
+    if remaining == 0 {
+        entity::user::Entity::update_many()
+            .col_expr(
+                entity::user::Column::UserType,
+                Expr::value(UserType::Customer),
+            )
+            .filter(entity::user::Column::Id.eq(user_id))
+            .filter(entity::user::Column::UserType.eq(UserType::Apothecary))
+            .exec(db)
+            .await?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn role_name(role: StaffRole) -> &'static str {
+    match role {
+        StaffRole::Owner => "owner",
+        StaffRole::Pharmacist => "pharmacist",
+        StaffRole::Assistant => "assistant",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        mail::{InMemoryMailer, Mailer},
+        test_support,
+    };
+
+    use super::*;
+
+    struct Setup {
+        service: StaffService,
+        mailer: Arc<InMemoryMailer>,
+        db: DatabaseConnection,
+        /// Owner of St. Rudolf.
+        john: Uuid,
+        rudolf: Uuid,
+    }
+
+    async fn setup() -> Setup {
+        let db = test_support::db().await;
+        let mailer = Arc::new(InMemoryMailer::default());
+        let service = StaffService::new(
+            db.clone(),
+            Arc::new(test_support::settings()),
+            mailer.clone() as Arc<dyn Mailer>,
+        );
+
+        let john = entity::user::Entity::find()
+            .filter(entity::user::Column::Email.eq("john@apo.com"))
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        let rudolf = apothecary_user::Entity::find()
+            .filter(apothecary_user::Column::UserId.eq(john))
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap()
+            .apothecary_id;
+
+        Setup {
+            service,
+            mailer,
+            db,
+            john,
+            rudolf,
+        }
+    }
+
+    fn invitation(email: &str, role: dto::staff::StaffRole) -> dto::staff::InvitationRequest {
+        dto::staff::InvitationRequest {
+            email: email.to_owned(),
+            role,
+        }
+    }
+
+    /// The token of the last invitation mailed.
+    fn last_token(mailer: &InMemoryMailer) -> String {
+        let body = mailer
+            .messages()
+            .pop()
+            .expect("an invitation was mailed")
+            .body;
+        let start = body.find("token=").expect("mail contains a token") + "token=".len();
+
+        body[start..].split_whitespace().next().unwrap().to_owned()
+    }
+
+    async fn user_type(db: &DatabaseConnection, user_id: Uuid) -> UserType {
+        entity::user::Entity::find_by_id(user_id)
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap()
+            .user_type
+    }
+
+    /// Invites the address to St. Rudolf and accepts as the user.
+    async fn join(setup: &Setup, user_id: Uuid, email: &str, role: dto::staff::StaffRole) {
+        setup
+            .service
+            .invite(setup.john, setup.rudolf, invitation(email, role))
+            .await
+            .ok()
+            .unwrap();
+        setup
+            .service
+            .accept(user_id, &last_token(&setup.mailer))
+            .await
+            .ok()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn apothecaries_keep_their_last_owner() {
+        let setup = setup().await;
+        let (service, john, rudolf) = (&setup.service, setup.john, setup.rudolf);
+
+        assert!(matches!(
+            service
+                .update_role(john, rudolf, john, StaffRole::Pharmacist)
+                .await,
+            Err(StaffServiceError::LastOwner)
+        ));
+        assert!(matches!(
+            service.remove_member(john, rudolf, john).await,
+            Err(StaffServiceError::LastOwner)
+        ));
+
+        let jane = test_support::customer(&setup.db, "jane@example.com").await;
+        join(
+            &setup,
+            jane,
+            "jane@example.com",
+            dto::staff::StaffRole::Owner,
+        )
+        .await;
+
+        // With a second owner, either of them can step down, but not both.
+        service
+            .update_role(john, rudolf, john, StaffRole::Pharmacist)
+            .await
+            .ok()
+            .unwrap();
+        assert!(matches!(
+            service.remove_member(jane, rudolf, jane).await,
+            Err(StaffServiceError::LastOwner)
+        ));
+
+        service
+            .remove_member(jane, rudolf, john)
+            .await
+            .ok()
+            .unwrap();
+        assert!(find_membership(&setup.db, john, rudolf)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn invitations_are_accepted_by_the_invited_address_only() {
+        let setup = setup().await;
+        let jane = test_support::customer(&setup.db, "jane@example.com").await;
+        let bob = test_support::customer(&setup.db, "bob@example.com").await;
+
+        setup
+            .service
+            .invite(
+                setup.john,
+                setup.rudolf,
+                invitation(" Jane@Example.com", dto::staff::StaffRole::Pharmacist),
+            )
+            .await
+            .ok()
+            .unwrap();
+        let token = last_token(&setup.mailer);
+
+        assert!(matches!(
+            setup.service.accept(bob, &token).await,
+            Err(StaffServiceError::InvalidInvitation)
+        ));
+        assert_eq!(user_type(&setup.db, bob).await, UserType::Customer);
+
+        let membership = setup.service.accept(jane, &token).await.ok().unwrap();
+        assert_eq!(membership.apothecary_id, setup.rudolf);
+        assert_eq!(membership.role, dto::staff::StaffRole::Pharmacist);
+        assert_eq!(user_type(&setup.db, jane).await, UserType::Apothecary);
+
+        // Invitations are used up.
+        assert!(matches!(
+            setup.service.accept(jane, &token).await,
+            Err(StaffServiceError::InvalidInvitation)
+        ));
+    }
+
+    #[tokio::test]
+    async fn inviting_again_replaces_the_earlier_invitation() {
+        let setup = setup().await;
+        let jane = test_support::customer(&setup.db, "jane@example.com").await;
+
+        let first = setup
+            .service
+            .invite(
+                setup.john,
+                setup.rudolf,
+                invitation("jane@example.com", dto::staff::StaffRole::Assistant),
+            )
+            .await
+            .ok()
+            .unwrap();
+        let first_token = last_token(&setup.mailer);
+        let second = setup
+            .service
+            .invite(
+                setup.john,
+                setup.rudolf,
+                invitation("JANE@example.com", dto::staff::StaffRole::Pharmacist),
+            )
+            .await
+            .ok()
+            .unwrap();
+        let second_token = last_token(&setup.mailer);
+
+        let pending = setup
+            .service
+            .get_invitations(setup.john, setup.rudolf, None)
+            .await
+            .ok()
+            .unwrap()
+            .content;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].id, second.id);
+        assert_ne!(first.id, second.id);
+
+        assert!(matches!(
+            setup.service.accept(jane, &first_token).await,
+            Err(StaffServiceError::InvalidInvitation)
+        ));
+
+        let membership = setup
+            .service
+            .accept(jane, &second_token)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(membership.role, dto::staff::StaffRole::Pharmacist);
+    }
+
+    #[tokio::test]
+    async fn members_become_customers_once_they_work_nowhere() {
+        let setup = setup().await;
+        let jane = test_support::customer(&setup.db, "jane@example.com").await;
+        join(
+            &setup,
+            jane,
+            "jane@example.com",
+            dto::staff::StaffRole::Assistant,
+        )
+        .await;
+
+        let krone = entity::apothecary::Entity::find()
+            .filter(entity::apothecary::Column::Name.eq("Zur goldenen Krone"))
+            .one(&setup.db)
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        apothecary_user::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            apothecary_id: Set(krone),
+            user_id: Set(jane),
+            role: Set(StaffRole::Assistant),
+        }
+        .insert(&setup.db)
+        .await
+        .unwrap();
+
+        setup
+            .service
+            .remove_member(setup.john, setup.rudolf, jane)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(user_type(&setup.db, jane).await, UserType::Apothecary);
+
+        setup
+            .service
+            .remove_member(jane, krone, jane)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(user_type(&setup.db, jane).await, UserType::Customer);
+
+        // Admins stay admins.
+        let admin = entity::user::Entity::find()
+            .filter(entity::user::Column::Email.eq("admin@email.com"))
+            .one(&setup.db)
+            .await
+            .unwrap()
+            .unwrap()
+            .id;
+        join(
+            &setup,
+            admin,
+            "admin@email.com",
+            dto::staff::StaffRole::Assistant,
+        )
+        .await;
+        setup
+            .service
+            .remove_member(setup.john, setup.rudolf, admin)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(user_type(&setup.db, admin).await, UserType::Admin);
+    }
+}
@@ -0,0 +1,101 @@
+use dto::{
+    medication::{MedicationQuantity, MedicationQuantityPackage},
+    reservation::MedicationReservationRequest,
+};
+use entity::{apothecary_medication, reservation};
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{
+    prelude::Decimal, ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    Set,
+};
+use serde_json::json;
+use settings::Settings;
+use uuid::Uuid;
+
+/// Settings for an in-memory database, with mails kept in memory as well.
+pub(crate) fn settings() -> Settings {
+    serde_json::from_value(json!({
+        "database": { "url": "sqlite::memory:" },
+        "endpoint": { "host": "127.0.0.1", "port": 0 },
+        "jwt": { "secret": "test-secret" },
+        "mail": {
+            "from": "PharmaTracker <noreply@example.com>",
+            "link_secret": "test-link-secret",
+            "verification_url": "http://localhost/verify-email",
+            "password_reset_url": "http://localhost/reset-password",
+            "invitation_url": "http://localhost/invitation",
+        },
+    }))
+    .expect("test settings are valid")
+}
+
+/// Migrated and seeded in-memory database, with the seed accounts `admin@email.com` and
+/// `john@apo.com`.
+pub(crate) async fn db() -> DatabaseConnection {
+    let db = entity::create_database_connection("sqlite::memory:")
+        .await
+        .unwrap();
+
+    Migrator::up(&db, None).await.unwrap();
+
+    db
+}
+
+/// Adds a verified customer and returns their id.
+pub(crate) async fn customer(db: &DatabaseConnection, email: &str) -> Uuid {
+    let now = crate::user::now();
+
+    entity::user::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        name: Set("Customer".to_owned()),
+        email: Set(email.to_owned()),
+        password: Set(crate::user::hash_password("Correct-Horse-Battery-9")
+            .ok()
+            .unwrap()),
+        user_type: Set(entity::user::UserType::Customer),
+        email_verified_at: Set(Some(now)),
+        pending_email: Set(None),
+        disabled_at: Set(None),
+    }
+    .insert(db)
+    .await
+    .unwrap()
+    .id
+}
+
+/// The seeded stock of 10 packages of Ibuprofen at St. Rudolf.
+pub(crate) async fn seeded_stock(db: &DatabaseConnection) -> apothecary_medication::Model {
+    apothecary_medication::Entity::find()
+        .filter(apothecary_medication::Column::MedicationQuantity.eq(10))
+        .one(db)
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+/// A request for `quantity` packages of the stock.
+pub(crate) fn reservation_request(
+    stock: &apothecary_medication::Model,
+    quantity: u64,
+) -> MedicationReservationRequest {
+    MedicationReservationRequest {
+        apothecary_id: stock.apothecary_id,
+        medication_id: stock.medication_id,
+        quantity: MedicationQuantity::Package(MedicationQuantityPackage {
+            quantity,
+            price: Decimal::new(1099, 2),
+        }),
+    }
+}
+
+/// The only reservation of the customer.
+pub(crate) async fn reservation_of(db: &DatabaseConnection, user_id: Uuid) -> reservation::Model {
+    let mut reservations = reservation::Entity::find()
+        .filter(reservation::Column::UserId.eq(user_id))
+        .all(db)
+        .await
+        .unwrap();
+
+    assert_eq!(reservations.len(), 1);
+    reservations.pop().unwrap()
+}
@@ -0,0 +1,350 @@
+use entity::{
+    audit_event::AuditEventKind,
+    login_throttle::{Entity, ThrottleScope},
+};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ActiveValue::Set,
+    ColumnTrait, ConnectionTrait, DbErr, EntityTrait, ModelTrait, QueryFilter,
+};
+use settings::Login;
+use time::{Duration, PrimitiveDateTime};
+use uuid::Uuid;
+
+use crate::audit;
+
+/// Returns how long the caller has to wait before the next login attempt, if at all.
+pub(crate) async fn wait_time<C: ConnectionTrait>(
+    db: &C,
+    settings: &Login,
+    scope: ThrottleScope,
+    key: &str,
+    now: PrimitiveDateTime,
+) -> Result<Option<Duration>, DbErr> {
+    let Some(throttle) = Entity::find_by_id((scope, key.to_owned())).one(db).await? else {
+        return Ok(None);
+    };
+
+    if let Some(locked_until) = throttle.locked_until.filter(|l| *l > now) {
+        return Ok(Some(locked_until - now));
+    }
+
+    if is_stale(settings, &throttle, now) {
+        return Ok(None);
+    }
+
+    let next_attempt_at = throttle.last_failure_at + backoff(settings, scope, throttle.failures);
+
+    Ok((next_attempt_at > now).then(|| next_attempt_at - now))
+}
+
+/// Counts a request that is limited to `limit` within `window`, e.g. password reset mails.
+/// Returns how long the caller has to wait once the limit is exceeded. Requests are forgotten
+/// once the last one is older than the window, so rejected requests keep the limit in place.
+pub(crate) async fn count_request<C: ConnectionTrait>(
+    db: &C,
+    scope: ThrottleScope,
+    key: &str,
+    limit: i32,
+    window: Duration,
+    now: PrimitiveDateTime,
+) -> Result<Option<Duration>, DbErr> {
+    let throttle = increment(db, scope, key, window, now).await?;
+
+    Ok((throttle.failures > limit).then_some(window))
+}
+
+/// Adds one to the count of a key in a single statement, so that concurrent requests cannot
+/// lose counts. Counting starts over once the last one is older than `window`, or a lockout has
+/// run out.
+async fn increment<C: ConnectionTrait>(
+    db: &C,
+    scope: ThrottleScope,
+    key: &str,
+    window: Duration,
+    now: PrimitiveDateTime,
+) -> Result<entity::login_throttle::Model, DbErr> {
+    use entity::login_throttle::Column;
+
+    let stale = Expr::col((Entity, Column::LastFailureAt))
+        .lte(now - window)
+        .or(Expr::col((Entity, Column::LockedUntil)).lte(now));
+
+    Entity::insert(entity::login_throttle::ActiveModel {
+        scope: Set(scope),
+        key: Set(key.to_owned()),
+        failures: Set(1),
+        last_failure_at: Set(now),
+        locked_until: Set(None),
+    })
+    .on_conflict(
+        OnConflict::columns([Column::Scope, Column::Key])
+            .value(
+                Column::Failures,
+                Expr::case(stale.clone(), 1).finally(Expr::col((Entity, Column::Failures)).add(1)),
+            )
+            .value(
+                Column::LockedUntil,
+                Expr::case(stale, Expr::value(Option::<PrimitiveDateTime>::None))
+                    .finally(Expr::col((Entity, Column::LockedUntil))),
+            )
+            .value(Column::LastFailureAt, Expr::value(now))
+            .to_owned(),
+    )
+    .exec_with_returning(db)
+    .await
+}
+
+/// Counts a failed attempt. Returns whether this failure locked the account or IP address.
+async fn record_failure<C: ConnectionTrait>(
+    db: &C,
+    settings: &Login,
+    scope: ThrottleScope,
+    key: &str,
+    now: PrimitiveDateTime,
+) -> Result<bool, DbErr> {
+    use entity::login_throttle::Column;
+
+    let window = Duration::minutes(settings.failure_window_minutes);
+    let throttle = increment(db, scope, key, window, now).await?;
+
+    let threshold = if scope.is_ip() {
+        settings.ip_lockout_threshold
+    } else {
+        settings.account_lockout_threshold
+    };
+
+    if throttle.failures < threshold {
+        return Ok(false);
+    }
+
+    // Guarded on the count this failure produced, so that only one of several concurrent
+    // failures reaching the threshold records the lockout.
+    let locked = Entity::update_many()
+        .col_expr(Column::Failures, Expr::value(0))
+        .col_expr(
+            Column::LockedUntil,
+            Expr::value(Some(now + Duration::minutes(settings.lockout_minutes))),
+        )
+        .filter(Column::Scope.eq(scope))
+        .filter(Column::Key.eq(key))
+        .filter(Column::Failures.eq(throttle.failures))
+        .exec(db)
+        .await?;
+
+    Ok(locked.rows_affected == 1)
+}
+
+/// Counts a failed login step and records a lockout in the audit log.
+pub(crate) async fn record_login_failure<C: ConnectionTrait>(
+    db: &C,
+    settings: &Login,
+    user_id: Option<Uuid>,
+    scope: ThrottleScope,
+    key: &str,
+    ip: Option<String>,
+    now: PrimitiveDateTime,
+) -> Result<(), DbErr> {
+    if !record_failure(db, settings, scope, key, now).await? {
+        return Ok(());
+    }
+
+    let (kind, detail) = if scope.is_ip() {
+        tracing::warn!("Locked IP address {} after failed logins", key);
+        (
+            AuditEventKind::IpLocked,
+            format!(
+                "Logins from {} locked for {} minutes after {} failed attempts",
+                key, settings.lockout_minutes, settings.ip_lockout_threshold
+            ),
+        )
+    } else {
+        tracing::warn!("Locked login for {} after failed attempts", key);
+        (
+            AuditEventKind::AccountLocked,
+            format!(
+                "Login for {} locked for {} minutes after {} failed attempts",
+                if user_id.is_some() {
+                    "account"
+                } else {
+                    "unknown account"
+                },
+                settings.lockout_minutes,
+                settings.account_lockout_threshold
+            ),
+        )
+    };
+
+    audit::record(db, user_id, kind, ip, detail).await?;
+
+    Ok(())
+}
+
+pub(crate) async fn reset<C: ConnectionTrait>(
+    db: &C,
+    scope: ThrottleScope,
+    key: &str,
+) -> Result<(), DbErr> {
+    if let Some(throttle) = Entity::find_by_id((scope, key.to_owned())).one(db).await? {
+        throttle.delete(db).await?;
+    }
+
+    Ok(())
+}
+
+/// Failures are forgotten after the window, and once a lockout has run out.
+fn is_stale(
+    settings: &Login,
+    throttle: &entity::login_throttle::Model,
+    now: PrimitiveDateTime,
+) -> bool {
+    throttle.last_failure_at + Duration::minutes(settings.failure_window_minutes) <= now
+        || throttle.locked_until.is_some_and(|l| l <= now)
+}
+
+fn backoff(settings: &Login, scope: ThrottleScope, failures: i32) -> Duration {
+    let free_attempts = if scope.is_ip() {
+        settings.ip_free_attempts
+    } else {
+        settings.free_attempts
+    };
+
+    let exponent = failures - free_attempts - 1;
+
+    if exponent < 0 {
+        return Duration::ZERO;
+    }
+
+    let seconds = settings
+        .backoff_base_seconds
+        .saturating_mul(1 << exponent.min(32));
+
+    Duration::seconds(seconds.min(settings.backoff_max_seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::test_support;
+
+    fn now() -> PrimitiveDateTime {
+        let now = OffsetDateTime::now_utc();
+        PrimitiveDateTime::new(now.date(), now.time())
+    }
+
+    #[tokio::test]
+    async fn failures_beyond_the_free_attempts_back_off_exponentially() {
+        let db = test_support::db().await;
+        let settings = Login::default();
+        let now = now();
+
+        for (scope, key, free_attempts) in [
+            (
+                ThrottleScope::Account,
+                "jane@example.com",
+                settings.free_attempts,
+            ),
+            (ThrottleScope::Ip, "127.0.0.1", settings.ip_free_attempts),
+        ] {
+            for _ in 0..free_attempts {
+                assert!(!record_failure(&db, &settings, scope, key, now)
+                    .await
+                    .unwrap());
+                assert_eq!(
+                    wait_time(&db, &settings, scope, key, now).await.unwrap(),
+                    None
+                );
+            }
+
+            for exponent in 0..3 {
+                assert!(!record_failure(&db, &settings, scope, key, now)
+                    .await
+                    .unwrap());
+                assert_eq!(
+                    wait_time(&db, &settings, scope, key, now).await.unwrap(),
+                    Some(Duration::seconds(settings.backoff_base_seconds << exponent)),
+                    "{scope:?} after {} failures",
+                    free_attempts + exponent + 1
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reaching_the_threshold_locks_once() {
+        let db = test_support::db().await;
+        let settings = Login::default();
+        let now = now();
+        let key = "jane@example.com";
+
+        for _ in 1..settings.account_lockout_threshold {
+            assert!(
+                !record_failure(&db, &settings, ThrottleScope::Account, key, now)
+                    .await
+                    .unwrap()
+            );
+        }
+
+        assert!(
+            record_failure(&db, &settings, ThrottleScope::Account, key, now)
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            wait_time(&db, &settings, ThrottleScope::Account, key, now)
+                .await
+                .unwrap(),
+            Some(Duration::minutes(settings.lockout_minutes))
+        );
+
+        // Once the lockout has run out, counting starts over.
+        let later = now + Duration::minutes(settings.lockout_minutes);
+        assert_eq!(
+            wait_time(&db, &settings, ThrottleScope::Account, key, later)
+                .await
+                .unwrap(),
+            None
+        );
+        assert!(
+            !record_failure(&db, &settings, ThrottleScope::Account, key, later)
+                .await
+                .unwrap()
+        );
+
+        let throttle = Entity::find_by_id((ThrottleScope::Account, key.to_owned()))
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(throttle.failures, 1);
+        assert_eq!(throttle.locked_until, None);
+    }
+
+    #[tokio::test]
+    async fn failures_are_forgotten_after_the_window() {
+        let db = test_support::db().await;
+        let settings = Login::default();
+        let now = now();
+        let key = "127.0.0.1";
+
+        for _ in 0..settings.ip_free_attempts + 1 {
+            record_failure(&db, &settings, ThrottleScope::Ip, key, now)
+                .await
+                .unwrap();
+        }
+
+        let later = now + Duration::minutes(settings.failure_window_minutes);
+        record_failure(&db, &settings, ThrottleScope::Ip, key, later)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            wait_time(&db, &settings, ThrottleScope::Ip, key, later)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
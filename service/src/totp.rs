@@ -0,0 +1,634 @@
+use std::{fmt::Display, sync::Arc};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use data_encoding::BASE32_NOPAD;
+use dto::totp::TotpEnrollment;
+use entity::{
+    audit_event::AuditEventKind, login_throttle::ThrottleScope, user::UserType, user_totp::Entity,
+    DatabaseConnection,
+};
+use hmac::{Hmac, Mac};
+use sea_orm::{
+    entity::prelude::*, sea_query::Expr, Condition, ConnectionTrait, Set, TransactionTrait,
+};
+use settings::Settings;
+use sha1::Sha1;
+use time::{Duration, PrimitiveDateTime};
+use uuid::Uuid;
+
+use crate::{
+    audit,
+    hash::sha256_hex,
+    throttle,
+    user::{now, verify_password, User, UserServiceError},
+};
+
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+
+pub enum TotpServiceError {
+    UserNotFound,
+    AlreadyEnabled,
+    NotEnabled,
+    InvalidCode,
+    InvalidCredentials,
+    /// Two-factor authentication is mandatory for the account and cannot be turned off.
+    Required,
+    TooManyAttempts(Duration),
+    Anyhow(anyhow::Error),
+}
+
+impl From<DbErr> for TotpServiceError {
+    fn from(err: DbErr) -> Self {
+        Self::Anyhow(err.into())
+    }
+}
+
+impl Display for TotpServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TotpServiceError::UserNotFound => write!(f, "User not found"),
+            TotpServiceError::AlreadyEnabled => {
+                write!(f, "Two-factor authentication is already enabled")
+            }
+            TotpServiceError::NotEnabled => write!(f, "Two-factor authentication is not enabled"),
+            TotpServiceError::InvalidCode => write!(f, "Invalid code"),
+            TotpServiceError::InvalidCredentials => write!(f, "Invalid credentials"),
+            TotpServiceError::Required => {
+                write!(f, "Two-factor authentication is mandatory for this account")
+            }
+            TotpServiceError::TooManyAttempts(_) => {
+                write!(f, "Too many failed attempts, try again later")
+            }
+            TotpServiceError::Anyhow(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub struct TotpService {
+    db: DatabaseConnection,
+    settings: Arc<Settings>,
+}
+
+impl TotpService {
+    pub fn new(db: DatabaseConnection, settings: Arc<Settings>) -> Self {
+        Self { db, settings }
+    }
+
+    /// Starts an enrollment, replacing an earlier one that was never confirmed.
+    pub async fn enroll(&self, user_id: Uuid) -> Result<TotpEnrollment, TotpServiceError> {
+        let user = self.get_user(user_id).await?;
+
+        if Entity::find_by_id(user.id)
+            .one(&self.db)
+            .await?
+            .is_some_and(|totp| totp.confirmed_at.is_some())
+        {
+            return Err(TotpServiceError::AlreadyEnabled);
+        }
+
+        Ok(begin_enrollment(&self.db, &self.settings.totp, &user).await?)
+    }
+
+    /// Enables two-factor authentication once the first code from the app matches, and returns
+    /// the recovery codes.
+    pub async fn confirm(
+        &self,
+        user_id: Uuid,
+        code: &str,
+    ) -> Result<Vec<String>, TotpServiceError> {
+        let user = self.get_user(user_id).await?;
+        let totp = Entity::find_by_id(user.id)
+            .one(&self.db)
+            .await?
+            .ok_or(TotpServiceError::NotEnabled)?;
+
+        if totp.confirmed_at.is_some() {
+            return Err(TotpServiceError::AlreadyEnabled);
+        }
+
+        self.check_code(&user, &totp, code).await?;
+
+        Ok(confirm_enrollment(&self.db, &self.settings.totp, totp).await?)
+    }
+
+    pub async fn disable(
+        &self,
+        user_id: Uuid,
+        request: dto::totp::DisableTotpRequest,
+    ) -> Result<(), TotpServiceError> {
+        let user = self.get_user(user_id).await?;
+
+        if is_required(&self.settings.totp, &user) {
+            return Err(TotpServiceError::Required);
+        }
+
+        match verify_password(&user, &request.password) {
+            Ok(()) => {}
+            Err(UserServiceError::InvalidCredentials) => {
+                return Err(TotpServiceError::InvalidCredentials)
+            }
+            Err(e) => return Err(TotpServiceError::Anyhow(anyhow::anyhow!("{}", e))),
+        }
+
+        let totp = self.get_confirmed(&user).await?;
+
+        self.check_code(&user, &totp, &request.code).await?;
+
+        let txn = self.db.begin().await?;
+
+        delete_for_user(&txn, user.id).await?;
+
+        audit::record(
+            &txn,
+            Some(user.id),
+            AuditEventKind::TotpDisabled,
+            None,
+            "Two-factor authentication disabled".to_owned(),
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Replaces all recovery codes, used or not.
+    pub async fn regenerate_recovery_codes(
+        &self,
+        user_id: Uuid,
+        code: &str,
+    ) -> Result<Vec<String>, TotpServiceError> {
+        let user = self.get_user(user_id).await?;
+        let totp = self.get_confirmed(&user).await?;
+
+        self.check_code(&user, &totp, code).await?;
+
+        Ok(replace_recovery_codes(&self.db, &self.settings.totp, user.id).await?)
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> Result<User, TotpServiceError> {
+        entity::user::Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(TotpServiceError::UserNotFound)
+    }
+
+    async fn get_confirmed(
+        &self,
+        user: &User,
+    ) -> Result<entity::user_totp::Model, TotpServiceError> {
+        Entity::find_by_id(user.id)
+            .one(&self.db)
+            .await?
+            .filter(|totp| totp.confirmed_at.is_some())
+            .ok_or(TotpServiceError::NotEnabled)
+    }
+
+    /// Codes are throttled like passwords, as six digits are quickly guessed otherwise.
+    async fn check_code(
+        &self,
+        user: &User,
+        totp: &entity::user_totp::Model,
+        code: &str,
+    ) -> Result<(), TotpServiceError> {
+        let now = now();
+
+        if let Some(wait) = throttle::wait_time(
+            &self.db,
+            &self.settings.login,
+            ThrottleScope::Account,
+            &user.email,
+            now,
+        )
+        .await?
+        {
+            return Err(TotpServiceError::TooManyAttempts(wait));
+        }
+
+        if verify_code(&self.db, &self.settings.totp, totp, code, now).await? {
+            throttle::reset(&self.db, ThrottleScope::Account, &user.email).await?;
+            Ok(())
+        } else {
+            throttle::record_login_failure(
+                &self.db,
+                &self.settings.login,
+                Some(user.id),
+                ThrottleScope::Account,
+                &user.email,
+                None,
+                now,
+            )
+            .await?;
+
+            Err(TotpServiceError::InvalidCode)
+        }
+    }
+}
+
+pub(crate) fn is_required(settings: &settings::Totp, user: &User) -> bool {
+    settings.required_for_staff && matches!(user.user_type, UserType::Apothecary | UserType::Admin)
+}
+
+/// Stores a new unconfirmed secret for the user.
+pub(crate) async fn begin_enrollment<C: ConnectionTrait>(
+    db: &C,
+    settings: &settings::Totp,
+    user: &User,
+) -> Result<TotpEnrollment, DbErr> {
+    let mut bytes = [0u8; 20];
+    OsRng.fill_bytes(&mut bytes);
+    let secret = BASE32_NOPAD.encode(&bytes);
+
+    Entity::delete_by_id(user.id).exec(db).await?;
+
+    entity::user_totp::ActiveModel {
+        user_id: Set(user.id),
+        secret: Set(secret.clone()),
+        created_at: Set(now()),
+        confirmed_at: Set(None),
+        last_used_step: Set(None),
+    }
+    .insert(db)
+    .await?;
+
+    let issuer = urlencoding::encode(&settings.issuer);
+
+    Ok(TotpEnrollment {
+        otpauth_uri: format!(
+            "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+            issuer,
+            urlencoding::encode(&user.email),
+            secret,
+            issuer,
+            DIGITS,
+            STEP_SECONDS
+        ),
+        secret,
+    })
+}
+
+/// Marks the enrollment as confirmed and returns fresh recovery codes.
+pub(crate) async fn confirm_enrollment<C: ConnectionTrait + TransactionTrait>(
+    db: &C,
+    settings: &settings::Totp,
+    totp: entity::user_totp::Model,
+) -> Result<Vec<String>, DbErr> {
+    let txn = db.begin().await?;
+    let user_id = totp.user_id;
+
+    let mut totp: entity::user_totp::ActiveModel = totp.into();
+    totp.confirmed_at = Set(Some(now()));
+    totp.update(&txn).await?;
+
+    let codes = replace_recovery_codes(&txn, settings, user_id).await?;
+
+    audit::record(
+        &txn,
+        Some(user_id),
+        AuditEventKind::TotpEnabled,
+        None,
+        "Two-factor authentication enabled".to_owned(),
+    )
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(codes)
+}
+
+/// Checks a code against the current time step and its neighbours. Each step is accepted only
+/// once, so an observed code cannot be replayed.
+pub(crate) async fn verify_code<C: ConnectionTrait>(
+    db: &C,
+    settings: &settings::Totp,
+    totp: &entity::user_totp::Model,
+    code: &str,
+    now: PrimitiveDateTime,
+) -> Result<bool, DbErr> {
+    let Ok(secret) = BASE32_NOPAD.decode(totp.secret.as_bytes()) else {
+        return Ok(false);
+    };
+
+    let code = code.trim();
+
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Ok(false);
+    }
+
+    let current = now.assume_utc().unix_timestamp() / STEP_SECONDS;
+
+    let Some(step) = (current - settings.allowed_drift_steps
+        ..=current + settings.allowed_drift_steps)
+        .filter(|step| totp.last_used_step.is_none_or(|last| *step > last))
+        .find(|step| {
+            format!(
+                "{:0width$}",
+                code_at(&secret, *step),
+                width = DIGITS as usize
+            ) == code
+        })
+    else {
+        return Ok(false);
+    };
+
+    let result = Entity::update_many()
+        .col_expr(entity::user_totp::Column::LastUsedStep, Expr::value(step))
+        .filter(entity::user_totp::Column::UserId.eq(totp.user_id))
+        .filter(
+            Condition::any()
+                .add(entity::user_totp::Column::LastUsedStep.is_null())
+                .add(entity::user_totp::Column::LastUsedStep.lt(step)),
+        )
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected == 1)
+}
+
+/// Marks the recovery code as used, if it belongs to the user and was not used before.
+pub(crate) async fn use_recovery_code<C: ConnectionTrait>(
+    db: &C,
+    user_id: Uuid,
+    code: &str,
+) -> Result<bool, DbErr> {
+    let result = entity::totp_recovery_code::Entity::update_many()
+        .col_expr(
+            entity::totp_recovery_code::Column::UsedAt,
+            Expr::value(Some(now())),
+        )
+        .filter(entity::totp_recovery_code::Column::UserId.eq(user_id))
+        .filter(
+            entity::totp_recovery_code::Column::CodeHash
+                .eq(sha256_hex(normalize_recovery_code(code))),
+        )
+        .filter(entity::totp_recovery_code::Column::UsedAt.is_null())
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected == 1)
+}
+
+pub(crate) async fn delete_for_user<C: ConnectionTrait>(
+    db: &C,
+    user_id: Uuid,
+) -> Result<(), DbErr> {
+    entity::totp_recovery_code::Entity::delete_many()
+        .filter(entity::totp_recovery_code::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    Entity::delete_by_id(user_id).exec(db).await?;
+
+    Ok(())
+}
+
+async fn replace_recovery_codes<C: ConnectionTrait>(
+    db: &C,
+    settings: &settings::Totp,
+    user_id: Uuid,
+) -> Result<Vec<String>, DbErr> {
+    entity::totp_recovery_code::Entity::delete_many()
+        .filter(entity::totp_recovery_code::Column::UserId.eq(user_id))
+        .exec(db)
+        .await?;
+
+    let now = now();
+    let codes: Vec<String> = (0..settings.recovery_codes)
+        .map(|_| generate_recovery_code())
+        .collect();
+
+    if codes.is_empty() {
+        return Ok(codes);
+    }
+
+    entity::totp_recovery_code::Entity::insert_many(codes.iter().map(|code| {
+        entity::totp_recovery_code::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(user_id),
+            code_hash: Set(sha256_hex(normalize_recovery_code(code))),
+            created_at: Set(now),
+            used_at: Set(None),
+        }
+    }))
+    .exec(db)
+    .await?;
+
+    Ok(codes)
+}
+
+/// RFC 6238 code for a time step, HMAC-SHA1 with dynamic truncation.
+fn code_at(secret: &[u8], step: i64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Ten base32 characters, grouped as `XXXXX-XXXXX`.
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 7];
+    OsRng.fill_bytes(&mut bytes);
+    let encoded = BASE32_NOPAD.encode(&bytes);
+
+    format!("{}-{}", &encoded[..5], &encoded[5..10])
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::test_support;
+
+    /// An enrolled and confirmed user, with the decoded secret and the recovery codes.
+    async fn enrolled(
+        db: &DatabaseConnection,
+        settings: &settings::Totp,
+        email: &str,
+    ) -> (Uuid, Vec<u8>, Vec<String>) {
+        let user_id = test_support::customer(db, email).await;
+        let user = entity::user::Entity::find_by_id(user_id)
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let enrollment = begin_enrollment(db, settings, &user).await.unwrap();
+        let totp = Entity::find_by_id(user_id).one(db).await.unwrap().unwrap();
+        let codes = confirm_enrollment(db, settings, totp).await.unwrap();
+
+        (
+            user_id,
+            BASE32_NOPAD.decode(enrollment.secret.as_bytes()).unwrap(),
+            codes,
+        )
+    }
+
+    async fn verify(
+        db: &DatabaseConnection,
+        settings: &settings::Totp,
+        user_id: Uuid,
+        code: &str,
+        now: PrimitiveDateTime,
+    ) -> bool {
+        let totp = Entity::find_by_id(user_id).one(db).await.unwrap().unwrap();
+
+        verify_code(db, settings, &totp, code, now).await.unwrap()
+    }
+
+    fn at(unix_timestamp: i64) -> PrimitiveDateTime {
+        let time = OffsetDateTime::from_unix_timestamp(unix_timestamp).unwrap();
+
+        PrimitiveDateTime::new(time.date(), time.time())
+    }
+
+    fn code(secret: &[u8], step: i64) -> String {
+        format!("{:06}", code_at(secret, step))
+    }
+
+    #[test]
+    fn codes_match_rfc_6238_vectors() {
+        // Appendix B, SHA1, cut down to the six digits used here.
+        let secret = b"12345678901234567890";
+
+        for (time, expected) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ] {
+            assert_eq!(
+                code_at(secret, time / STEP_SECONDS),
+                expected,
+                "at {}",
+                time
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn codes_are_accepted_once_per_step() {
+        let db = test_support::db().await;
+        let settings = settings::Totp::default();
+        let (user_id, secret, _) = enrolled(&db, &settings, "jane@example.com").await;
+        let now = at(1_800_000_000);
+        let step = 1_800_000_000 / STEP_SECONDS;
+
+        assert!(verify(&db, &settings, user_id, &code(&secret, step), now).await);
+        assert!(!verify(&db, &settings, user_id, &code(&secret, step), now).await);
+
+        // A code of an earlier step is no longer accepted either.
+        assert!(!verify(&db, &settings, user_id, &code(&secret, step - 1), now).await);
+    }
+
+    #[tokio::test]
+    async fn codes_of_neighbouring_steps_are_accepted() {
+        let db = test_support::db().await;
+        let settings = settings::Totp::default();
+        let now = at(1_800_000_000);
+        let step = 1_800_000_000 / STEP_SECONDS;
+
+        for (email, offset, accepted) in [
+            ("behind@example.com", -2, false),
+            ("late@example.com", -1, true),
+            ("early@example.com", 1, true),
+            ("ahead@example.com", 2, false),
+        ] {
+            let (user_id, secret, _) = enrolled(&db, &settings, email).await;
+
+            assert_eq!(
+                verify(&db, &settings, user_id, &code(&secret, step + offset), now).await,
+                accepted,
+                "offset {}",
+                offset
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn malformed_codes_are_rejected() {
+        let db = test_support::db().await;
+        let settings = settings::Totp::default();
+        let (user_id, _, _) = enrolled(&db, &settings, "jane@example.com").await;
+
+        for code in ["", "12345", "1234567", "12345a"] {
+            assert!(
+                !verify(&db, &settings, user_id, code, now()).await,
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn recovery_codes_work_once() {
+        let db = test_support::db().await;
+        let settings = settings::Totp::default();
+        let (user_id, _, codes) = enrolled(&db, &settings, "jane@example.com").await;
+        let (other_id, _, _) = enrolled(&db, &settings, "john@example.com").await;
+
+        assert_eq!(codes.len(), settings.recovery_codes);
+
+        // Codes of other users don't count, and the formatting of a code doesn't matter.
+        assert!(!use_recovery_code(&db, other_id, &codes[0]).await.unwrap());
+        assert!(
+            use_recovery_code(&db, user_id, &codes[0].to_lowercase().replace('-', " "))
+                .await
+                .unwrap()
+        );
+        assert!(!use_recovery_code(&db, user_id, &codes[0]).await.unwrap());
+        assert!(use_recovery_code(&db, user_id, &codes[1]).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn enrollment_uri_describes_the_secret() {
+        let db = test_support::db().await;
+        let settings = settings::Totp {
+            issuer: "Pharma Tracker".to_owned(),
+            ..Default::default()
+        };
+        let user_id = test_support::customer(&db, "jane+2fa@example.com").await;
+        let user = entity::user::Entity::find_by_id(user_id)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let enrollment = begin_enrollment(&db, &settings, &user).await.unwrap();
+
+        assert_eq!(
+            enrollment.otpauth_uri,
+            format!(
+                "otpauth://totp/Pharma%20Tracker:jane%2B2fa%40example.com?secret={}\
+                 &issuer=Pharma%20Tracker&algorithm=SHA1&digits=6&period=30",
+                enrollment.secret
+            )
+        );
+        assert_eq!(
+            BASE32_NOPAD
+                .decode(enrollment.secret.as_bytes())
+                .unwrap()
+                .len(),
+            20
+        );
+    }
+}
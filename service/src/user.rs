@@ -1,20 +1,47 @@
-use std::fmt::Display;
+use std::{
+    fmt::Display,
+    net::IpAddr,
+    sync::{Arc, OnceLock},
+};
 
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use entity::user::Entity;
 pub use entity::user::Model as User;
 use entity::DatabaseConnection;
-use sea_orm::{entity::prelude::*, Set, TryIntoModel};
+use entity::{audit_event::AuditEventKind, login_throttle::ThrottleScope, user::Entity};
+use sea_orm::{
+    entity::prelude::*, sea_query::Expr, Condition, Set, TransactionTrait, TryIntoModel,
+};
+use settings::Settings;
+use time::{Duration, OffsetDateTime, PrimitiveDateTime};
 use tracing::debug;
 use uuid::Uuid;
 
+use crate::{
+    audit,
+    hash::{hmac_sha256_hex, random_token, sha256_hex, verify_hmac_sha256_hex},
+    mail::{MailMessage, Mailer},
+    password::{self, PasswordViolation},
+    reservation::anonymize_reservations,
+    throttle, totp,
+};
+
 pub enum UserServiceError {
     InvalidCredentials,
+    TooManyAttempts(Duration),
     UserAlreadyExists,
     UserNotFound,
+    EmailNotVerified,
+    InvalidVerificationToken,
+    InvalidResetToken,
+    InvalidLoginChallenge,
+    /// The action needs the password, or a login that happened just now.
+    ReauthenticationRequired,
+    AccountDisabled,
+    /// The password in the named request field does not meet the password policy.
+    InvalidPassword(&'static str, Vec<PasswordViolation>),
     Anyhow(anyhow::Error),
 }
 
@@ -34,75 +61,1233 @@ impl Display for UserServiceError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             UserServiceError::InvalidCredentials => write!(f, "Invalid credentials"),
+            UserServiceError::TooManyAttempts(_) => {
+                write!(f, "Too many failed login attempts, try again later")
+            }
             UserServiceError::UserAlreadyExists => write!(f, "User already exists"),
             UserServiceError::UserNotFound => write!(f, "User not found"),
+            UserServiceError::EmailNotVerified => write!(f, "Email address is not verified"),
+            UserServiceError::InvalidVerificationToken => {
+                write!(f, "Invalid or expired verification link")
+            }
+            UserServiceError::InvalidResetToken => write!(f, "Invalid or expired reset link"),
+            UserServiceError::InvalidLoginChallenge => {
+                write!(f, "Login expired, sign in again")
+            }
+            UserServiceError::ReauthenticationRequired => {
+                write!(f, "Confirm your password or sign in again")
+            }
+            UserServiceError::AccountDisabled => write!(f, "Account is disabled"),
+            UserServiceError::InvalidPassword(_, _) => {
+                write!(f, "Password does not meet the requirements")
+            }
             UserServiceError::Anyhow(e) => write!(f, "{}", e),
         }
     }
 }
 
+pub enum LoginOutcome {
+    Authenticated(User),
+    /// The password was accepted, but the login has to be completed with a second factor.
+    SecondFactorRequired(dto::totp::SecondFactorChallenge),
+}
+
 pub struct UserService {
     db: DatabaseConnection,
+    settings: Arc<Settings>,
+    mailer: Arc<dyn Mailer>,
+    /// Signs the links sent by mail, see [`settings::Mail::link_secret`].
+    link_secret: String,
+    /// Signs login challenges, see [`settings::Totp::challenge_secret`].
+    challenge_secret: String,
 }
 
 impl UserService {
-    pub fn new(db: DatabaseConnection) -> Self {
-        Self { db }
+    pub fn new(db: DatabaseConnection, settings: Arc<Settings>, mailer: Arc<dyn Mailer>) -> Self {
+        let link_secret = settings
+            .mail
+            .link_secret
+            .clone()
+            .unwrap_or_else(random_token);
+        let challenge_secret = settings
+            .totp
+            .challenge_secret
+            .clone()
+            .unwrap_or_else(random_token);
+
+        Self {
+            db,
+            settings,
+            mailer,
+            link_secret,
+            challenge_secret,
+        }
     }
 
     pub async fn get_by_id(&self, id: Uuid) -> Result<User, UserServiceError> {
-        Ok(Entity::find_by_id(id)
+        Entity::find_by_id(id)
             .one(&self.db)
             .await?
-            .ok_or(UserServiceError::UserNotFound)?)
+            .ok_or(UserServiceError::UserNotFound)
+    }
+
+    /// Like [`Self::get_by_id`], but fails for disabled accounts.
+    pub async fn get_active(&self, id: Uuid) -> Result<User, UserServiceError> {
+        let user = self.get_by_id(id).await?;
+
+        if user.disabled_at.is_some() {
+            return Err(UserServiceError::AccountDisabled);
+        }
+
+        Ok(user)
     }
 
-    pub async fn login(&self, user_login: dto::user::UserLogin) -> Result<User, UserServiceError> {
+    /// Checks the credentials. Failed attempts are counted per account and per IP address;
+    /// repeated failures make further attempts wait and eventually lock the account or address.
+    /// Accounts with two-factor authentication get a challenge for [`Self::login_second_factor`].
+    pub async fn login(
+        &self,
+        user_login: dto::user::UserLogin,
+        ip: IpAddr,
+    ) -> Result<LoginOutcome, UserServiceError> {
+        let settings = &self.settings.login;
+        let now = now();
+        let email = normalize_email(&user_login.email);
+        let account_key = email.clone();
+        let ip_key = ip.to_string();
+
+        for (scope, key) in [
+            (ThrottleScope::Account, &account_key),
+            (ThrottleScope::Ip, &ip_key),
+        ] {
+            if let Some(wait) = throttle::wait_time(&self.db, settings, scope, key, now).await? {
+                return Err(UserServiceError::TooManyAttempts(wait));
+            }
+        }
+
         let user = Entity::find()
-            .filter(entity::user::Column::Email.contains(user_login.email))
+            .filter(entity::user::Column::Email.eq(email))
+            .one(&self.db)
+            .await?;
+
+        let verified = match &user {
+            Some(user) => verify_password(user, &user_login.password),
+            None => {
+                // Takes as long as checking a real password, so unknown addresses do not stand out.
+                let _ = verify_hash(dummy_hash(), &user_login.password);
+                Err(UserServiceError::InvalidCredentials)
+            }
+        };
+
+        match verified {
+            Ok(()) => throttle::reset(&self.db, ThrottleScope::Account, &account_key).await?,
+            Err(UserServiceError::InvalidCredentials) => {
+                self.record_login_failure(user.as_ref().map(|u| u.id), &account_key, &ip_key, now)
+                    .await?;
+
+                return Err(UserServiceError::InvalidCredentials);
+            }
+            Err(e) => return Err(e),
+        }
+
+        let user = user.ok_or(UserServiceError::InvalidCredentials)?;
+
+        if user.email_verified_at.is_none() {
+            return Err(UserServiceError::EmailNotVerified);
+        }
+
+        self.finish_login(user).await
+    }
+
+    /// Decides whether a user whose first factor was accepted, by password or an identity
+    /// provider, is logged in or still has to provide a TOTP code.
+    pub async fn finish_login(&self, user: User) -> Result<LoginOutcome, UserServiceError> {
+        if user.disabled_at.is_some() {
+            return Err(UserServiceError::AccountDisabled);
+        }
+
+        let enrolled = entity::user_totp::Entity::find_by_id(user.id)
             .one(&self.db)
             .await?
-            .ok_or(UserServiceError::InvalidCredentials)?;
+            .is_some_and(|totp| totp.confirmed_at.is_some());
 
-        let parsed_hash = PasswordHash::new(&user.password).map_err(anyhow::Error::from)?;
+        let enrollment = if enrolled {
+            None
+        } else if totp::is_required(&self.settings.totp, &user) {
+            Some(totp::begin_enrollment(&self.db, &self.settings.totp, &user).await?)
+        } else {
+            return Ok(LoginOutcome::Authenticated(user));
+        };
 
-        Argon2::default()
-            .verify_password(user_login.password.as_bytes(), &parsed_hash)
-            .map_err(|e| match e {
-                argon2::password_hash::Error::Password => UserServiceError::InvalidCredentials,
-                _ => UserServiceError::Anyhow(anyhow::Error::from(e)),
-            })?;
+        Ok(LoginOutcome::SecondFactorRequired(
+            dto::totp::SecondFactorChallenge {
+                challenge: self.login_challenge(&user),
+                expires_in: self.settings.totp.challenge_lifetime,
+                enrollment,
+            },
+        ))
+    }
 
-        Ok(user)
+    /// Second step of a login that requires a TOTP or recovery code. Finishing an enrollment
+    /// confirms it and returns the new recovery codes along with the user.
+    pub async fn login_second_factor(
+        &self,
+        request: dto::totp::SecondFactorLogin,
+        ip: IpAddr,
+    ) -> Result<(User, Vec<String>), UserServiceError> {
+        let settings = &self.settings.login;
+        let now = now();
+        let ip_key = ip.to_string();
+
+        if let Some(wait) =
+            throttle::wait_time(&self.db, settings, ThrottleScope::Ip, &ip_key, now).await?
+        {
+            return Err(UserServiceError::TooManyAttempts(wait));
+        }
+
+        let user = self.verify_login_challenge(&request.challenge).await?;
+
+        if user.disabled_at.is_some() {
+            return Err(UserServiceError::AccountDisabled);
+        }
+
+        if let Some(wait) =
+            throttle::wait_time(&self.db, settings, ThrottleScope::Account, &user.email, now)
+                .await?
+        {
+            return Err(UserServiceError::TooManyAttempts(wait));
+        }
+
+        let totp = entity::user_totp::Entity::find_by_id(user.id)
+            .one(&self.db)
+            .await?
+            .ok_or(UserServiceError::InvalidLoginChallenge)?;
+
+        let accepted = match (&request.code, &request.recovery_code) {
+            (Some(code), _) => {
+                totp::verify_code(&self.db, &self.settings.totp, &totp, code, now).await?
+            }
+            // Recovery codes only exist once an enrollment is confirmed.
+            (None, Some(recovery_code)) => {
+                let used = totp::use_recovery_code(&self.db, user.id, recovery_code).await?;
+
+                if used {
+                    audit::record(
+                        &self.db,
+                        Some(user.id),
+                        AuditEventKind::RecoveryCodeUsed,
+                        Some(ip_key.clone()),
+                        "Logged in with a recovery code".to_owned(),
+                    )
+                    .await?;
+                }
+
+                used
+            }
+            (None, None) => false,
+        };
+
+        if !accepted {
+            self.record_login_failure(Some(user.id), &user.email, &ip_key, now)
+                .await?;
+
+            return Err(UserServiceError::InvalidCredentials);
+        }
+
+        throttle::reset(&self.db, ThrottleScope::Account, &user.email).await?;
+
+        let recovery_codes = if totp.confirmed_at.is_none() {
+            totp::confirm_enrollment(&self.db, &self.settings.totp, totp).await?
+        } else {
+            vec![]
+        };
+
+        Ok((user, recovery_codes))
     }
 
     pub async fn register(
         &self,
         user_registration: dto::user::UserRegistration,
     ) -> Result<User, UserServiceError> {
-        debug!("register({:?})", user_registration);
+        debug!("register({:?})", user_registration.email);
 
-        let password_hash = Argon2::default()
-            .hash_password(
-                user_registration.password.as_bytes(),
-                &SaltString::generate(&mut OsRng),
-            )
-            .map_err(anyhow::Error::from)?
-            .to_string();
+        self.check_password("password", &user_registration.password)
+            .await?;
 
-        Ok(entity::user::ActiveModel {
+        let password_hash = hash_password(&user_registration.password)?;
+
+        let user = entity::user::ActiveModel {
             id: Set(Uuid::new_v4()),
             name: Set(user_registration.name),
-            email: Set(user_registration.email),
+            email: Set(normalize_email(&user_registration.email)),
             password: Set(password_hash),
             user_type: Set(entity::user::UserType::Customer),
+            email_verified_at: Set(None),
+            pending_email: Set(None),
+            disabled_at: Set(None),
         }
         .insert(&self.db)
         .await
-        .map_err(|e| match e.sql_err() {
-            Some(SqlErr::UniqueConstraintViolation(_)) => UserServiceError::UserAlreadyExists,
+        .map_err(map_unique_violation)?
+        .try_into_model()?;
+
+        // The account exists at this point, a failed mail can be retried with a resend.
+        if let Err(e) = self.send_verification(&user, &user.email).await {
+            tracing::error!("Failed to send verification mail to {}: {}", user.id, e);
+        }
+
+        Ok(user)
+    }
+
+    /// Marks the email address of the user the verification token was issued for as verified.
+    pub async fn verify_email(&self, token: &str) -> Result<User, UserServiceError> {
+        let (user_id, expires_at, signature) =
+            parse_verification_token(token).ok_or(UserServiceError::InvalidVerificationToken)?;
+
+        if expires_at < OffsetDateTime::now_utc().unix_timestamp() {
+            return Err(UserServiceError::InvalidVerificationToken);
+        }
+
+        let user = Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(UserServiceError::InvalidVerificationToken)?;
+
+        let signed_for = |email: &str| {
+            verify_hmac_sha256_hex(
+                &self.link_secret,
+                &verification_message(user.id, email, expires_at),
+                signature,
+            )
+        };
+
+        if let Some(pending_email) = user.pending_email.clone().filter(|e| signed_for(e)) {
+            let mut user: entity::user::ActiveModel = user.into();
+            user.email = Set(pending_email);
+            user.pending_email = Set(None);
+            user.email_verified_at = Set(Some(now()));
+
+            return user.update(&self.db).await.map_err(map_unique_violation);
+        }
+
+        if !signed_for(&user.email) {
+            return Err(UserServiceError::InvalidVerificationToken);
+        }
+
+        if user.email_verified_at.is_some() {
+            return Ok(user);
+        }
+
+        let mut user: entity::user::ActiveModel = user.into();
+        user.email_verified_at = Set(Some(now()));
+
+        Ok(user.update(&self.db).await?)
+    }
+
+    /// Sends a new verification link if the address is unverified or pending. Does not tell the
+    /// caller whether it did, so the endpoint cannot be used to probe for accounts.
+    pub async fn resend_verification(&self, email: String) -> Result<(), UserServiceError> {
+        let email = normalize_email(&email);
+
+        let Some(user) = Entity::find()
+            .filter(
+                Condition::any()
+                    .add(entity::user::Column::Email.eq(&email))
+                    .add(entity::user::Column::PendingEmail.eq(&email)),
+            )
+            .one(&self.db)
+            .await?
+        else {
+            return Ok(());
+        };
+
+        if user.pending_email.as_ref() == Some(&email)
+            || (user.email == email && user.email_verified_at.is_none())
+        {
+            self.send_verification(&user, &email).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Changes the name right away; a changed email address is kept as pending and a
+    /// verification link is sent to it.
+    pub async fn update_profile(
+        &self,
+        user_id: Uuid,
+        update: dto::user::UserUpdate,
+    ) -> Result<User, UserServiceError> {
+        let user = self.get_by_id(user_id).await?;
+
+        let pending_email = match update.email.as_deref().map(normalize_email) {
+            Some(email) if email == user.email => Some(None),
+            Some(email) => {
+                let taken = Entity::find()
+                    .filter(entity::user::Column::Email.eq(&email))
+                    .one(&self.db)
+                    .await?
+                    .is_some();
+
+                if taken {
+                    return Err(UserServiceError::UserAlreadyExists);
+                }
+
+                Some(Some(email))
+            }
+            None => None,
+        };
+
+        let email_changed = matches!(pending_email, Some(Some(_)));
+
+        let mut active: entity::user::ActiveModel = user.into();
+
+        if let Some(name) = update.name {
+            active.name = Set(name);
+        }
+
+        if let Some(pending_email) = pending_email {
+            active.pending_email = Set(pending_email);
+        }
+
+        let user = active.update(&self.db).await?;
+
+        if let (true, Some(pending_email)) = (email_changed, &user.pending_email) {
+            self.send_verification(&user, pending_email).await?;
+        }
+
+        Ok(user)
+    }
+
+    /// Sets a new password after checking the current one. The caller should revoke the user's
+    /// sessions.
+    pub async fn change_password(
+        &self,
+        user_id: Uuid,
+        request: dto::user::ChangePasswordRequest,
+    ) -> Result<User, UserServiceError> {
+        let user = self.get_by_id(user_id).await?;
+
+        verify_password(&user, &request.current_password)?;
+
+        self.check_password("newPassword", &request.new_password)
+            .await?;
+
+        let mut user: entity::user::ActiveModel = user.into();
+        user.password = Set(hash_password(&request.new_password)?);
+
+        Ok(user.update(&self.db).await?)
+    }
+
+    /// Deletes the account after checking the password. Reservations are kept for the
+    /// apothecaries' records but no longer point to the user; everything else tied to the
+    /// account is removed.
+    pub async fn delete_account(
+        &self,
+        user_id: Uuid,
+        auth_time: i64,
+        request: dto::user::DeleteAccountRequest,
+    ) -> Result<(), UserServiceError> {
+        let user = self.get_by_id(user_id).await?;
+
+        match &request.password {
+            Some(password) => verify_password(&user, password)?,
+            None => {
+                let recent = OffsetDateTime::now_utc()
+                    - Duration::minutes(self.settings.login.recent_login_minutes);
+
+                if auth_time < recent.unix_timestamp() {
+                    return Err(UserServiceError::ReauthenticationRequired);
+                }
+            }
+        }
+
+        let txn = self.db.begin().await?;
+
+        anonymize_reservations(&txn, user.id).await?;
+
+        entity::refresh_token::Entity::delete_many()
+            .filter(entity::refresh_token::Column::UserId.eq(user.id))
+            .exec(&txn)
+            .await?;
+
+        entity::password_reset_token::Entity::delete_many()
+            .filter(entity::password_reset_token::Column::UserId.eq(user.id))
+            .exec(&txn)
+            .await?;
+
+        entity::apothecary_user::Entity::delete_many()
+            .filter(entity::apothecary_user::Column::UserId.eq(user.id))
+            .exec(&txn)
+            .await?;
+
+        totp::delete_for_user(&txn, user.id).await?;
+
+        entity::staff_invitation::Entity::update_many()
+            .col_expr(
+                entity::staff_invitation::Column::InvitedBy,
+                Expr::value(Option::<Uuid>::None),
+            )
+            .filter(entity::staff_invitation::Column::InvitedBy.eq(user.id))
+            .exec(&txn)
+            .await?;
+
+        entity::api_key::Entity::update_many()
+            .col_expr(
+                entity::api_key::Column::CreatedBy,
+                Expr::value(Option::<Uuid>::None),
+            )
+            .filter(entity::api_key::Column::CreatedBy.eq(user.id))
+            .exec(&txn)
+            .await?;
+
+        entity::user_identity::Entity::delete_many()
+            .filter(entity::user_identity::Column::UserId.eq(user.id))
+            .exec(&txn)
+            .await?;
+
+        entity::login_throttle::Entity::delete_many()
+            .filter(
+                entity::login_throttle::Column::Scope
+                    .is_in([ThrottleScope::Account, ThrottleScope::PasswordResetAccount]),
+            )
+            .filter(entity::login_throttle::Column::Key.eq(&user.email))
+            .exec(&txn)
+            .await?;
+
+        // The events stay for the log, without the details and addresses that could identify
+        // the person.
+        entity::audit_event::Entity::update_many()
+            .col_expr(
+                entity::audit_event::Column::UserId,
+                Expr::value(Option::<Uuid>::None),
+            )
+            .col_expr(
+                entity::audit_event::Column::Ip,
+                Expr::value(Option::<String>::None),
+            )
+            .col_expr(entity::audit_event::Column::Detail, Expr::value(""))
+            .filter(entity::audit_event::Column::UserId.eq(user.id))
+            .exec(&txn)
+            .await?;
+
+        entity::audit_event::Entity::update_many()
+            .col_expr(
+                entity::audit_event::Column::ActorId,
+                Expr::value(Option::<Uuid>::None),
+            )
+            .col_expr(
+                entity::audit_event::Column::Ip,
+                Expr::value(Option::<String>::None),
+            )
+            .filter(entity::audit_event::Column::ActorId.eq(user.id))
+            .exec(&txn)
+            .await?;
+
+        user.delete(&txn).await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Mails a password reset link if the address belongs to an account. Requests are limited
+    /// per address and per IP address, whether or not the address is registered. Everything
+    /// that depends on the address happens in the background, so neither the response nor its
+    /// timing reveals whether an account exists.
+    pub async fn forgot_password(&self, email: String, ip: IpAddr) -> Result<(), UserServiceError> {
+        let settings = &self.settings.login;
+        let now = now();
+        let email = normalize_email(&email);
+        let window = Duration::minutes(settings.password_reset_window_minutes);
+
+        for (scope, key, limit) in [
+            (
+                ThrottleScope::PasswordResetIp,
+                ip.to_string(),
+                settings.password_reset_limit_per_ip,
+            ),
+            (
+                ThrottleScope::PasswordResetAccount,
+                email.clone(),
+                settings.password_reset_limit_per_account,
+            ),
+        ] {
+            if let Some(wait) =
+                throttle::count_request(&self.db, scope, &key, limit, window, now).await?
+            {
+                return Err(UserServiceError::TooManyAttempts(wait));
+            }
+        }
+
+        let db = self.db.clone();
+        let settings = self.settings.clone();
+        let mailer = self.mailer.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = send_password_reset(&db, &settings, mailer.as_ref(), &email).await {
+                tracing::error!("Failed to send password reset mail: {}", e);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Sets a new password using a reset token and returns the user, whose sessions the caller
+    /// should revoke.
+    pub async fn reset_password(
+        &self,
+        request: dto::user::ResetPasswordRequest,
+    ) -> Result<User, UserServiceError> {
+        let now = now();
+
+        let reset_token = entity::password_reset_token::Entity::find()
+            .filter(entity::password_reset_token::Column::TokenHash.eq(sha256_hex(&request.token)))
+            .one(&self.db)
+            .await?
+            .filter(|t| t.used_at.is_none() && t.expires_at > now)
+            .ok_or(UserServiceError::InvalidResetToken)?;
+
+        self.check_password("password", &request.password).await?;
+
+        let password_hash = hash_password(&request.password)?;
+
+        let txn = self.db.begin().await?;
+
+        let used = entity::password_reset_token::Entity::update_many()
+            .col_expr(
+                entity::password_reset_token::Column::UsedAt,
+                Expr::value(Some(now)),
+            )
+            .filter(entity::password_reset_token::Column::Id.eq(reset_token.id))
+            .filter(entity::password_reset_token::Column::UsedAt.is_null())
+            .exec(&txn)
+            .await?;
+
+        if used.rows_affected == 0 {
+            return Err(UserServiceError::InvalidResetToken);
+        }
+
+        let user = Entity::find_by_id(reset_token.user_id)
+            .one(&txn)
+            .await?
+            .ok_or(UserServiceError::InvalidResetToken)?;
+
+        // Receiving the mail proves the address belongs to the user.
+        let email_verified_at = user.email_verified_at.unwrap_or(now);
+
+        let mut user: entity::user::ActiveModel = user.into();
+        user.password = Set(password_hash);
+        user.email_verified_at = Set(Some(email_verified_at));
+        let user = user.update(&txn).await?;
+
+        txn.commit().await?;
+
+        Ok(user)
+    }
+
+    async fn record_login_failure(
+        &self,
+        user_id: Option<Uuid>,
+        account_key: &str,
+        ip_key: &str,
+        now: PrimitiveDateTime,
+    ) -> Result<(), UserServiceError> {
+        let settings = &self.settings.login;
+
+        throttle::record_login_failure(
+            &self.db,
+            settings,
+            user_id,
+            ThrottleScope::Account,
+            account_key,
+            Some(ip_key.to_owned()),
+            now,
+        )
+        .await?;
+
+        throttle::record_login_failure(
+            &self.db,
+            settings,
+            user_id,
+            ThrottleScope::Ip,
+            ip_key,
+            Some(ip_key.to_owned()),
+            now,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Proves that the password was accepted. The signature covers the password hash, so a
+    /// changed password invalidates open challenges.
+    fn login_challenge(&self, user: &User) -> String {
+        let expires_at = (OffsetDateTime::now_utc()
+            + Duration::seconds(self.settings.totp.challenge_lifetime))
+        .unix_timestamp();
+
+        let signature = hmac_sha256_hex(
+            &self.challenge_secret,
+            &login_challenge_message(user, expires_at),
+        );
+
+        format!("{}.{}.{}", user.id, expires_at, signature)
+    }
+
+    async fn verify_login_challenge(&self, challenge: &str) -> Result<User, UserServiceError> {
+        let (user_id, expires_at, signature) =
+            parse_verification_token(challenge).ok_or(UserServiceError::InvalidLoginChallenge)?;
+
+        if expires_at < OffsetDateTime::now_utc().unix_timestamp() {
+            return Err(UserServiceError::InvalidLoginChallenge);
+        }
+
+        let user = Entity::find_by_id(user_id)
+            .one(&self.db)
+            .await?
+            .ok_or(UserServiceError::InvalidLoginChallenge)?;
+
+        if !verify_hmac_sha256_hex(
+            &self.challenge_secret,
+            &login_challenge_message(&user, expires_at),
+            signature,
+        ) {
+            return Err(UserServiceError::InvalidLoginChallenge);
+        }
+
+        Ok(user)
+    }
+
+    async fn check_password(
+        &self,
+        field: &'static str,
+        password: &str,
+    ) -> Result<(), UserServiceError> {
+        let violations = password::validate(&self.settings.password, password).await?;
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(UserServiceError::InvalidPassword(field, violations))
+        }
+    }
+
+    async fn send_verification(&self, user: &User, email: &str) -> Result<(), UserServiceError> {
+        let mail = &self.settings.mail;
+        let expires_at = (OffsetDateTime::now_utc()
+            + Duration::seconds(mail.verification_link_lifetime))
+        .unix_timestamp();
+
+        let signature = hmac_sha256_hex(
+            &self.link_secret,
+            &verification_message(user.id, email, expires_at),
+        );
+        let link = format!(
+            "{}?token={}.{}.{}",
+            mail.verification_url, user.id, expires_at, signature
+        );
+
+        self.mailer
+            .send(MailMessage {
+                to: email.to_owned(),
+                subject: "Verify your email address".to_owned(),
+                body: format!(
+                    "Hello {},\n\nplease confirm your email address by opening the link below:\n\n{}\n",
+                    user.name, link
+                ),
+            })
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Creates a reset token for the user, invalidating earlier ones since only the most recent
+/// link is valid.
+pub(crate) async fn issue_password_reset<C: TransactionTrait>(
+    db: &C,
+    settings: &Settings,
+    user_id: Uuid,
+) -> Result<String, DbErr> {
+    let now = now();
+    let token = random_token();
+
+    let txn = db.begin().await?;
+
+    entity::password_reset_token::Entity::update_many()
+        .col_expr(
+            entity::password_reset_token::Column::UsedAt,
+            Expr::value(Some(now)),
+        )
+        .filter(entity::password_reset_token::Column::UserId.eq(user_id))
+        .filter(entity::password_reset_token::Column::UsedAt.is_null())
+        .exec(&txn)
+        .await?;
+
+    entity::password_reset_token::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        user_id: Set(user_id),
+        token_hash: Set(sha256_hex(&token)),
+        created_at: Set(now),
+        expires_at: Set(now + Duration::seconds(settings.mail.password_reset_link_lifetime)),
+        used_at: Set(None),
+    }
+    .insert(&txn)
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(token)
+}
+
+async fn send_password_reset(
+    db: &DatabaseConnection,
+    settings: &Settings,
+    mailer: &dyn Mailer,
+    email: &str,
+) -> anyhow::Result<()> {
+    let Some(user) = Entity::find()
+        .filter(entity::user::Column::Email.eq(email))
+        .one(db)
+        .await?
+    else {
+        return Ok(());
+    };
+
+    let token = issue_password_reset(db, settings, user.id).await?;
+
+    mailer
+        .send(password_reset_mail(settings, &user, &token))
+        .await
+}
+
+pub(crate) fn password_reset_mail(settings: &Settings, user: &User, token: &str) -> MailMessage {
+    MailMessage {
+        to: user.email.clone(),
+        subject: "Reset your password".to_owned(),
+        body: format!(
+            "Hello {},\n\nyou can choose a new password by opening the link below:\n\n{}?token={}\n\nIf you did not ask for this, you can ignore this mail.\n",
+            user.name, settings.mail.password_reset_url, token
+        ),
+    }
+}
+
+pub(crate) fn verify_password(user: &User, password: &str) -> Result<(), UserServiceError> {
+    verify_hash(&user.password, password)
+}
+
+fn verify_hash(hash: &str, password: &str) -> Result<(), UserServiceError> {
+    let parsed_hash = PasswordHash::new(hash).map_err(anyhow::Error::from)?;
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .map_err(|e| match e {
+            argon2::password_hash::Error::Password => UserServiceError::InvalidCredentials,
             _ => UserServiceError::Anyhow(anyhow::Error::from(e)),
-        })?
-        .try_into_model()?)
+        })
+}
+
+/// Canonical form of an email address, used for storing and looking up addresses. The local
+/// part is technically case-sensitive, but no provider treats it that way and users do not
+/// expect it.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// A hash with the same parameters as real ones, to verify against for unknown addresses.
+fn dummy_hash() -> &'static str {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    DUMMY_HASH.get_or_init(|| {
+        Argon2::default()
+            .hash_password(b"dummy password", &SaltString::generate(&mut OsRng))
+            .expect("hashing a constant password cannot fail")
+            .to_string()
+    })
+}
+
+fn map_unique_violation(e: DbErr) -> UserServiceError {
+    match e.sql_err() {
+        Some(SqlErr::UniqueConstraintViolation(_)) => UserServiceError::UserAlreadyExists,
+        _ => UserServiceError::Anyhow(anyhow::Error::from(e)),
+    }
+}
+
+pub(crate) fn hash_password(password: &str) -> Result<String, UserServiceError> {
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+        .map_err(anyhow::Error::from)?
+        .to_string())
+}
+
+pub(crate) fn now() -> PrimitiveDateTime {
+    let now = OffsetDateTime::now_utc();
+    PrimitiveDateTime::new(now.date(), now.time())
+}
+
+/// The signature covers the address, so links stop working once the address changes.
+fn verification_message(user_id: Uuid, email: &str, expires_at: i64) -> String {
+    format!("verify-email:{}:{}:{}", user_id, email, expires_at)
+}
+
+fn login_challenge_message(user: &User, expires_at: i64) -> String {
+    format!(
+        "login-challenge:{}:{}:{}",
+        user.id, user.password, expires_at
+    )
+}
+
+fn parse_verification_token(token: &str) -> Option<(Uuid, i64, &str)> {
+    let mut parts = token.splitn(3, '.');
+
+    Some((
+        parts.next()?.parse().ok()?,
+        parts.next()?.parse().ok()?,
+        parts.next()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        mail::{InMemoryMailer, Mailer},
+        test_support,
+    };
+
+    use super::*;
+
+    async fn service() -> (UserService, Arc<InMemoryMailer>) {
+        let mailer = Arc::new(InMemoryMailer::default());
+        let service = UserService::new(
+            test_support::db().await,
+            Arc::new(test_support::settings()),
+            mailer.clone() as Arc<dyn Mailer>,
+        );
+
+        (service, mailer)
+    }
+
+    fn registration(email: &str) -> dto::user::UserRegistration {
+        dto::user::UserRegistration {
+            name: "Jane".to_owned(),
+            email: email.to_owned(),
+            password: "Correct-Horse-Battery-9".to_owned(),
+        }
+    }
+
+    fn token_in(body: &str) -> &str {
+        let start = body.find("token=").expect("mail contains a token") + "token=".len();
+
+        body[start..].split_whitespace().next().unwrap()
+    }
+
+    #[tokio::test]
+    async fn register_mails_a_link_that_verifies_the_address() {
+        let (service, mailer) = service().await;
+
+        let user = service
+            .register(registration("Jane@Example.com"))
+            .await
+            .ok()
+            .unwrap();
+        assert!(user.email_verified_at.is_none());
+
+        let messages = mailer.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].to, "jane@example.com");
+        assert!(messages[0]
+            .body
+            .contains("http://localhost/verify-email?token="));
+
+        let verified = service
+            .verify_email(token_in(&messages[0].body))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(verified.id, user.id);
+        assert!(verified.email_verified_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn verify_email_rejects_tampered_tokens() {
+        let (service, mailer) = service().await;
+
+        service
+            .register(registration("jane@example.com"))
+            .await
+            .ok()
+            .unwrap();
+
+        let token = token_in(&mailer.messages()[0].body).to_owned();
+        let last = if token.ends_with('0') { '1' } else { '0' };
+        let tampered = format!("{}{}", &token[..token.len() - 1], last);
+
+        assert!(matches!(
+            service.verify_email(&tampered).await,
+            Err(UserServiceError::InvalidVerificationToken)
+        ));
+        assert!(matches!(
+            service.verify_email("not-a-token").await,
+            Err(UserServiceError::InvalidVerificationToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn forgot_password_is_limited_per_address_whether_registered_or_not() {
+        let (service, mailer) = service().await;
+        let ip: IpAddr = [127, 0, 0, 1].into();
+
+        service
+            .register(registration("jane@example.com"))
+            .await
+            .ok()
+            .unwrap();
+
+        for email in ["jane@example.com", "nobody@example.com"] {
+            for _ in 0..3 {
+                assert!(service.forgot_password(email.to_owned(), ip).await.is_ok());
+            }
+
+            assert!(matches!(
+                service.forgot_password(email.to_owned(), ip).await,
+                Err(UserServiceError::TooManyAttempts(_))
+            ));
+        }
+
+        // Verification mail plus three reset mails, sent in the background.
+        for _ in 0..50 {
+            if mailer.messages().len() == 4 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+
+        let messages = mailer.messages();
+        assert_eq!(messages.len(), 4);
+        assert!(messages.iter().all(|m| m.to == "jane@example.com"));
+    }
+
+    #[tokio::test]
+    async fn forgot_password_is_limited_per_ip_across_addresses() {
+        let (service, _) = service().await;
+        let ip: IpAddr = [127, 0, 0, 1].into();
+
+        for i in 0..20 {
+            assert!(service
+                .forgot_password(format!("user{i}@example.com"), ip)
+                .await
+                .is_ok());
+        }
+
+        assert!(matches!(
+            service
+                .forgot_password("another@example.com".to_owned(), ip)
+                .await,
+            Err(UserServiceError::TooManyAttempts(_))
+        ));
+        assert!(service
+            .forgot_password("another@example.com".to_owned(), [10, 0, 0, 1].into())
+            .await
+            .is_ok());
+    }
+    #[tokio::test]
+    async fn delete_account_needs_the_password_or_a_recent_login() {
+        let (service, _) = service().await;
+        let user_id = test_support::customer(&service.db, "jane@example.com").await;
+        let long_ago = (OffsetDateTime::now_utc() - Duration::hours(1)).unix_timestamp();
+
+        let request = |password: Option<&str>| dto::user::DeleteAccountRequest {
+            password: password.map(str::to_owned),
+        };
+
+        assert!(matches!(
+            service
+                .delete_account(user_id, long_ago, request(Some("wrong")))
+                .await,
+            Err(UserServiceError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            service
+                .delete_account(user_id, long_ago, request(None))
+                .await,
+            Err(UserServiceError::ReauthenticationRequired)
+        ));
+
+        let just_now = OffsetDateTime::now_utc().unix_timestamp();
+        assert!(service
+            .delete_account(user_id, just_now, request(None))
+            .await
+            .is_ok());
+        assert!(Entity::find_by_id(user_id)
+            .one(&service.db)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_account_leaves_nothing_pointing_to_the_person() {
+        let (service, _) = service().await;
+        let db = service.db.clone();
+        let settings = Arc::new(test_support::settings());
+        let user_id = test_support::customer(&db, "jane@example.com").await;
+        let stock = test_support::seeded_stock(&db).await;
+
+        crate::reservation::ReservationService::new(db.clone(), settings.clone())
+            .reserve(
+                user_id,
+                Some("retry-key".to_owned()),
+                test_support::reservation_request(&stock, 1),
+            )
+            .await
+            .ok()
+            .unwrap();
+        let reservation = test_support::reservation_of(&db, user_id).await;
+
+        entity::no_show::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            user_id: Set(user_id),
+            reservation_id: Set(reservation.id),
+            apothecary_id: Set(reservation.apothecary_id),
+            missed_at: Set(now()),
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+
+        let user = service.get_by_id(user_id).await.ok().unwrap();
+        crate::jwt::JwtService::new(db.clone(), settings.clone())
+            .ok()
+            .unwrap()
+            .create_session(&user)
+            .await
+            .ok()
+            .unwrap();
+
+        for scope in [ThrottleScope::Account, ThrottleScope::PasswordResetAccount] {
+            throttle::count_request(&db, scope, &user.email, 10, Duration::hours(1), now())
+                .await
+                .unwrap();
+        }
+
+        audit::record(
+            &db,
+            Some(user_id),
+            AuditEventKind::TotpEnabled,
+            Some("127.0.0.1".to_owned()),
+            "Enabled by jane@example.com".to_owned(),
+        )
+        .await
+        .unwrap();
+
+        service
+            .delete_account(
+                user_id,
+                0,
+                dto::user::DeleteAccountRequest {
+                    password: Some("Correct-Horse-Battery-9".to_owned()),
+                },
+            )
+            .await
+            .ok()
+            .unwrap();
+
+        let reservation = entity::reservation::Entity::find_by_id(reservation.id)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reservation.user_id, None);
+        assert_eq!(
+            reservation.status,
+            entity::reservation::ReservationStatus::Cancelled
+        );
+
+        assert_eq!(
+            entity::idempotency_key::Entity::find()
+                .count(&db)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(entity::no_show::Entity::find().count(&db).await.unwrap(), 0);
+        assert_eq!(
+            entity::refresh_token::Entity::find()
+                .count(&db)
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            entity::login_throttle::Entity::find()
+                .filter(entity::login_throttle::Column::Key.eq(&user.email))
+                .count(&db)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let events = entity::audit_event::Entity::find().all(&db).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].user_id, None);
+        assert_eq!(events[0].ip, None);
+        assert!(!events[0].detail.contains("jane"));
+    }
+
+    fn login(email: &str) -> dto::user::UserLogin {
+        dto::user::UserLogin {
+            email: email.to_owned(),
+            password: "Correct-Horse-Battery-9".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn login_ignores_case_and_surrounding_whitespace() {
+        let (service, _) = service().await;
+        let user_id = test_support::customer(&service.db, "jane@example.com").await;
+        let ip: IpAddr = [127, 0, 0, 1].into();
+
+        let Ok(LoginOutcome::Authenticated(user)) =
+            service.login(login("  Jane@Example.COM "), ip).await
+        else {
+            panic!("the address matches regardless of case");
+        };
+        assert_eq!(user.id, user_id);
+    }
+
+    #[tokio::test]
+    async fn login_needs_the_whole_address() {
+        let (service, _) = service().await;
+        test_support::customer(&service.db, "ba@b.com").await;
+        let ip: IpAddr = [127, 0, 0, 1].into();
+
+        for email in ["a@b.c", "a@b.com", "ba@b.c", "%@b.com"] {
+            assert!(
+                matches!(
+                    service.login(login(email), ip).await,
+                    Err(UserServiceError::InvalidCredentials)
+                ),
+                "{}",
+                email
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn register_rejects_addresses_that_only_differ_in_case() {
+        let (service, _) = service().await;
+
+        service
+            .register(registration("jane@example.com"))
+            .await
+            .ok()
+            .unwrap();
+
+        assert!(matches!(
+            service.register(registration(" JANE@example.com ")).await,
+            Err(UserServiceError::UserAlreadyExists)
+        ));
     }
 }
@@ -6,11 +6,26 @@ pub struct Settings {
     pub database: Database,
     pub endpoint: Endpoint,
     pub jwt: Jwt,
+    #[serde(default)]
+    pub mail: Mail,
+    #[serde(default)]
+    pub login: Login,
+    #[serde(default)]
+    pub oidc: Oidc,
+    #[serde(default)]
+    pub password: PasswordPolicy,
+    #[serde(default)]
+    pub reservation: Reservation,
+    #[serde(default)]
+    pub totp: Totp,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Database {
     pub url: String,
+    /// Drops all tables and runs the migrations from scratch on every start, for development.
+    #[serde(default)]
+    pub refresh: bool,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -21,7 +36,316 @@ pub struct Endpoint {
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Jwt {
-    pub secret: String,
+    /// Shared secret for HS256 tokens. Used for signing only when no `active_key` is set, and
+    /// for verifying tokens without a `kid` as long as it is configured.
+    #[serde(default)]
+    pub secret: Option<String>,
+    /// `kid` of the entry in `keys` that signs new tokens.
+    #[serde(default)]
+    pub active_key: Option<String>,
+    /// Asymmetric keys. All of them verify tokens and are published in the JWKS, so a retired
+    /// key stays listed until the tokens signed with it have expired.
+    #[serde(default)]
+    pub keys: Vec<JwtKey>,
+    /// Lifetime of access tokens in seconds.
+    #[serde(default = "default_access_token_lifetime")]
+    pub access_token_lifetime: i64,
+    /// Lifetime of refresh tokens in seconds.
+    #[serde(default = "default_refresh_token_lifetime")]
+    pub refresh_token_lifetime: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct JwtKey {
+    pub kid: String,
+    pub algorithm: JwtAlgorithm,
+    /// Path to the PEM encoded public key (SPKI).
+    pub public_key_file: String,
+    /// Path to the PEM encoded private key (PKCS#8), only needed for the active key.
+    #[serde(default)]
+    pub private_key_file: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum JwtAlgorithm {
+    RS256,
+    EdDSA,
+}
+
+const fn default_access_token_lifetime() -> i64 {
+    15 * 60
+}
+
+const fn default_refresh_token_lifetime() -> i64 {
+    30 * 24 * 60 * 60
+}
+
+/// Without this section, mails are only logged and their links point to `localhost`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Mail {
+    /// Sender address, e.g. `PharmaTracker <noreply@example.com>`.
+    #[serde(default = "default_mail_from")]
+    pub from: String,
+    /// Secret used to sign the links sent by mail. A random one is generated on every start
+    /// when unset, so links mailed before a restart stop working.
+    #[serde(default)]
+    pub link_secret: Option<String>,
+    /// Page the verification link points to, the token is appended as `?token=`.
+    #[serde(default = "default_verification_url")]
+    pub verification_url: String,
+    /// Lifetime of verification links in seconds.
+    #[serde(default = "default_verification_link_lifetime")]
+    pub verification_link_lifetime: i64,
+    /// Page the password reset link points to, the token is appended as `?token=`.
+    #[serde(default = "default_password_reset_url")]
+    pub password_reset_url: String,
+    /// Lifetime of password reset links in seconds.
+    #[serde(default = "default_password_reset_link_lifetime")]
+    pub password_reset_link_lifetime: i64,
+    /// Page the staff invitation link points to, the token is appended as `?token=`.
+    #[serde(default = "default_invitation_url")]
+    pub invitation_url: String,
+    /// Lifetime of staff invitations in seconds.
+    #[serde(default = "default_invitation_link_lifetime")]
+    pub invitation_link_lifetime: i64,
+    /// Mails are only kept in memory and logged when no SMTP server is configured.
+    #[serde(default)]
+    pub smtp: Option<Smtp>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Smtp {
+    pub host: String,
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+}
+
+impl Default for Mail {
+    fn default() -> Self {
+        Self {
+            from: default_mail_from(),
+            link_secret: None,
+            verification_url: default_verification_url(),
+            verification_link_lifetime: default_verification_link_lifetime(),
+            password_reset_url: default_password_reset_url(),
+            password_reset_link_lifetime: default_password_reset_link_lifetime(),
+            invitation_url: default_invitation_url(),
+            invitation_link_lifetime: default_invitation_link_lifetime(),
+            smtp: None,
+        }
+    }
+}
+
+fn default_mail_from() -> String {
+    "PharmaTracker <noreply@localhost>".to_owned()
+}
+
+fn default_verification_url() -> String {
+    "http://localhost/verify-email".to_owned()
+}
+
+fn default_password_reset_url() -> String {
+    "http://localhost/reset-password".to_owned()
+}
+
+fn default_invitation_url() -> String {
+    "http://localhost/invitation".to_owned()
+}
+
+const fn default_verification_link_lifetime() -> i64 {
+    24 * 60 * 60
+}
+
+const fn default_password_reset_link_lifetime() -> i64 {
+    60 * 60
+}
+
+const fn default_invitation_link_lifetime() -> i64 {
+    7 * 24 * 60 * 60
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Login {
+    /// Failed attempts on an account before each further attempt has to wait.
+    pub free_attempts: i32,
+    /// Failed attempts from one IP address, across all accounts, before each further attempt
+    /// from it has to wait.
+    pub ip_free_attempts: i32,
+    /// Wait after the first failure beyond `free_attempts`, doubled with every further failure.
+    pub backoff_base_seconds: i64,
+    pub backoff_max_seconds: i64,
+    /// Failed attempts on an account that lock it for `lockout_minutes`.
+    pub account_lockout_threshold: i32,
+    /// Failed attempts from one IP address, across all accounts, that lock it out.
+    pub ip_lockout_threshold: i32,
+    pub lockout_minutes: i64,
+    /// Failures are forgotten once the last one is older than this.
+    pub failure_window_minutes: i64,
+    /// Password reset requests for one address, and from one IP address, allowed within
+    /// `password_reset_window_minutes`.
+    pub password_reset_limit_per_account: i32,
+    pub password_reset_limit_per_ip: i32,
+    pub password_reset_window_minutes: i64,
+    /// Minutes after logging in during which deleting the account needs no password. Accounts
+    /// created through a provider have none, their users log in through it again instead.
+    pub recent_login_minutes: i64,
+}
+
+impl Default for Login {
+    fn default() -> Self {
+        Self {
+            free_attempts: 3,
+            ip_free_attempts: 10,
+            backoff_base_seconds: 1,
+            backoff_max_seconds: 5 * 60,
+            account_lockout_threshold: 10,
+            ip_lockout_threshold: 50,
+            lockout_minutes: 15,
+            failure_window_minutes: 60,
+            password_reset_limit_per_account: 3,
+            password_reset_limit_per_ip: 20,
+            password_reset_window_minutes: 60,
+            recent_login_minutes: 5,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Oidc {
+    /// Seconds a login started at a provider stays valid until the callback.
+    pub authorization_lifetime: i64,
+    pub providers: Vec<OidcProvider>,
+}
+
+impl Default for Oidc {
+    fn default() -> Self {
+        Self {
+            authorization_lifetime: 10 * 60,
+            providers: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct OidcProvider {
+    /// Name used in the API paths, e.g. `google`.
+    pub name: String,
+    /// The provider configuration is discovered from `{issuer}/.well-known/openid-configuration`.
+    pub issuer: String,
+    pub client_id: String,
+    /// Sent as `client_secret_post`; public clients rely on PKCE alone.
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    /// Page of the app the provider redirects back to. It passes `code` and `state` on to the
+    /// callback endpoint.
+    pub redirect_url: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec![
+        "openid".to_owned(),
+        "email".to_owned(),
+        "profile".to_owned(),
+    ]
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    /// Minimum estimated entropy in bits, based on length and the character classes used.
+    pub min_entropy_bits: f64,
+    /// Directory of breached password hashes, one file per five character SHA-1 prefix
+    /// (e.g. `21BD1`) with `SUFFIX:COUNT` lines, as served by the Pwned Passwords range API.
+    /// The check is skipped when unset.
+    pub breached_passwords_dir: Option<String>,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 10,
+            max_length: 128,
+            min_entropy_bits: 45.0,
+            breached_passwords_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Reservation {
+    /// Maximum number of active or pending reservations a single user may hold.
+    pub max_active_per_user: u64,
+    /// Maximum number of packages of one medication a user may hold across all apothecaries.
+    pub max_packages_per_medication: u64,
+    /// Minutes a user has to wait before reserving again after missing a pickup.
+    pub no_show_cooldown_minutes: i64,
+    /// Number of missed pickups within `no_show_ban_window_days` that suspends reservations.
+    pub no_show_ban_threshold: u64,
+    pub no_show_ban_window_days: i64,
+    /// Days reservations stay suspended after the missed pickup that reached the threshold.
+    pub no_show_ban_days: i64,
+    /// Seconds between sweeps that mark overdue reservations as expired.
+    pub expiry_sweep_interval_seconds: u64,
+    /// Hours an idempotency key is remembered. Retries after that create a new reservation.
+    pub idempotency_key_ttl_hours: i64,
+}
+
+impl Default for Reservation {
+    fn default() -> Self {
+        Self {
+            max_active_per_user: 5,
+            max_packages_per_medication: 3,
+            no_show_cooldown_minutes: 24 * 60,
+            no_show_ban_threshold: 3,
+            no_show_ban_window_days: 90,
+            no_show_ban_days: 30,
+            expiry_sweep_interval_seconds: 60,
+            idempotency_key_ttl_hours: 24,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Totp {
+    /// Issuer shown in authenticator apps.
+    pub issuer: String,
+    /// Makes two-factor authentication mandatory for apothecary and admin accounts. Staff
+    /// without it enroll during their next login.
+    pub required_for_staff: bool,
+    /// Seconds a user has to enter the code after the password was accepted.
+    pub challenge_lifetime: i64,
+    /// Secret used to sign the challenges of two-step logins. A random one is generated on
+    /// every start when unset, which only works with a single instance and ends open logins.
+    pub challenge_secret: Option<String>,
+    /// Time steps before and after the current one whose codes are accepted, for clock drift.
+    pub allowed_drift_steps: i64,
+    /// Number of recovery codes handed out on enrollment.
+    pub recovery_codes: usize,
+}
+
+impl Default for Totp {
+    fn default() -> Self {
+        Self {
+            issuer: "PharmaTracker".to_owned(),
+            required_for_staff: false,
+            challenge_lifetime: 5 * 60,
+            challenge_secret: None,
+            allowed_drift_steps: 1,
+            recovery_codes: 10,
+        }
+    }
 }
 
 impl Settings {
@@ -32,3 +356,27 @@ impl Settings {
             .try_deserialize()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn configs_without_mail_section_still_load() {
+        let settings: Settings = serde_json::from_value(json!({
+            "database": { "url": "sqlite::memory:" },
+            "endpoint": { "host": "127.0.0.1", "port": 8080 },
+            "jwt": { "secret": "secret" },
+        }))
+        .unwrap();
+
+        assert!(settings.mail.link_secret.is_none());
+        assert!(settings.mail.smtp.is_none());
+        assert_eq!(
+            settings.mail.verification_url,
+            "http://localhost/verify-email"
+        );
+    }
+}